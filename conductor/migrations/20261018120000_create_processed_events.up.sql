@@ -0,0 +1,10 @@
+-- Up migration
+CREATE TABLE processed_events (
+    namespace VARCHAR(255) NOT NULL,
+    msg_id BIGINT NOT NULL,
+    event_type VARCHAR(255) NOT NULL,
+    outcome VARCHAR(255) NOT NULL,
+    read_ct INTEGER NOT NULL,
+    processed_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (namespace)
+);
@@ -167,6 +167,46 @@ impl AWSConfigState {
         }
     }
 
+    // List the names of all stacks that have not been successfully deleted
+    pub async fn list_cloudformation_stacks(&self) -> Result<Vec<String>, ConductorError> {
+        let mut stack_names = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let list_stacks_result = self
+                .cf_client
+                .list_stacks()
+                .set_next_token(next_token.take())
+                .stack_status_filter(StackStatus::CreateComplete)
+                .stack_status_filter(StackStatus::CreateFailed)
+                .stack_status_filter(StackStatus::UpdateComplete)
+                .stack_status_filter(StackStatus::UpdateRollbackComplete)
+                .stack_status_filter(StackStatus::RollbackComplete)
+                .stack_status_filter(StackStatus::RollbackFailed)
+                .stack_status_filter(StackStatus::DeleteFailed)
+                .send()
+                .await;
+
+            match list_stacks_result {
+                Ok(response) => {
+                    for summary in response.stack_summaries.unwrap_or_default() {
+                        if let Some(stack_name) = summary.stack_name {
+                            stack_names.push(stack_name);
+                        }
+                    }
+                    match response.next_token {
+                        Some(token) => next_token = Some(token),
+                        None => break,
+                    }
+                }
+                Err(err) => {
+                    error!("Error listing stacks: {:?}", err);
+                    return Err(ConductorError::AwsError(Box::new(err.into())));
+                }
+            }
+        }
+        Ok(stack_names)
+    }
+
     // Function to lookup outputs from a specific stack
     pub async fn lookup_cloudformation_stack(
         &self,
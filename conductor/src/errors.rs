@@ -54,6 +54,10 @@ pub enum ConductorError {
     #[error("Google Cloud Storage error: {0}")]
     GcsError(#[from] GcsError),
 
+    /// Invalid configuration
+    #[error("Configuration error: {0}")]
+    ConfigurationError(String),
+
     /// Dataplane error
     #[error("Dataplane not found error: {0}")]
     DataplaneError(String),
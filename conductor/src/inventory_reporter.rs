@@ -0,0 +1,552 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use conductor::monitoring::CustomMetrics;
+use conductor::types::{InventoryInstance, InventorySnapshot, Orphan, OrphanAction, OrphanKind};
+use conductor::{
+    delete_cloudformation, delete_coredb_and_namespace, get_org_inst_id,
+    list_cloudformation_namespaces,
+};
+use controller::apis::coredb_types::CoreDB;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::ListParams;
+use kube::{Api, Client, ResourceExt};
+use log::{error, info, warn};
+use opentelemetry::KeyValue;
+use pgmq::PGMQueueExt;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::{env, time::Duration};
+use tokio::time::interval;
+
+use crate::from_env_default;
+
+const INSTANCE_ID_LABEL: &str = "tembo.io/instance_id";
+const ORGANIZATION_ID_LABEL: &str = "tembo.io/organization_id";
+
+const ORPHAN_KINDS: [OrphanKind; 4] = [
+    OrphanKind::DeletedInstance,
+    OrphanKind::NamespaceWithoutCoreDB,
+    OrphanKind::UnmanagedCoreDB,
+    OrphanKind::CloudFormationStack,
+];
+const ORPHAN_ACTIONS: [OrphanAction; 3] = [
+    OrphanAction::Flagged,
+    OrphanAction::Deleting,
+    OrphanAction::DeleteFailed,
+];
+
+/// What to do with orphaned resources once they are past the grace period
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrphanPolicy {
+    /// Only log, count and report orphans to control plane
+    Report,
+    /// Report orphans, then delete them
+    Delete,
+}
+
+impl FromStr for OrphanPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "report" => Ok(OrphanPolicy::Report),
+            "delete" => Ok(OrphanPolicy::Delete),
+            other => bail!("Unknown orphan policy `{other}`, expected `report` or `delete`"),
+        }
+    }
+}
+
+pub async fn run_inventory_reporter(metrics: CustomMetrics) -> Result<()> {
+    let pg_conn_url = env::var("POSTGRES_QUEUE_CONNECTION")
+        .with_context(|| "POSTGRES_QUEUE_CONNECTION must be set")?;
+    let inventory_events_queue =
+        env::var("INVENTORY_EVENTS_QUEUE").with_context(|| "INVENTORY_EVENTS_QUEUE must be set")?;
+    let data_plane_id = env::var("DATA_PLANE_ID").with_context(|| "DATA_PLANE_ID must be set")?;
+    let policy: OrphanPolicy = from_env_default("INVENTORY_ORPHAN_POLICY", "report").parse()?;
+    let interval_seconds: u64 = from_env_default("INVENTORY_INTERVAL_SECONDS", "600")
+        .parse()
+        .with_context(|| "error parsing INVENTORY_INTERVAL_SECONDS")?;
+    let grace_period_seconds: i64 =
+        from_env_default("INVENTORY_ORPHAN_GRACE_PERIOD_SECONDS", "86400")
+            .parse()
+            .with_context(|| "error parsing INVENTORY_ORPHAN_GRACE_PERIOD_SECONDS")?;
+    let is_cloud_formation: bool = from_env_default("IS_CLOUD_FORMATION", "true")
+        .parse()
+        .with_context(|| "error parsing IS_CLOUD_FORMATION")?;
+    let aws_region = from_env_default("AWS_REGION", "us-east-1");
+    let grace_period = ChronoDuration::seconds(grace_period_seconds);
+
+    info!(
+        "inventory_reporter: every {}s, orphan policy {:?}, grace period {}s",
+        interval_seconds, policy, grace_period_seconds
+    );
+
+    let queue = PGMQueueExt::new(pg_conn_url, 1).await?;
+    queue.init().await?;
+    queue.create_partitioned(&inventory_events_queue).await?;
+
+    let client = Client::try_default().await?;
+
+    let mut sync_interval = interval(Duration::from_secs(interval_seconds));
+    // When each labelled namespace was first seen without a CoreDB, kept across passes
+    let mut without_coredb_since: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+    loop {
+        sync_interval.tick().await;
+
+        let instances = match collect_instances(&client).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                error!(
+                    "Failed to collect inventory from Kubernetes. Skipping! {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let deleted_instances = match fetch_deleted_instances(&queue).await {
+            Ok(deleted_instances) => deleted_instances,
+            Err(e) => {
+                error!("Failed to read deleted_instances. Skipping! {}", e);
+                continue;
+            }
+        };
+
+        let stack_namespaces = if is_cloud_formation {
+            match list_cloudformation_namespaces(aws_region.clone()).await {
+                Ok(stack_namespaces) => stack_namespaces,
+                Err(e) => {
+                    // Still report the Kubernetes side of the inventory
+                    warn!("Failed to list cloudformation stacks: {}", e);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+
+        let now = Utc::now();
+        track_without_coredb(&mut without_coredb_since, &instances, now);
+        let candidates = find_orphans(
+            &instances,
+            &deleted_instances,
+            &without_coredb_since,
+            &stack_namespaces,
+            now,
+            grace_period,
+        );
+
+        let mut orphans = Vec::with_capacity(candidates.len());
+        for OrphanCandidate {
+            mut orphan,
+            collectable,
+        } in candidates
+        {
+            if policy == OrphanPolicy::Delete && collectable {
+                orphan.action = collect_orphan(&client, &aws_region, &orphan).await;
+            }
+            warn!(
+                "inventory_reporter: orphan {:?} in {}, action: {:?}",
+                orphan.kind, orphan.namespace, orphan.action
+            );
+            orphans.push(orphan);
+        }
+        record_orphans(&metrics, &orphans);
+
+        let snapshot = InventorySnapshot {
+            data_plane_id: data_plane_id.clone(),
+            taken_at: now,
+            instances,
+            orphans,
+        };
+
+        match queue.send(&inventory_events_queue, &snapshot).await {
+            Ok(msg_id) => info!(
+                "inventory_reporter: sent snapshot of {} instances with {} orphans, message_id: {}",
+                snapshot.instances.len(),
+                snapshot.orphans.len(),
+                msg_id
+            ),
+            Err(e) => error!("Failed to send inventory snapshot: {}", e),
+        }
+    }
+}
+
+// Build one inventory entry per namespace, from labelled namespaces and CoreDBs
+async fn collect_instances(client: &Client) -> Result<Vec<InventoryInstance>> {
+    let ns_api: Api<Namespace> = Api::all(client.clone());
+    let coredb_api: Api<CoreDB> = Api::all(client.clone());
+
+    let namespaces = ns_api
+        .list(&ListParams::default().labels(INSTANCE_ID_LABEL))
+        .await?;
+    let coredbs = coredb_api.list(&ListParams::default()).await?;
+
+    let mut instances: BTreeMap<String, InventoryInstance> = BTreeMap::new();
+    for ns in namespaces.items {
+        let name = ns.name_any();
+        instances.insert(
+            name.clone(),
+            InventoryInstance {
+                namespace: name,
+                org_id: ns.labels().get(ORGANIZATION_ID_LABEL).cloned(),
+                inst_id: ns.labels().get(INSTANCE_ID_LABEL).cloned(),
+                labelled_namespace: true,
+                namespace_phase: ns.status.as_ref().and_then(|status| status.phase.clone()),
+                has_coredb: false,
+                coredb_annotated: false,
+                running: None,
+                created_at: ns.metadata.creation_timestamp.as_ref().map(|time| time.0),
+            },
+        );
+    }
+
+    for coredb in coredbs.items {
+        let Some(namespace) = coredb.namespace() else {
+            continue;
+        };
+        let instance = instances
+            .entry(namespace.clone())
+            .or_insert_with(|| InventoryInstance {
+                namespace,
+                org_id: None,
+                inst_id: None,
+                labelled_namespace: false,
+                namespace_phase: None,
+                has_coredb: false,
+                coredb_annotated: false,
+                running: None,
+                created_at: coredb
+                    .metadata
+                    .creation_timestamp
+                    .as_ref()
+                    .map(|time| time.0),
+            });
+        instance.has_coredb = true;
+        instance.running = coredb.status.as_ref().map(|status| status.running);
+        if let Ok(org_inst) = get_org_inst_id(&coredb) {
+            instance.coredb_annotated = true;
+            instance.org_id = Some(org_inst.org_id);
+            instance.inst_id = Some(org_inst.inst_id);
+        }
+    }
+
+    Ok(instances.into_values().collect())
+}
+
+// Set the gauge for every kind and action, so orphans which are gone drop back to zero
+fn record_orphans(metrics: &CustomMetrics, orphans: &[Orphan]) {
+    for kind in ORPHAN_KINDS {
+        for action in ORPHAN_ACTIONS {
+            let count = orphans
+                .iter()
+                .filter(|orphan| orphan.kind == kind && orphan.action == action)
+                .count();
+            metrics.conductor_orphans.record(
+                count as u64,
+                &[
+                    KeyValue::new("kind", format!("{:?}", kind)),
+                    KeyValue::new("action", format!("{:?}", action)),
+                ],
+            );
+        }
+    }
+}
+
+// Remember when labelled namespaces were first seen without a CoreDB, and forget
+// the ones which have one again or are gone
+fn track_without_coredb(
+    without_coredb_since: &mut HashMap<String, DateTime<Utc>>,
+    instances: &[InventoryInstance],
+    now: DateTime<Utc>,
+) {
+    without_coredb_since.retain(|namespace, _| {
+        instances.iter().any(|instance| {
+            &instance.namespace == namespace && instance.labelled_namespace && !instance.has_coredb
+        })
+    });
+    for instance in instances {
+        if instance.labelled_namespace && !instance.has_coredb {
+            without_coredb_since
+                .entry(instance.namespace.clone())
+                .or_insert(now);
+        }
+    }
+}
+
+async fn fetch_deleted_instances(
+    queue: &PGMQueueExt,
+) -> Result<HashMap<String, Option<DateTime<Utc>>>> {
+    let rows: Vec<(String, Option<DateTime<Utc>>)> =
+        sqlx::query_as("SELECT namespace, deleted_at FROM deleted_instances;")
+            .fetch_all(&queue.connection)
+            .await?;
+    Ok(rows.into_iter().collect())
+}
+
+#[derive(Debug, PartialEq)]
+struct OrphanCandidate {
+    orphan: Orphan,
+    // past the grace period and safe to garbage-collect
+    collectable: bool,
+}
+
+fn past_grace_period(
+    since: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    grace_period: ChronoDuration,
+) -> bool {
+    match since {
+        Some(since) => since + grace_period <= now,
+        None => false,
+    }
+}
+
+fn find_orphans(
+    instances: &[InventoryInstance],
+    deleted_instances: &HashMap<String, Option<DateTime<Utc>>>,
+    without_coredb_since: &HashMap<String, DateTime<Utc>>,
+    stack_namespaces: &[String],
+    now: DateTime<Utc>,
+    grace_period: ChronoDuration,
+) -> Vec<OrphanCandidate> {
+    let flagged = |namespace: &str, kind: OrphanKind, collectable: bool| OrphanCandidate {
+        orphan: Orphan {
+            namespace: namespace.to_string(),
+            kind,
+            action: OrphanAction::Flagged,
+        },
+        collectable,
+    };
+
+    let mut candidates = Vec::new();
+    for instance in instances {
+        if let Some(deleted_at) = deleted_instances.get(&instance.namespace) {
+            candidates.push(flagged(
+                &instance.namespace,
+                OrphanKind::DeletedInstance,
+                past_grace_period(*deleted_at, now, grace_period),
+            ));
+        } else if instance.labelled_namespace && !instance.has_coredb {
+            // A new namespace, or one whose CoreDB is being recreated, isn't an orphan yet
+            let collectable = past_grace_period(instance.created_at, now, grace_period)
+                && past_grace_period(
+                    without_coredb_since.get(&instance.namespace).copied(),
+                    now,
+                    grace_period,
+                );
+            candidates.push(flagged(
+                &instance.namespace,
+                OrphanKind::NamespaceWithoutCoreDB,
+                collectable,
+            ));
+        } else if instance.has_coredb && !instance.coredb_annotated {
+            // Without annotations we can't tell who owns it, so never delete
+            candidates.push(flagged(
+                &instance.namespace,
+                OrphanKind::UnmanagedCoreDB,
+                false,
+            ));
+        }
+    }
+
+    for namespace in stack_namespaces {
+        if let Some(deleted_at) = deleted_instances.get(namespace) {
+            candidates.push(flagged(
+                namespace,
+                OrphanKind::CloudFormationStack,
+                past_grace_period(*deleted_at, now, grace_period),
+            ));
+        }
+    }
+
+    candidates
+}
+
+async fn collect_orphan(client: &Client, aws_region: &str, orphan: &Orphan) -> OrphanAction {
+    let result = match orphan.kind {
+        OrphanKind::DeletedInstance | OrphanKind::NamespaceWithoutCoreDB => {
+            delete_coredb_and_namespace(client.clone(), &orphan.namespace, &orphan.namespace)
+                .await
+                .map(|_| ())
+        }
+        OrphanKind::CloudFormationStack => {
+            delete_cloudformation(aws_region.to_string(), &orphan.namespace).await
+        }
+        OrphanKind::UnmanagedCoreDB => return OrphanAction::Flagged,
+    };
+
+    match result {
+        Ok(_) => {
+            info!(
+                "inventory_reporter: deleting orphan {:?} in {}",
+                orphan.kind, orphan.namespace
+            );
+            OrphanAction::Deleting
+        }
+        Err(e) => {
+            error!(
+                "inventory_reporter: failed to delete orphan {:?} in {}: {}",
+                orphan.kind, orphan.namespace, e
+            );
+            OrphanAction::DeleteFailed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(namespace: &str) -> InventoryInstance {
+        InventoryInstance {
+            namespace: namespace.to_string(),
+            org_id: Some("org-1".to_string()),
+            inst_id: Some("inst-1".to_string()),
+            labelled_namespace: true,
+            namespace_phase: Some("Active".to_string()),
+            has_coredb: true,
+            coredb_annotated: true,
+            running: Some(true),
+            created_at: Some(Utc::now() - ChronoDuration::days(7)),
+        }
+    }
+
+    #[test]
+    fn test_parse_orphan_policy() {
+        assert_eq!(
+            "report".parse::<OrphanPolicy>().unwrap(),
+            OrphanPolicy::Report
+        );
+        assert_eq!(
+            "delete".parse::<OrphanPolicy>().unwrap(),
+            OrphanPolicy::Delete
+        );
+        assert!("gc".parse::<OrphanPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_healthy_instances_are_not_orphans() {
+        let instances = vec![instance("org-1-inst-1")];
+        let candidates = find_orphans(
+            &instances,
+            &HashMap::new(),
+            &HashMap::new(),
+            &["org-1-inst-1".to_string()],
+            Utc::now(),
+            ChronoDuration::hours(1),
+        );
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let now = Utc::now();
+        let grace_period = ChronoDuration::hours(1);
+
+        let deleted = instance("deleted");
+        let recently_deleted = instance("recently-deleted");
+        let empty_namespace = InventoryInstance {
+            has_coredb: false,
+            coredb_annotated: false,
+            running: None,
+            ..instance("empty")
+        };
+        let recently_emptied = InventoryInstance {
+            has_coredb: false,
+            coredb_annotated: false,
+            running: None,
+            ..instance("recently-emptied")
+        };
+        let unmanaged = InventoryInstance {
+            labelled_namespace: false,
+            coredb_annotated: false,
+            org_id: None,
+            inst_id: None,
+            ..instance("unmanaged")
+        };
+        let instances = vec![
+            deleted,
+            recently_deleted,
+            empty_namespace,
+            recently_emptied,
+            unmanaged,
+        ];
+        let without_coredb_since = HashMap::from([
+            ("empty".to_string(), now - ChronoDuration::hours(2)),
+            ("recently-emptied".to_string(), now),
+        ]);
+
+        let deleted_instances = HashMap::from([
+            ("deleted".to_string(), Some(now - ChronoDuration::hours(2))),
+            ("recently-deleted".to_string(), Some(now)),
+            (
+                "stack-only".to_string(),
+                Some(now - ChronoDuration::hours(2)),
+            ),
+        ]);
+        let stack_namespaces = vec!["stack-only".to_string(), "live".to_string()];
+
+        let candidates = find_orphans(
+            &instances,
+            &deleted_instances,
+            &without_coredb_since,
+            &stack_namespaces,
+            now,
+            grace_period,
+        );
+        let summary: Vec<(&str, OrphanKind, bool)> = candidates
+            .iter()
+            .map(|c| (c.orphan.namespace.as_str(), c.orphan.kind, c.collectable))
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                ("deleted", OrphanKind::DeletedInstance, true),
+                ("recently-deleted", OrphanKind::DeletedInstance, false),
+                ("empty", OrphanKind::NamespaceWithoutCoreDB, true),
+                (
+                    "recently-emptied",
+                    OrphanKind::NamespaceWithoutCoreDB,
+                    false
+                ),
+                ("unmanaged", OrphanKind::UnmanagedCoreDB, false),
+                ("stack-only", OrphanKind::CloudFormationStack, true),
+            ]
+        );
+        assert!(candidates
+            .iter()
+            .all(|c| c.orphan.action == OrphanAction::Flagged));
+    }
+
+    #[test]
+    fn test_track_without_coredb() {
+        let now = Utc::now();
+        let earlier = now - ChronoDuration::hours(2);
+        let empty = InventoryInstance {
+            has_coredb: false,
+            ..instance("empty")
+        };
+        let mut without_coredb_since = HashMap::from([
+            ("empty".to_string(), earlier),
+            ("recreated".to_string(), earlier),
+        ]);
+
+        let instances = vec![
+            empty,
+            instance("recreated"),
+            InventoryInstance {
+                has_coredb: false,
+                ..instance("new")
+            },
+        ];
+        track_without_coredb(&mut without_coredb_since, &instances, now);
+
+        assert_eq!(
+            without_coredb_since,
+            HashMap::from([("empty".to_string(), earlier), ("new".to_string(), now)])
+        );
+    }
+}
@@ -4,6 +4,7 @@ pub mod errors;
 pub mod extensions;
 pub mod metrics;
 pub mod monitoring;
+pub mod namespace_policies;
 pub mod routes;
 pub mod types;
 
@@ -23,6 +24,7 @@ use chrono::{DateTime, SecondsFormat, Utc};
 use kube::{Api, Client, ResourceExt};
 use log::{debug, info, warn};
 use serde_json::{from_str, to_string, Value};
+use sqlx::PgPool;
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
@@ -509,6 +511,21 @@ pub async fn delete_cloudformation(
     Ok(())
 }
 
+// List the namespaces which have a cloudformation stack, based on the
+// `<namespace>-cf` stack naming convention used by create_cloudformation
+pub async fn list_cloudformation_namespaces(
+    aws_region: String,
+) -> Result<Vec<String>, ConductorError> {
+    let region = Region::new(aws_region);
+    let aws_config_state = AWSConfigState::new(region).await;
+    let stack_names = aws_config_state.list_cloudformation_stacks().await?;
+    Ok(stack_names
+        .iter()
+        .filter_map(|stack_name| stack_name.strip_suffix("-cf"))
+        .map(String::from)
+        .collect())
+}
+
 pub struct StackOutputs {
     pub role_name: Option<String>,
     pub role_arn: Option<String>,
@@ -561,6 +578,39 @@ pub fn generate_cron_expression(input: &str) -> String {
     format!("{} {} * * *", minute, hour)
 }
 
+// Remember the last event processed for a namespace, for the admin API.
+// Failures are only logged, so they never hold up processing of the queue.
+pub async fn record_event_outcome(
+    pool: &PgPool,
+    namespace: &str,
+    msg_id: i64,
+    read_ct: i32,
+    event_type: types::Event,
+    outcome: types::EventOutcome,
+) {
+    let result = sqlx::query(
+        "INSERT INTO processed_events (namespace, msg_id, event_type, outcome, read_ct, processed_at)
+         VALUES ($1, $2, $3, $4, $5, now())
+         ON CONFLICT (namespace) DO UPDATE SET
+             msg_id = EXCLUDED.msg_id,
+             event_type = EXCLUDED.event_type,
+             outcome = EXCLUDED.outcome,
+             read_ct = EXCLUDED.read_ct,
+             processed_at = EXCLUDED.processed_at;",
+    )
+    .bind(namespace)
+    .bind(msg_id)
+    .bind(format!("{:?}", event_type))
+    .bind(outcome.as_str())
+    .bind(read_ct)
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        warn!("Failed to record outcome of message {}: {}", msg_id, e);
+    }
+}
+
 // returns Ok(true) when all deleted, otherwise Ok(false) when delete in progress
 pub async fn delete_coredb_and_namespace(
     client: Client,
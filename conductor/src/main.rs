@@ -2,15 +2,21 @@ use actix_web::{web, App, HttpResponse, HttpServer};
 use actix_web_opentelemetry::{RequestMetrics, RequestTracing};
 use conductor::errors::ConductorError;
 use conductor::monitoring::CustomMetrics;
+use conductor::namespace_policies::{apply_namespace_policies, NamespaceTiers};
 use conductor::{
     cloud::CloudProvider, create_cloudformation, create_namespace, create_or_update,
     delete_cloudformation, delete_coredb_and_namespace, generate_cron_expression, generate_spec,
-    get_coredb_error_without_status, get_one, get_pg_conn, lookup_role_arn, restart_coredb, types,
+    get_coredb_error_without_status, get_one, get_pg_conn, lookup_role_arn, record_event_outcome,
+    restart_coredb, types,
 };
 use opentelemetry_sdk::{metrics::SdkMeterProvider, Resource};
 
+use crate::inventory_reporter::run_inventory_reporter;
 use crate::metrics_reporter::run_metrics_reporter;
 use crate::status_reporter::run_status_reporter;
+use conductor::routes::admin::{
+    last_event, list_messages, mark_deleted, requeue_message, unmark_deleted, AdminState,
+};
 use conductor::routes::health::background_threads_running;
 use controller::apis::coredb_types::{
     Backup, CoreDBSpec, S3Credentials, S3CredentialsAccessKeyId, S3CredentialsSecretAccessKey,
@@ -28,11 +34,13 @@ use opentelemetry::{global, KeyValue};
 use pgmq::{Message, PGMQueueExt};
 use sqlx::error::Error;
 use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
 use std::env;
 use std::sync::{Arc, Mutex};
 use std::time;
-use types::{CRUDevent, Event};
+use types::{CRUDevent, Event, EventOutcome};
 
+mod inventory_reporter;
 mod metrics_reporter;
 mod status_reporter;
 
@@ -110,6 +118,13 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
         .parse()
         .expect("error parsing CUSTOM_S3_SECRET_ACCESS_KEY");
 
+    let is_namespace_policies: bool = env::var("NAMESPACE_POLICIES_ENABLED")
+        .unwrap_or_else(|_| "false".to_owned())
+        .parse()
+        .expect("error parsing NAMESPACE_POLICIES_ENABLED");
+    // Built-in tiers from namespace-tiers.yml are used when not set
+    let namespace_tiers_path = env::var("NAMESPACE_TIERS_PATH").ok();
+
     // Error and exit if CF_TEMPLATE_BUCKET is not set when IS_CLOUD_FORMATION is enabled
     if is_cloud_formation && cf_template_bucket.is_empty() {
         panic!("CF_TEMPLATE_BUCKET is required when IS_CLOUD_FORMATION is true");
@@ -162,6 +177,12 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
 
     log::info!("Database migrations have been successfully applied.");
 
+    let namespace_tiers = if is_namespace_policies {
+        Some(NamespaceTiers::load(namespace_tiers_path.as_deref())?)
+    } else {
+        None
+    };
+
     // Determine the cloud provider using the builder
     let cloud_provider = CloudProvider::builder().aws(is_cloud_formation).build();
 
@@ -219,6 +240,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                 {
                     error!("Failed to archive message: {}", e);
                 }
+                record_outcome(&db_pool, &read_msg, EventOutcome::Archived).await;
                 continue;
             }
         }
@@ -236,6 +258,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                 .archive(&control_plane_events_queue, read_msg.msg_id)
                 .await?;
             metrics.conductor_errors.add(1, &[]);
+            record_outcome(&db_pool, &read_msg, EventOutcome::Failed).await;
 
             // this is what we'll send back to control-plane
             let error_event = types::StateToControlPlane {
@@ -246,6 +269,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                 spec: None,
                 status: None,
                 connection: None,
+                changes: Vec::new(),
             };
             let msg_id = queue.send(&data_plane_events_queue, &error_event).await?;
             error!(
@@ -274,6 +298,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                         .archive(&control_plane_events_queue, read_msg.msg_id)
                         .await?;
                     metrics.conductor_errors.add(1, &[]);
+                    record_outcome(&db_pool, &read_msg, EventOutcome::Failed).await;
                     continue;
                 }
                 // spec.expect() should be safe here - since above we continue in loop when it is None
@@ -315,6 +340,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                                     REQUEUE_VT_SEC_SHORT,
                                 )
                                 .await?;
+                            record_outcome(&db_pool, &read_msg, EventOutcome::Requeued).await;
                             metrics
                                 .conductor_requeues
                                 .add(1, &[KeyValue::new("queue_duration", "short")]);
@@ -332,6 +358,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                                     REQUEUE_VT_SEC_LONG,
                                 )
                                 .await?;
+                            record_outcome(&db_pool, &read_msg, EventOutcome::Requeued).await;
                             metrics
                                 .conductor_requeues
                                 .add(1, &[KeyValue::new("queue_duration", "long")]);
@@ -344,6 +371,24 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                 // create Namespace
                 create_namespace(client.clone(), &namespace, org_id, instance_id).await?;
 
+                if let Some(namespace_tiers) = &namespace_tiers {
+                    let tier = read_msg.message.tier.as_deref();
+                    // Events without a tier keep the namespace's current limits, only new
+                    // namespaces fall back to the default tier's container limits. Their
+                    // quota is skipped, since it can't be sized for the instance.
+                    if tier.is_some()
+                        || matches!(read_msg.message.event_type, Event::Create | Event::Restore)
+                    {
+                        apply_namespace_policies(
+                            client.clone(),
+                            &namespace,
+                            namespace_tiers.for_tier(tier),
+                            namespace_tiers.quota_for_tier(tier),
+                        )
+                        .await?;
+                    }
+                }
+
                 init_custom_s3_backup_configuration(
                     is_custom_s3_backup,
                     &read_msg,
@@ -412,6 +457,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                                         REQUEUE_VT_SEC_SHORT,
                                     )
                                     .await?;
+                                record_outcome(&db_pool, &read_msg, EventOutcome::Requeued).await;
                                 metrics
                                     .conductor_requeues
                                     .add(1, &[KeyValue::new("queue_duration", "short")]);
@@ -429,6 +475,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                                         REQUEUE_VT_SEC_LONG,
                                     )
                                     .await?;
+                                record_outcome(&db_pool, &read_msg, EventOutcome::Requeued).await;
                                 metrics.conductor_errors.add(1, &[]);
                                 continue;
                             }
@@ -457,6 +504,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                                     &metrics,
                                     &control_plane_events_queue,
                                     &queue,
+                                    &db_pool,
                                     &read_msg,
                                 )
                                 .await?;
@@ -482,6 +530,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                     spec: Some(current_spec.spec),
                     status: current_spec.status,
                     connection: Some(conn_info),
+                    changes: Vec::new(),
                 }
             }
             Event::Delete => {
@@ -501,6 +550,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                             REQUEUE_DELETE_VT_SEC,
                         )
                         .await?;
+                    record_outcome(&db_pool, &read_msg, EventOutcome::Requeued).await;
                     // requeue the delete event
                     // don't process the remainder of the delete event until CoreDB and NS are deleted
                     continue;
@@ -536,6 +586,7 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                     spec: None,
                     status: None,
                     connection: None,
+                    changes: Vec::new(),
                 }
             }
             Event::Restart => {
@@ -551,8 +602,14 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                     }
                     Err(_) => {
                         error!("{}: Error restarting instance", read_msg.msg_id);
-                        requeue_short(&metrics, &control_plane_events_queue, &queue, &read_msg)
-                            .await?;
+                        requeue_short(
+                            &metrics,
+                            &control_plane_events_queue,
+                            &queue,
+                            &db_pool,
+                            &read_msg,
+                        )
+                        .await?;
                         continue;
                     }
                 };
@@ -566,8 +623,14 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                         coredb
                     }
                     Err(_) => {
-                        requeue_short(&metrics, &control_plane_events_queue, &queue, &read_msg)
-                            .await?;
+                        requeue_short(
+                            &metrics,
+                            &control_plane_events_queue,
+                            &queue,
+                            &db_pool,
+                            &read_msg,
+                        )
+                        .await?;
                         continue;
                     }
                 };
@@ -588,11 +651,13 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
                     spec: Some(current_resource.spec),
                     status: current_resource.status,
                     connection: conn_info.ok(),
+                    changes: Vec::new(),
                 }
             }
             _ => {
                 warn!("Unhandled event_type: {:?}", read_msg.message.event_type);
                 metrics.conductor_errors.add(1, &[]);
+                record_outcome(&db_pool, &read_msg, EventOutcome::Failed).await;
                 continue;
             }
         };
@@ -609,6 +674,15 @@ async fn run(metrics: CustomMetrics) -> Result<(), ConductorError> {
             .await?;
 
         metrics.conductor_completed.add(1, &[]);
+        record_event_outcome(
+            &db_pool,
+            &namespace,
+            read_msg.msg_id,
+            read_msg.read_ct,
+            read_msg.message.event_type,
+            EventOutcome::Completed,
+        )
+        .await;
 
         info!("{}: archived: {:?}", read_msg.msg_id, archived);
     }
@@ -642,6 +716,7 @@ async fn requeue_short(
     metrics: &CustomMetrics,
     control_plane_events_queue: &str,
     queue: &PGMQueueExt,
+    db_pool: &PgPool,
     read_msg: &Message<CRUDevent>,
 ) -> Result<(), ConductorError> {
     let _ = queue
@@ -654,9 +729,22 @@ async fn requeue_short(
     metrics
         .conductor_requeues
         .add(1, &[KeyValue::new("queue_duration", "short")]);
+    record_outcome(db_pool, read_msg, EventOutcome::Requeued).await;
     Ok(())
 }
 
+async fn record_outcome(db_pool: &PgPool, read_msg: &Message<CRUDevent>, outcome: EventOutcome) {
+    record_event_outcome(
+        db_pool,
+        &read_msg.message.namespace,
+        read_msg.msg_id,
+        read_msg.read_ct,
+        read_msg.message.event_type,
+        outcome,
+    )
+    .await;
+}
+
 // https://github.com/rust-lang/rust-clippy/issues/6446
 // False positive because lock is dropped before await
 #[allow(clippy::await_holding_lock)]
@@ -703,6 +791,7 @@ async fn main() -> std::io::Result<()> {
     let conductor_enabled = from_env_default("CONDUCTOR_ENABLED", "true");
     let status_reporter_enabled = from_env_default("WATCHER_ENABLED", "true");
     let metrics_reported_enabled = from_env_default("METRICS_REPORTER_ENABLED", "false");
+    let inventory_reporter_enabled = from_env_default("INVENTORY_REPORTER_ENABLED", "false");
 
     if conductor_enabled != "false" {
         info!("Starting conductor");
@@ -768,6 +857,23 @@ async fn main() -> std::io::Result<()> {
         }));
     }
 
+    if inventory_reporter_enabled != "false" {
+        info!("Starting inventory reporter");
+        background_threads_locked.push(tokio::spawn({
+            let custom_metrics_copy = custom_metrics.clone();
+            async move {
+                loop {
+                    if let Err(err) = run_inventory_reporter(custom_metrics_copy.clone()).await {
+                        custom_metrics_copy.conductor_errors.add(1, &[]);
+                        error!("error in inventory_reporter: {err}")
+                    }
+                    warn!("inventory_reporter exited, sleeping for 1 second");
+                    tokio::time::sleep(time::Duration::from_secs(1)).await;
+                }
+            }
+        }));
+    }
+
     std::mem::drop(background_threads_locked);
 
     let server_port = env::var("PORT")
@@ -778,15 +884,49 @@ async fn main() -> std::io::Result<()> {
     // Create a shared data structure for the registry
     let registry_data = web::Data::new(registry);
 
+    // The admin API is only served when a token is configured
+    let admin_state = match env::var("ADMIN_API_TOKEN") {
+        Ok(token) if !token.is_empty() => {
+            let pg_conn_url = env::var("POSTGRES_QUEUE_CONNECTION")
+                .expect("POSTGRES_QUEUE_CONNECTION must be set");
+            let control_plane_events_queue = env::var("CONTROL_PLANE_EVENTS_QUEUE")
+                .expect("CONTROL_PLANE_EVENTS_QUEUE must be set");
+            let pool = PgPoolOptions::new()
+                .max_connections(2)
+                .connect_lazy(&pg_conn_url)
+                .expect("Failed to create admin PG pool");
+            info!("Serving admin API");
+            Some(web::Data::new(AdminState {
+                pool,
+                control_plane_events_queue,
+                token,
+            }))
+        }
+        _ => None,
+    };
+
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .app_data(web::Data::new(custom_metrics.clone()))
             .app_data(web::Data::new(background_threads.clone()))
             .app_data(registry_data.clone())
             .wrap(RequestTracing::new())
             .wrap(RequestMetrics::default())
             .route("/metrics", web::get().to(metrics_handler))
-            .service(web::scope("/health").service(background_threads_running))
+            .service(web::scope("/health").service(background_threads_running));
+
+        match &admin_state {
+            Some(admin_state) => app.service(
+                web::scope("/admin")
+                    .app_data(admin_state.clone())
+                    .service(list_messages)
+                    .service(last_event)
+                    .service(requeue_message)
+                    .service(mark_deleted)
+                    .service(unmark_deleted),
+            ),
+            None => app,
+        }
     })
     .workers(1)
     .bind(("0.0.0.0", server_port))?
@@ -90,6 +90,149 @@ pub mod dataplane_metrics {
     }
 }
 
+/// Encoding of Data Plane metrics for the Prometheus remote-write protocol
+pub mod remote_write {
+    use super::dataplane_metrics::DataPlaneMetrics;
+    use prost::Message;
+
+    pub const CONTENT_TYPE: &str = "application/x-protobuf";
+    pub const CONTENT_ENCODING: &str = "snappy";
+    pub const VERSION_HEADER: &str = "X-Prometheus-Remote-Write-Version";
+    pub const VERSION: &str = "0.1.0";
+
+    /// `prometheus.WriteRequest` from prompb/remote.proto, without the deprecated
+    /// and metadata fields which we never send
+    #[derive(Clone, PartialEq, Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    /// `prometheus.TimeSeries` from prompb/types.proto
+    #[derive(Clone, PartialEq, Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    /// `prometheus.Label` from prompb/types.proto
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    /// `prometheus.Sample` from prompb/types.proto
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+
+    /// Encode metrics as a snappy-compressed `prometheus.WriteRequest`
+    pub fn encode_write_request(metrics: &DataPlaneMetrics) -> Result<Vec<u8>, snap::Error> {
+        snap::raw::Encoder::new().compress_vec(&write_request(metrics).encode_to_vec())
+    }
+
+    fn write_request(metrics: &DataPlaneMetrics) -> WriteRequest {
+        let timeseries = metrics
+            .result
+            .iter()
+            .map(|result| {
+                let mut labels = vec![label("__name__", &metrics.name)];
+                if let Some(instance_id) = &result.metric.instance_id {
+                    labels.push(label("instance_id", instance_id));
+                }
+                if let Some(pod) = &result.metric.pod {
+                    labels.push(label("pod", pod));
+                }
+                if let Some(server_name) = &result.metric.server_name {
+                    labels.push(label("server_name", server_name));
+                }
+                // Remote-write receivers expect labels sorted by name
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+                // Query results have a timestamp in seconds, samples use milliseconds
+                let (timestamp, value) = result.value;
+                TimeSeries {
+                    labels,
+                    samples: vec![Sample {
+                        value: value as f64,
+                        timestamp: timestamp * 1000,
+                    }],
+                }
+            })
+            .collect();
+        WriteRequest { timeseries }
+    }
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::metrics::prometheus::{MetricLabels, MetricsResult};
+
+        fn metrics() -> DataPlaneMetrics {
+            DataPlaneMetrics {
+                name: "up".to_string(),
+                result: vec![MetricsResult {
+                    metric: MetricLabels {
+                        instance_id: None,
+                        pod: Some("p".to_string()),
+                        server_name: None,
+                    },
+                    value: (1, 2),
+                }],
+            }
+        }
+
+        #[test]
+        fn test_write_request() {
+            let mut expected_series = vec![
+                // label __name__="up"
+                0x0A, 0x0E, 0x0A, 0x08,
+            ];
+            expected_series.extend_from_slice(b"__name__");
+            expected_series.extend_from_slice(&[0x12, 0x02, b'u', b'p']);
+            // label pod="p"
+            expected_series.extend_from_slice(&[0x0A, 0x08, 0x0A, 0x03]);
+            expected_series.extend_from_slice(b"pod");
+            expected_series.extend_from_slice(&[0x12, 0x01, b'p']);
+            // sample value=2.0 timestamp=1000ms
+            expected_series.extend_from_slice(&[0x12, 0x0C, 0x09]);
+            expected_series.extend_from_slice(&2.0f64.to_le_bytes());
+            expected_series.extend_from_slice(&[0x10, 0xE8, 0x07]);
+
+            let mut expected = vec![0x0A, expected_series.len() as u8];
+            expected.extend_from_slice(&expected_series);
+
+            assert_eq!(write_request(&metrics()).encode_to_vec(), expected);
+        }
+
+        #[test]
+        fn test_encode_write_request() {
+            let body = encode_write_request(&metrics()).unwrap();
+            let decoded = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+            assert_eq!(
+                WriteRequest::decode(decoded.as_slice()).unwrap(),
+                write_request(&metrics())
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::metrics::dataplane_metrics::{split_data_plane_metrics, DataPlaneMetrics};
@@ -1,22 +1,43 @@
 use anyhow::{bail, Context, Result};
 use conductor::metrics::dataplane_metrics::split_data_plane_metrics;
+use conductor::metrics::remote_write;
 use conductor::metrics::{dataplane_metrics::DataPlaneMetrics, prometheus::Metrics};
-use log::{error, info};
+use futures::{stream, StreamExt};
+use log::{error, info, warn};
 use pgmq::PGMQueueExt;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::time::Instant;
 use std::{env, time::Duration};
-use tokio::time::interval;
+use tokio::time::{interval, MissedTickBehavior};
 
 const METRICS_FILE: &str = include_str!("../metrics.yml");
 
+// How often the scheduler checks for queries which are due
+const SCHEDULER_TICK_SECONDS: u64 = 1;
+
+// How often the metrics file is checked for changes
+const RELOAD_INTERVAL_SECONDS: u64 = 30;
+
 use crate::from_env_default;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MetricQuery {
     name: String,
     server: ServerType,
     query: String,
+    #[serde(default = "default_interval_seconds")]
+    interval_seconds: u64,
+    #[serde(default = "default_timeout_seconds")]
+    timeout_seconds: u64,
+}
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,46 +45,236 @@ pub struct MetricQueries {
     metrics: Vec<MetricQuery>,
 }
 
-fn load_metric_queries() -> Result<MetricQueries> {
-    serde_yaml::from_str(METRICS_FILE).map_err(Into::into)
+fn parse_metric_queries(contents: &str) -> Result<MetricQueries> {
+    let queries: MetricQueries = serde_yaml::from_str(contents)?;
+    for metric in &queries.metrics {
+        if metric.interval_seconds == 0 {
+            bail!("Metric `{}` must have a non-zero interval", metric.name);
+        }
+    }
+    Ok(queries)
 }
 
-pub async fn run_metrics_reporter() -> Result<()> {
-    let client = Client::new().await;
+/// Metric queries loaded from METRICS_FILE_PATH, typically a mounted ConfigMap,
+/// falling back to the metrics.yml bundled in the binary
+struct QueryLoader {
+    path: Option<String>,
+    contents: String,
+    metrics: Vec<MetricQuery>,
+}
+
+impl QueryLoader {
+    fn new(path: Option<String>) -> Result<Self> {
+        let contents = match &path {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read metrics file {path}"))?,
+            None => METRICS_FILE.to_string(),
+        };
+        let MetricQueries { metrics } = parse_metric_queries(&contents)?;
+        Ok(Self {
+            path,
+            contents,
+            metrics,
+        })
+    }
+
+    // Returns true when the metrics changed. A bad file keeps the current queries.
+    fn reload(&mut self) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                error!("Failed to read metrics file {path}, keeping current queries: {e}");
+                return false;
+            }
+        };
+        if contents == self.contents {
+            return false;
+        }
+        match parse_metric_queries(&contents) {
+            Ok(MetricQueries { metrics }) => {
+                self.contents = contents;
+                self.metrics = metrics;
+                true
+            }
+            Err(e) => {
+                error!("Failed to parse metrics file {path}, keeping current queries: {e}");
+                false
+            }
+        }
+    }
+}
 
-    let MetricQueries { metrics } = load_metric_queries()?;
-    info!("metrics_reporter: loaded {} metrics", metrics.len());
+/// Destination for query results
+enum Sink {
+    /// The pgmq metrics queue read by control plane
+    Pgmq {
+        queue: PGMQueueExt,
+        queue_name: String,
+    },
+    /// A Prometheus remote-write receiver
+    RemoteWrite {
+        url: String,
+        client: reqwest::Client,
+    },
+}
 
-    let pg_conn_url = env::var("POSTGRES_QUEUE_CONNECTION")
-        .with_context(|| "POSTGRES_QUEUE_CONNECTION must be set")?;
+impl Sink {
+    async fn send(&self, data_plane_metrics: DataPlaneMetrics) -> Result<()> {
+        match self {
+            Sink::Pgmq { queue, queue_name } => {
+                let batch_size = 1000;
+                let metrics_to_send = split_data_plane_metrics(data_plane_metrics, batch_size);
+                let batches = metrics_to_send.len();
+
+                info!(
+                    "Split metrics into {} chunks, each with {} results",
+                    batches, batch_size
+                );
+
+                for (i, data_plane_metrics) in metrics_to_send.iter().enumerate() {
+                    queue.send(queue_name, data_plane_metrics).await?;
+                    info!("Enqueued batch {}/{} to PGMQ", i + 1, batches);
+                }
+                Ok(())
+            }
+            Sink::RemoteWrite { url, client } => {
+                let response = client
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, remote_write::CONTENT_TYPE)
+                    .header(
+                        reqwest::header::CONTENT_ENCODING,
+                        remote_write::CONTENT_ENCODING,
+                    )
+                    .header(remote_write::VERSION_HEADER, remote_write::VERSION)
+                    .body(remote_write::encode_write_request(&data_plane_metrics)?)
+                    .send()
+                    .await?;
 
-    let queue = PGMQueueExt::new(pg_conn_url, 5).await?;
-    let metrics_events_queue =
-        env::var("METRICS_EVENTS_QUEUE").expect("METRICS_EVENTS_QUEUE must be set");
+                if !response.status().is_success() {
+                    let error_msg = response.text().await?;
+                    bail!("Failed to remote-write to {url}: {error_msg}")
+                }
+                info!(
+                    "Remote-wrote {} results of `{}`",
+                    data_plane_metrics.result.len(),
+                    data_plane_metrics.name
+                );
+                Ok(())
+            }
+        }
+    }
+}
 
-    queue.init().await?;
-    queue.create_partitioned(&metrics_events_queue).await?;
+async fn build_sinks() -> Result<Vec<Sink>> {
+    let sink_names = from_env_default("METRICS_SINKS", "pgmq");
+    let mut sinks = Vec::new();
+    for sink_name in sink_names.split(',').map(str::trim) {
+        match sink_name {
+            "pgmq" => {
+                let pg_conn_url = env::var("POSTGRES_QUEUE_CONNECTION")
+                    .with_context(|| "POSTGRES_QUEUE_CONNECTION must be set")?;
+                let queue_name = env::var("METRICS_EVENTS_QUEUE")
+                    .with_context(|| "METRICS_EVENTS_QUEUE must be set")?;
+
+                let queue = PGMQueueExt::new(pg_conn_url, 5).await?;
+                queue.init().await?;
+                queue.create_partitioned(&queue_name).await?;
+                sinks.push(Sink::Pgmq { queue, queue_name });
+            }
+            "remote_write" => {
+                let url = env::var("METRICS_REMOTE_WRITE_URL")
+                    .with_context(|| "METRICS_REMOTE_WRITE_URL must be set")?;
+                info!("metrics_reporter will remote-write to '{url}'");
+                sinks.push(Sink::RemoteWrite {
+                    url,
+                    client: reqwest::Client::new(),
+                });
+            }
+            other => bail!("Unknown metrics sink `{other}`, expected `pgmq` or `remote_write`"),
+        }
+    }
+    Ok(sinks)
+}
 
-    let polling_interval_seconds = 60;
-    let number_of_metrics = metrics.len();
-    let timeout_ms = (polling_interval_seconds * 1000) / number_of_metrics as u64;
-    if timeout_ms < 1000 {
-        panic!("Configuration error: too many metrics, currently up to 60 metrics are supported.");
+// How many queries run at once, which must be at least one or none would ever run
+fn parse_concurrency(value: &str) -> Result<usize> {
+    let concurrency: usize = value
+        .parse()
+        .with_context(|| "error parsing METRICS_QUERY_CONCURRENCY")?;
+    if concurrency == 0 {
+        bail!("METRICS_QUERY_CONCURRENCY must be at least 1");
     }
+    Ok(concurrency)
+}
+
+pub async fn run_metrics_reporter() -> Result<()> {
+    let client = Client::new().await;
+
+    let mut loader = QueryLoader::new(env::var("METRICS_FILE_PATH").ok())?;
+    info!("metrics_reporter: loaded {} metrics", loader.metrics.len());
+
+    let concurrency = parse_concurrency(&from_env_default("METRICS_QUERY_CONCURRENCY", "4"))?;
+
+    let sinks = build_sinks().await?;
 
-    let mut sync_interval = interval(Duration::from_secs(polling_interval_seconds));
+    // When each metric, by name, should next be queried
+    let mut next_runs: HashMap<String, Instant> = HashMap::new();
+    let mut last_reload = Instant::now();
+
+    let mut scheduler_interval = interval(Duration::from_secs(SCHEDULER_TICK_SECONDS));
+    scheduler_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
     loop {
-        sync_interval.tick().await;
+        scheduler_interval.tick().await;
+
+        if last_reload.elapsed() >= Duration::from_secs(RELOAD_INTERVAL_SECONDS) {
+            last_reload = Instant::now();
+            if loader.reload() {
+                info!(
+                    "metrics_reporter: reloaded {} metrics",
+                    loader.metrics.len()
+                );
+                next_runs.retain(|name, _| loader.metrics.iter().any(|m| &m.name == name));
+            }
+        }
 
         let now = Instant::now();
-        for metric in &metrics {
-            info!("Querying '{}' from {}", metric.name, metric.server);
+        let due: Vec<MetricQuery> = loader
+            .metrics
+            .iter()
+            .filter(|metric| next_runs.get(&metric.name).is_none_or(|next| *next <= now))
+            .cloned()
+            .collect();
+        if due.is_empty() {
+            continue;
+        }
+        for metric in &due {
+            next_runs.insert(
+                metric.name.clone(),
+                now + Duration::from_secs(metric.interval_seconds),
+            );
+        }
+
+        let results: Vec<(MetricQuery, Result<Metrics>)> = stream::iter(due)
+            .map(|metric| {
+                let client = &client;
+                async move {
+                    info!("Querying '{}' from {}", metric.name, metric.server);
+                    let result = client
+                        .query(&metric.query, &metric.server, metric.timeout_seconds * 1000)
+                        .await;
+                    (metric, result)
+                }
+            })
+            .buffer_unordered(concurrency)
+            .collect()
+            .await;
 
-            let metrics = match client
-                .query(&metric.query, &metric.server, timeout_ms)
-                .await
-            {
+        for (metric, result) in results {
+            let metrics = match result {
                 Ok(metrics) => metrics,
                 Err(e) => {
                     error!(
@@ -85,25 +296,13 @@ pub async fn run_metrics_reporter() -> Result<()> {
                 result: metrics.data.result,
             };
 
-            let batch_size = 1000;
-            let metrics_to_send = split_data_plane_metrics(data_plane_metrics, batch_size);
-            let batches = metrics_to_send.len();
-
-            info!(
-                "Split metrics into {} chunks, each with {} results",
-                batches, batch_size
-            );
-
-            let mut i = 1;
-            for data_plane_metrics in &metrics_to_send {
-                queue
-                    .send(&metrics_events_queue, data_plane_metrics)
-                    .await?;
-                info!("Enqueued batch {}/{} to PGMQ", i, batches);
-                i += 1;
+            for sink in &sinks {
+                if let Err(e) = sink.send(data_plane_metrics.clone()).await {
+                    warn!("Failed to send `{}` to a sink: {}", metric.name, e);
+                }
             }
-            info!("Processed metric in {:?}", now.elapsed());
         }
+        info!("Processed metrics in {:?}", now.elapsed());
     }
 }
 
@@ -113,7 +312,7 @@ struct Client {
     client: reqwest::Client,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum ServerType {
     Prometheus,
@@ -184,10 +383,76 @@ impl Client {
 
 #[cfg(test)]
 mod tests {
-    use super::load_metric_queries;
+    use super::{parse_concurrency, parse_metric_queries, QueryLoader, METRICS_FILE};
 
     #[test]
     fn deserializes_metrics_yaml() {
-        load_metric_queries().unwrap();
+        parse_metric_queries(METRICS_FILE).unwrap();
+    }
+
+    #[test]
+    fn applies_query_defaults() {
+        let queries = parse_metric_queries(
+            r#"
+metrics:
+- name: up
+  server: prometheus
+  query: up
+- name: slow
+  server: loki
+  query: count_over_time({namespace="traefik"}[5m])
+  interval_seconds: 300
+  timeout_seconds: 30
+"#,
+        )
+        .unwrap();
+        assert_eq!(queries.metrics[0].interval_seconds, 60);
+        assert_eq!(queries.metrics[0].timeout_seconds, 10);
+        assert_eq!(queries.metrics[1].interval_seconds, 300);
+        assert_eq!(queries.metrics[1].timeout_seconds, 30);
+    }
+
+    #[test]
+    fn rejects_zero_interval() {
+        let result = parse_metric_queries(
+            r#"
+metrics:
+- name: up
+  server: prometheus
+  query: up
+  interval_seconds: 0
+"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_zero_concurrency() {
+        assert_eq!(parse_concurrency("8").unwrap(), 8);
+        assert!(parse_concurrency("0").is_err());
+        assert!(parse_concurrency("-1").is_err());
+    }
+
+    #[test]
+    fn reloads_metrics_file() {
+        let path = std::env::temp_dir().join(format!("metrics-{}.yml", std::process::id()));
+        let query =
+            |name: &str| format!("metrics:\n- name: {name}\n  server: prometheus\n  query: up\n");
+        std::fs::write(&path, query("first")).unwrap();
+
+        let mut loader = QueryLoader::new(Some(path.to_string_lossy().to_string())).unwrap();
+        assert_eq!(loader.metrics[0].name, "first");
+        assert!(!loader.reload());
+
+        std::fs::write(&path, query("second")).unwrap();
+        assert!(loader.reload());
+        assert_eq!(loader.metrics[0].name, "second");
+
+        // An invalid file keeps the previous queries
+        std::fs::write(&path, "metrics: [").unwrap();
+        assert!(!loader.reload());
+        assert_eq!(loader.metrics[0].name, "second");
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -1,4 +1,4 @@
-use opentelemetry::metrics::{Counter, Meter};
+use opentelemetry::metrics::{Counter, Gauge, Meter};
 
 #[derive(Clone)]
 pub struct CustomMetrics {
@@ -6,6 +6,8 @@ pub struct CustomMetrics {
     pub conductor_requeues: Counter<u64>,
     pub conductor_errors: Counter<u64>,
     pub conductor_completed: Counter<u64>,
+    pub conductor_orphans: Gauge<u64>,
+    pub conductor_status_updates: Counter<u64>,
 }
 
 impl CustomMetrics {
@@ -26,11 +28,21 @@ impl CustomMetrics {
             .u64_counter("conductor_completed")
             .with_description("Number of messages sucessfully processed in conductor")
             .build();
+        let conductor_orphans = meter
+            .u64_gauge("conductor_orphans")
+            .with_description("Number of orphaned resources found by the last inventory pass")
+            .build();
+        let conductor_status_updates = meter
+            .u64_counter("conductor_status_updates")
+            .with_description("Number of CoreDB status updates sent or suppressed by the watcher")
+            .build();
         Self {
             conductor_total,
             conductor_requeues,
             conductor_errors,
             conductor_completed,
+            conductor_orphans,
+            conductor_status_updates,
         }
     }
 }
@@ -0,0 +1,291 @@
+use crate::errors::ConductorError;
+use k8s_openapi::api::core::v1::{LimitRange, ResourceQuota};
+use k8s_openapi::api::networking::v1::NetworkPolicy;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::{Api, Client};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const DEFAULT_TIERS_FILE: &str = include_str!("../namespace-tiers.yml");
+const DEFAULT_TIER: &str = "default";
+
+const RESOURCE_QUOTA_NAME: &str = "tembo-quota";
+const LIMIT_RANGE_NAME: &str = "tembo-limits";
+const NETWORK_POLICY_NAME: &str = "tembo-default";
+
+/// Resource sizing applied to an instance namespace
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct TierLimits {
+    /// ResourceQuota `spec.hard` for the whole namespace
+    pub quota: BTreeMap<String, String>,
+    /// LimitRange `default` for containers which don't set limits
+    pub container_limits: BTreeMap<String, String>,
+    /// LimitRange `defaultRequest` for containers which don't set requests
+    pub container_requests: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NamespaceTiers {
+    tiers: BTreeMap<String, TierLimits>,
+}
+
+impl NamespaceTiers {
+    /// Load the tier table from `path`, or the built-in table when not provided
+    pub fn load(path: Option<&str>) -> Result<Self, ConductorError> {
+        match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path).map_err(|e| {
+                    ConductorError::ConfigurationError(format!("failed to read {path}: {e}"))
+                })?;
+                Self::from_yaml(&contents)
+            }
+            None => Self::from_yaml(DEFAULT_TIERS_FILE),
+        }
+    }
+
+    pub fn from_yaml(contents: &str) -> Result<Self, ConductorError> {
+        let namespace_tiers: NamespaceTiers = serde_yaml::from_str(contents)
+            .map_err(|e| ConductorError::ConfigurationError(e.to_string()))?;
+        if !namespace_tiers.tiers.contains_key(DEFAULT_TIER) {
+            return Err(ConductorError::ConfigurationError(format!(
+                "namespace tiers must include a `{DEFAULT_TIER}` tier"
+            )));
+        }
+        Ok(namespace_tiers)
+    }
+
+    /// Limits for the given tier, falling back to the default tier
+    pub fn for_tier(&self, tier: Option<&str>) -> &TierLimits {
+        if let Some(tier) = tier {
+            match self.tiers.get(tier) {
+                Some(limits) => return limits,
+                None => warn!("Unknown instance tier {}, using {}", tier, DEFAULT_TIER),
+            }
+        }
+        &self.tiers[DEFAULT_TIER]
+    }
+
+    /// ResourceQuota `spec.hard` for the given tier, only when the tier is known. The
+    /// default tier's quota could be smaller than the instance, which would keep its pods
+    /// from scheduling, so namespaces without a known tier get no quota.
+    pub fn quota_for_tier(&self, tier: Option<&str>) -> Option<&BTreeMap<String, String>> {
+        tier.and_then(|tier| self.tiers.get(tier))
+            .map(|limits| &limits.quota)
+    }
+}
+
+fn resource_quota(namespace: &str, quota: &BTreeMap<String, String>) -> Value {
+    serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "ResourceQuota",
+        "metadata": {
+            "name": RESOURCE_QUOTA_NAME,
+            "namespace": namespace,
+        },
+        "spec": {
+            "hard": quota,
+        }
+    })
+}
+
+fn limit_range(namespace: &str, limits: &TierLimits) -> Value {
+    serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "LimitRange",
+        "metadata": {
+            "name": LIMIT_RANGE_NAME,
+            "namespace": namespace,
+        },
+        "spec": {
+            "limits": [{
+                "type": "Container",
+                "default": limits.container_limits,
+                "defaultRequest": limits.container_requests,
+            }]
+        }
+    })
+}
+
+// Only allow ingress from inside the namespace and from platform namespaces,
+// which are the ones without an instance label. Postgres stays reachable from
+// anywhere, since it may be exposed through a dedicated load balancer.
+fn network_policy(namespace: &str) -> Value {
+    serde_json::json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": {
+            "name": NETWORK_POLICY_NAME,
+            "namespace": namespace,
+        },
+        "spec": {
+            "podSelector": {},
+            "policyTypes": ["Ingress"],
+            "ingress": [
+                {
+                    "from": [
+                        { "podSelector": {} },
+                        {
+                            "namespaceSelector": {
+                                "matchExpressions": [{
+                                    "key": "tembo.io/instance_id",
+                                    "operator": "DoesNotExist",
+                                }]
+                            }
+                        }
+                    ]
+                },
+                {
+                    "ports": [{ "protocol": "TCP", "port": 5432 }]
+                }
+            ]
+        }
+    })
+}
+
+// Create or update the ResourceQuota, LimitRange and NetworkPolicy of an instance namespace.
+// Without a quota, any ResourceQuota applied for an earlier tier is removed.
+pub async fn apply_namespace_policies(
+    client: Client,
+    namespace: &str,
+    limits: &TierLimits,
+    quota: Option<&BTreeMap<String, String>>,
+) -> Result<(), ConductorError> {
+    let params = PatchParams::apply("conductor").force();
+
+    info!(
+        "Applying resource quota and limits to namespace {}",
+        namespace
+    );
+    let quota_api: Api<ResourceQuota> = Api::namespaced(client.clone(), namespace);
+    match quota {
+        Some(quota) => {
+            quota_api
+                .patch(
+                    RESOURCE_QUOTA_NAME,
+                    &params,
+                    &Patch::Apply(&resource_quota(namespace, quota)),
+                )
+                .await
+                .map_err(ConductorError::KubeError)?;
+        }
+        None => match quota_api
+            .delete(RESOURCE_QUOTA_NAME, &DeleteParams::default())
+            .await
+        {
+            Ok(_) => info!("Removed resource quota from namespace {}", namespace),
+            Err(kube::Error::Api(err)) if err.code == 404 => (),
+            Err(e) => return Err(ConductorError::KubeError(e)),
+        },
+    }
+
+    let limit_range_api: Api<LimitRange> = Api::namespaced(client.clone(), namespace);
+    limit_range_api
+        .patch(
+            LIMIT_RANGE_NAME,
+            &params,
+            &Patch::Apply(&limit_range(namespace, limits)),
+        )
+        .await
+        .map_err(ConductorError::KubeError)?;
+
+    let network_policy_api: Api<NetworkPolicy> = Api::namespaced(client, namespace);
+    network_policy_api
+        .patch(
+            NETWORK_POLICY_NAME,
+            &params,
+            &Patch::Apply(&network_policy(namespace)),
+        )
+        .await
+        .map_err(ConductorError::KubeError)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_tiers_deserialize() {
+        let namespace_tiers = NamespaceTiers::load(None).unwrap();
+        let limits = namespace_tiers.for_tier(None);
+        assert_eq!(limits.quota.get("pods").unwrap(), "30");
+    }
+
+    #[test]
+    fn test_for_tier_falls_back_to_default() {
+        let namespace_tiers = NamespaceTiers::load(None).unwrap();
+        assert_eq!(
+            namespace_tiers.for_tier(Some("does-not-exist")),
+            namespace_tiers.for_tier(None)
+        );
+        assert_ne!(
+            namespace_tiers.for_tier(Some("large")),
+            namespace_tiers.for_tier(None)
+        );
+    }
+
+    #[test]
+    fn test_large_instance_without_tier_has_no_quota() {
+        let namespace_tiers = NamespaceTiers::load(None).unwrap();
+        // A 64Gi instance doesn't fit the default tier's 16Gi of requests, so namespaces
+        // without a tier, or with one conductor doesn't know, must not be capped by it
+        assert_eq!(
+            namespace_tiers
+                .for_tier(None)
+                .quota
+                .get("requests.memory")
+                .unwrap(),
+            "16Gi"
+        );
+        assert_eq!(namespace_tiers.quota_for_tier(None), None);
+        assert_eq!(namespace_tiers.quota_for_tier(Some("xlarge")), None);
+        // Containers without resources still get the default tier's limits
+        assert_eq!(
+            namespace_tiers
+                .for_tier(None)
+                .container_limits
+                .get("memory")
+                .unwrap(),
+            "1Gi"
+        );
+
+        let large = namespace_tiers.quota_for_tier(Some("large")).unwrap();
+        assert_eq!(large.get("requests.memory").unwrap(), "128Gi");
+    }
+
+    #[test]
+    fn test_tiers_require_default() {
+        let yaml = r#"
+tiers:
+  small:
+    quota:
+      pods: "10"
+    container_limits: {}
+    container_requests: {}
+"#;
+        assert!(NamespaceTiers::from_yaml(yaml).is_err());
+    }
+
+    #[test]
+    fn test_resource_quota_and_limit_range() {
+        let namespace_tiers = NamespaceTiers::load(None).unwrap();
+        let limits = namespace_tiers.for_tier(Some("small"));
+
+        let quota = resource_quota("org-inst", &limits.quota);
+        assert_eq!(quota["metadata"]["namespace"], "org-inst");
+        assert_eq!(quota["spec"]["hard"]["requests.cpu"], "4");
+
+        let limit_range = limit_range("org-inst", limits);
+        assert_eq!(
+            limit_range["spec"]["limits"][0]["default"]["memory"],
+            "512Mi"
+        );
+        assert_eq!(
+            limit_range["spec"]["limits"][0]["defaultRequest"]["cpu"],
+            "100m"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Shared state for the admin endpoints
+pub struct AdminState {
+    pub pool: PgPool,
+    pub control_plane_events_queue: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct InFlightMessage {
+    pub msg_id: i64,
+    pub read_ct: i32,
+    pub enqueued_at: DateTime<Utc>,
+    pub vt: DateTime<Utc>,
+    pub event_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ProcessedEvent {
+    pub namespace: String,
+    pub msg_id: i64,
+    pub event_type: String,
+    pub outcome: String,
+    pub read_ct: i32,
+    pub processed_at: Option<DateTime<Utc>>,
+}
+
+// Every admin request must carry `Authorization: Bearer <ADMIN_API_TOKEN>`
+fn unauthorized(req: &HttpRequest, state: &AdminState) -> Option<HttpResponse> {
+    let provided = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), state.token.as_bytes()) => None,
+        _ => Some(HttpResponse::Unauthorized().body("Missing or invalid admin token")),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Queue names are interpolated into table names, so only allow what pgmq allows
+fn valid_queue_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[get("/namespaces/{namespace}/messages")]
+pub async fn list_messages(
+    req: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Some(response) = unauthorized(&req, &state) {
+        return response;
+    }
+    let namespace = path.into_inner();
+    if !valid_queue_name(&state.control_plane_events_queue) {
+        return HttpResponse::InternalServerError().body("Invalid control plane queue name");
+    }
+
+    let query = format!(
+        "SELECT msg_id, read_ct, enqueued_at, vt, message->>'event_type' AS event_type
+         FROM pgmq.q_{}
+         WHERE message->>'namespace' = $1
+         ORDER BY msg_id;",
+        state.control_plane_events_queue
+    );
+    match sqlx::query_as::<_, InFlightMessage>(&query)
+        .bind(&namespace)
+        .fetch_all(&state.pool)
+        .await
+    {
+        Ok(messages) => HttpResponse::Ok().json(messages),
+        Err(e) => {
+            error!("Failed to list messages for {}: {}", namespace, e);
+            HttpResponse::InternalServerError().body("Failed to list messages")
+        }
+    }
+}
+
+#[get("/namespaces/{namespace}/last-event")]
+pub async fn last_event(
+    req: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Some(response) = unauthorized(&req, &state) {
+        return response;
+    }
+    let namespace = path.into_inner();
+
+    match sqlx::query_as::<_, ProcessedEvent>(
+        "SELECT namespace, msg_id, event_type, outcome, read_ct, processed_at
+         FROM processed_events
+         WHERE namespace = $1;",
+    )
+    .bind(&namespace)
+    .fetch_optional(&state.pool)
+    .await
+    {
+        Ok(Some(event)) => HttpResponse::Ok().json(event),
+        Ok(None) => HttpResponse::NotFound().body("No event processed for this namespace"),
+        Err(e) => {
+            error!("Failed to get last event for {}: {}", namespace, e);
+            HttpResponse::InternalServerError().body("Failed to get last event")
+        }
+    }
+}
+
+// Make a message visible again immediately, so conductor picks it up on its next read
+#[post("/messages/{msg_id}/requeue")]
+pub async fn requeue_message(
+    req: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    if let Some(response) = unauthorized(&req, &state) {
+        return response;
+    }
+    let msg_id = path.into_inner();
+
+    match sqlx::query_scalar::<_, i64>("SELECT msg_id FROM pgmq.set_vt($1, $2, 0);")
+        .bind(&state.control_plane_events_queue)
+        .bind(msg_id)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(_)) => {
+            info!("admin: requeued message {}", msg_id);
+            HttpResponse::Ok().json(msg_id)
+        }
+        Ok(None) => HttpResponse::NotFound().body("Message not found"),
+        Err(e) => {
+            error!("Failed to requeue message {}: {}", msg_id, e);
+            HttpResponse::InternalServerError().body("Failed to requeue message")
+        }
+    }
+}
+
+#[put("/deleted-instances/{namespace}")]
+pub async fn mark_deleted(
+    req: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Some(response) = unauthorized(&req, &state) {
+        return response;
+    }
+    let namespace = path.into_inner();
+
+    match sqlx::query(
+        "INSERT INTO deleted_instances (namespace) VALUES ($1) ON CONFLICT (namespace) DO NOTHING;",
+    )
+    .bind(&namespace)
+    .execute(&state.pool)
+    .await
+    {
+        Ok(_) => {
+            info!("admin: marked namespace {} as deleted", namespace);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            error!("Failed to mark namespace {} as deleted: {}", namespace, e);
+            HttpResponse::InternalServerError().body("Failed to mark namespace as deleted")
+        }
+    }
+}
+
+#[delete("/deleted-instances/{namespace}")]
+pub async fn unmark_deleted(
+    req: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Some(response) = unauthorized(&req, &state) {
+        return response;
+    }
+    let namespace = path.into_inner();
+
+    match sqlx::query("DELETE FROM deleted_instances WHERE namespace = $1;")
+        .bind(&namespace)
+        .execute(&state.pool)
+        .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::NotFound().body("Namespace is not marked as deleted")
+        }
+        Ok(_) => {
+            info!("admin: unmarked namespace {} as deleted", namespace);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            error!("Failed to unmark namespace {} as deleted: {}", namespace, e);
+            HttpResponse::InternalServerError().body("Failed to unmark namespace as deleted")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+
+    #[test]
+    fn test_valid_queue_name() {
+        assert!(valid_queue_name("myqueue_control_plane"));
+        assert!(!valid_queue_name(""));
+        assert!(!valid_queue_name("queue; DROP TABLE deleted_instances"));
+    }
+}
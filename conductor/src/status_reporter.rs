@@ -1,63 +1,305 @@
 use conductor::errors::ConductorError;
-use controller::apis::coredb_types::CoreDB;
-use futures::TryStreamExt;
-use kube::runtime::{watcher, WatchStreamExt};
-use kube::{Api, Client};
-use log::{error, info, warn};
+use controller::apis::coredb_types::{CoreDB, CoreDBStatus};
+use futures::{StreamExt, TryStreamExt};
+use kube::runtime::watcher;
+use kube::{Api, Client, ResourceExt};
+use log::{debug, error, info, warn};
+use opentelemetry::KeyValue;
 use pgmq::PGMQueueExt;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
 
 use conductor::monitoring::CustomMetrics;
-use conductor::types::Event;
+use conductor::types::{Event, StatusChange};
 use conductor::{get_data_plane_id_from_coredb, get_org_inst_id, get_pg_conn, types};
 
-pub async fn run_status_reporter(
-    _metrics: CustomMetrics,
-) -> Result<(), Box<dyn std::error::Error>> {
+use crate::from_env_default;
+
+// How often pending updates are checked to see if they are ready to be sent
+const FLUSH_INTERVAL_MS: u64 = 250;
+
+// Latest observed state of a CoreDB which has not been reported yet
+struct PendingUpdate {
+    first_seen: Instant,
+    last_seen: Instant,
+    coredb: CoreDB,
+}
+
+impl PendingUpdate {
+    // Ready once the CoreDB has been quiet for the debounce period,
+    // or when it has been changing for longer than the max delay
+    fn is_ready(&self, now: Instant, debounce: Duration, max_delay: Duration) -> bool {
+        now.duration_since(self.last_seen) >= debounce
+            || now.duration_since(self.first_seen) >= max_delay
+    }
+}
+
+// State of a CoreDB as it was last reported to the control plane
+struct ReportedState {
+    fingerprint: serde_json::Value,
+    status: Option<CoreDBStatus>,
+}
+
+// Puts back an update which could not be sent, so it's retried once the debounce period
+// has passed again. A newer change of the CoreDB which arrived meanwhile takes precedence.
+fn requeue(
+    pending: &mut HashMap<String, PendingUpdate>,
+    key: String,
+    coredb: CoreDB,
+    now: Instant,
+) {
+    pending.entry(key).or_insert(PendingUpdate {
+        first_seen: now,
+        last_seen: now,
+        coredb,
+    });
+}
+
+// Forgets CoreDBs which were not listed when the watch restarted, since they were
+// deleted while it was down and no Delete event is coming for them
+fn forget_unlisted(
+    listed: &HashSet<String>,
+    pending: &mut HashMap<String, PendingUpdate>,
+    last_reported: &mut HashMap<String, ReportedState>,
+) {
+    pending.retain(|key, _| listed.contains(key));
+    last_reported.retain(|key, _| listed.contains(key));
+}
+
+fn fingerprint(coredb: &CoreDB) -> serde_json::Value {
+    serde_json::json!({
+        "spec": coredb.spec,
+        "status": coredb.status,
+        "annotations": coredb.metadata.annotations,
+    })
+}
+
+pub async fn run_status_reporter(metrics: CustomMetrics) -> Result<(), Box<dyn std::error::Error>> {
     // Move to config
     let pg_conn_url =
         env::var("POSTGRES_QUEUE_CONNECTION").expect("POSTGRES_QUEUE_CONNECTION must be set");
+    let debounce =
+        Duration::from_millis(from_env_default("STATUS_REPORTER_DEBOUNCE_MS", "2000").parse()?);
+    let max_delay =
+        Duration::from_millis(from_env_default("STATUS_REPORTER_MAX_DELAY_MS", "10000").parse()?);
     // Connect to pgmq
     let queue = PGMQueueExt::new(pg_conn_url.clone(), 1).await?;
 
     // Get a kubernetes watcher on all changes in coredb resources
     let client = Client::try_default().await?;
     let coredb_api: Api<CoreDB> = Api::all(client.clone());
+    let mut events = watcher(coredb_api, watcher::Config::default()).boxed();
 
-    watcher(coredb_api, watcher::Config::default())
-        .applied_objects()
-        .try_for_each(move |coredb| {
-            let client = client.clone();
-            let queue = queue.clone();
-            async move {
-                info!(
-                    "Detected change in coredb: {}",
-                    coredb
-                        .metadata
-                        .name
-                        .as_ref()
-                        .expect("CoreDB should always have a name")
-                );
-                match send_status_update(client, &queue, coredb).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error sending status update: {}", e);
+    // Both keyed on namespace/name
+    let mut pending: HashMap<String, PendingUpdate> = HashMap::new();
+    let mut last_reported: HashMap<String, ReportedState> = HashMap::new();
+    // CoreDBs listed since the watch (re)started, until the listing is done
+    let mut listed: Option<HashSet<String>> = None;
+
+    let mut flush_interval = interval(Duration::from_millis(FLUSH_INTERVAL_MS));
+
+    loop {
+        tokio::select! {
+            event = events.try_next() => {
+                match event? {
+                    Some(watcher::Event::Init) => listed = Some(HashSet::new()),
+                    Some(watcher::Event::InitDone) => {
+                        if let Some(listed) = listed.take() {
+                            forget_unlisted(&listed, &mut pending, &mut last_reported);
+                        }
+                    }
+                    Some(watcher::Event::Apply(coredb))
+                    | Some(watcher::Event::InitApply(coredb)) => {
+                        let key = coredb_key(&coredb);
+                        if let Some(listed) = listed.as_mut() {
+                            listed.insert(key.clone());
+                        }
+                        debug!("Detected change in coredb: {}", key);
+                        let now = Instant::now();
+                        match pending.get_mut(&key) {
+                            Some(update) => {
+                                update.last_seen = now;
+                                update.coredb = coredb;
+                            }
+                            None => {
+                                pending.insert(
+                                    key,
+                                    PendingUpdate {
+                                        first_seen: now,
+                                        last_seen: now,
+                                        coredb,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                    Some(watcher::Event::Delete(coredb)) => {
+                        let key = coredb_key(&coredb);
+                        pending.remove(&key);
+                        last_reported.remove(&key);
                     }
-                };
-                Ok(())
+                    None => return Ok(()),
+                }
             }
-        })
-        .await?;
-    Ok(())
+            _ = flush_interval.tick() => {
+                let now = Instant::now();
+                let ready: Vec<String> = pending
+                    .iter()
+                    .filter(|(_, update)| update.is_ready(now, debounce, max_delay))
+                    .map(|(key, _)| key.clone())
+                    .collect();
+
+                for key in ready {
+                    let Some(update) = pending.remove(&key) else {
+                        continue;
+                    };
+                    let current = ReportedState {
+                        fingerprint: fingerprint(&update.coredb),
+                        status: update.coredb.status.clone(),
+                    };
+                    let previous = last_reported.get(&key);
+                    let unchanged = previous
+                        .is_some_and(|previous| previous.fingerprint == current.fingerprint);
+                    if unchanged {
+                        debug!("No change in coredb {} since last report, skipping", key);
+                        metrics
+                            .conductor_status_updates
+                            .add(1, &[KeyValue::new("outcome", "suppressed")]);
+                        continue;
+                    }
+
+                    let changes = match previous {
+                        Some(previous) => classify_changes(previous, &current),
+                        None => Vec::new(),
+                    };
+                    info!("Reporting change in coredb {}: {:?}", key, changes);
+                    let sent =
+                        send_status_update(client.clone(), &queue, update.coredb.clone(), changes)
+                            .await;
+                    // Only a sent update counts as reported, others are retried
+                    match sent {
+                        Ok(true) => {
+                            metrics
+                                .conductor_status_updates
+                                .add(1, &[KeyValue::new("outcome", "sent")]);
+                            last_reported.insert(key, current);
+                        }
+                        Ok(false) => requeue(&mut pending, key, update.coredb, Instant::now()),
+                        Err(e) => {
+                            error!("Error sending status update: {}", e);
+                            requeue(&mut pending, key, update.coredb, Instant::now());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn coredb_key(coredb: &CoreDB) -> String {
+    format!(
+        "{}/{}",
+        coredb.namespace().unwrap_or_default(),
+        coredb.name_any()
+    )
+}
+
+// Describe what changed between two reported states. Falls back to
+// SpecChanged or Other when none of the typed changes apply.
+fn classify_changes(previous: &ReportedState, current: &ReportedState) -> Vec<StatusChange> {
+    let mut changes = Vec::new();
+
+    if let (Some(before), Some(after)) = (&previous.status, &current.status) {
+        classify_status_changes(before, after, &mut changes);
+    }
+
+    if previous.fingerprint["spec"] != current.fingerprint["spec"] {
+        changes.push(StatusChange::SpecChanged);
+    }
+
+    if changes.is_empty() {
+        changes.push(StatusChange::Other);
+    }
+    changes
+}
+
+fn classify_status_changes(
+    before: &CoreDBStatus,
+    after: &CoreDBStatus,
+    changes: &mut Vec<StatusChange>,
+) {
+    if before.running != after.running {
+        changes.push(if after.running {
+            StatusChange::Started
+        } else {
+            StatusChange::Stopped
+        });
+    }
+
+    for extension in after.extensions.iter().flatten() {
+        for location in &extension.locations {
+            if location.enabled != Some(true) {
+                continue;
+            }
+            let was_enabled = before.extensions.iter().flatten().any(|previous| {
+                previous.name == extension.name
+                    && previous.locations.iter().any(|previous_location| {
+                        previous_location.database == location.database
+                            && previous_location.enabled == Some(true)
+                    })
+            });
+            if !was_enabled {
+                changes.push(StatusChange::ExtensionEnabled {
+                    name: extension.name.clone(),
+                    database: location.database.clone(),
+                });
+            }
+        }
+    }
+
+    for install in after.trunk_installs.iter().flatten() {
+        let previous = before
+            .trunk_installs
+            .iter()
+            .flatten()
+            .find(|previous| previous.name == install.name);
+        if install.error {
+            if !previous.is_some_and(|previous| previous.error) {
+                changes.push(StatusChange::TrunkInstallFailed {
+                    name: install.name.clone(),
+                    error_message: install.error_message.clone(),
+                });
+            }
+        } else if !install.loading
+            && previous.is_none_or(|previous| {
+                previous.error || previous.loading || previous.version != install.version
+            })
+        {
+            changes.push(StatusChange::ExtensionInstalled {
+                name: install.name.clone(),
+                version: install.version.clone(),
+            });
+        }
+    }
+
+    if before.first_recoverability_time != after.first_recoverability_time {
+        changes.push(StatusChange::RecoverabilityTimeChanged {
+            first_recoverability_time: after.first_recoverability_time,
+        });
+    }
 }
 
 // Used for sending ad-hoc status updates to the control plane.
 // This can be triggered when a change in a CoreDB's status is detected.
+// Returns Ok(false) when the update was skipped.
 async fn send_status_update(
     client: Client,
     response_queue: &PGMQueueExt,
     coredb: CoreDB,
-) -> Result<(), ConductorError> {
+    changes: Vec<StatusChange>,
+) -> Result<bool, ConductorError> {
     let coredb_name = &coredb
         .metadata
         .name
@@ -73,21 +315,21 @@ async fn send_status_update(
         Ok(domain) => domain,
         Err(_) => {
             error!("DATA_PLANE_BASEDOMAIN is not set, skipping status update");
-            return Ok(());
+            return Ok(false);
         }
     };
     let data_plane_events_queue = match env::var("DATA_PLANE_EVENTS_QUEUE") {
         Ok(data_plane_events_queue) => data_plane_events_queue,
         Err(_) => {
             error!("DATA_PLANE_EVENTS_QUEUE is not set, skipping status update");
-            return Ok(());
+            return Ok(false);
         }
     };
     let org_inst = match get_org_inst_id(&coredb) {
         Ok(org_inst) => org_inst,
         Err(_) => {
             warn!("Could not get org_id and inst_id from CoreDB {}, needs to be updated with annotations, which will happen on the next update from control plane, skipping", coredb_name);
-            return Ok(());
+            return Ok(false);
         }
     };
 
@@ -95,7 +337,7 @@ async fn send_status_update(
         Ok(dp_id) => dp_id,
         Err(_) => {
             warn!("Could not get data_plane_id from CoreDB {}, needs to be updated with annotations, which will happen on the next update from control plane, skipping", coredb_name);
-            return Ok(());
+            return Ok(false);
         }
     };
 
@@ -104,7 +346,7 @@ async fn send_status_update(
         Ok(conn_info) => conn_info,
         Err(_) => {
             info!("Could not get connection info for CoreDB {}, skipping status update. This can be normal for a few seconds when the resource is initially created, and when the instance is being deleted.", coredb_name);
-            return Ok(());
+            return Ok(false);
         }
     };
     let response = types::StateToControlPlane {
@@ -115,6 +357,7 @@ async fn send_status_update(
         spec: Some(coredb.spec.clone()),
         status: coredb.status.clone(),
         connection: Some(conn_info),
+        changes,
     };
     let msg_id = response_queue
         .send(&data_plane_events_queue, &response)
@@ -123,5 +366,181 @@ async fn send_status_update(
         "{}.{}: Sent ad hoc update to control plane, message_id: {}",
         org_inst.org_id, org_inst.inst_id, msg_id
     );
-    Ok(())
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use controller::extensions::types::{
+        ExtensionInstallLocationStatus, ExtensionStatus, TrunkInstallStatus,
+    };
+
+    fn trunk_install(name: &str, error: bool, loading: bool) -> TrunkInstallStatus {
+        TrunkInstallStatus {
+            name: name.to_string(),
+            version: Some("1.0.0".to_string()),
+            error,
+            loading,
+            error_message: error.then(|| "failed".to_string()),
+            installed_to_pods: None,
+        }
+    }
+
+    fn enabled_extension(name: &str) -> ExtensionStatus {
+        ExtensionStatus {
+            name: name.to_string(),
+            description: None,
+            locations: vec![ExtensionInstallLocationStatus {
+                database: "postgres".to_string(),
+                schema: None,
+                version: None,
+                enabled: Some(true),
+                error: None,
+                error_message: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_pending_update_debounce() {
+        let start = Instant::now();
+        let update = PendingUpdate {
+            first_seen: start,
+            last_seen: start + Duration::from_secs(8),
+            coredb: CoreDB::new("test", Default::default()),
+        };
+        let debounce = Duration::from_secs(2);
+        let max_delay = Duration::from_secs(10);
+
+        assert!(!update.is_ready(start + Duration::from_secs(9), debounce, max_delay));
+        assert!(update.is_ready(start + Duration::from_secs(10), debounce, max_delay));
+    }
+
+    #[test]
+    fn test_requeue_keeps_newer_changes() {
+        let start = Instant::now();
+        let mut pending = HashMap::new();
+        requeue(
+            &mut pending,
+            "ns/test".to_string(),
+            CoreDB::new("test", Default::default()),
+            start,
+        );
+        let update = &pending["ns/test"];
+        // waits for the debounce period again rather than the max delay
+        assert!(!update.is_ready(start, Duration::from_secs(2), Duration::from_secs(10)));
+        assert!(update.is_ready(
+            start + Duration::from_secs(2),
+            Duration::from_secs(2),
+            Duration::from_secs(10)
+        ));
+
+        let mut newer = CoreDB::new("test", Default::default());
+        newer.spec.replicas = 2;
+        pending.get_mut("ns/test").unwrap().coredb = newer;
+        requeue(
+            &mut pending,
+            "ns/test".to_string(),
+            CoreDB::new("test", Default::default()),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(pending["ns/test"].coredb.spec.replicas, 2);
+    }
+
+    #[test]
+    fn test_forget_unlisted() {
+        let now = Instant::now();
+        let mut pending = HashMap::new();
+        let mut last_reported = HashMap::new();
+        for key in ["ns/kept", "ns/deleted"] {
+            requeue(
+                &mut pending,
+                key.to_string(),
+                CoreDB::new("test", Default::default()),
+                now,
+            );
+            last_reported.insert(
+                key.to_string(),
+                ReportedState {
+                    fingerprint: serde_json::Value::Null,
+                    status: None,
+                },
+            );
+        }
+
+        forget_unlisted(
+            &HashSet::from(["ns/kept".to_string()]),
+            &mut pending,
+            &mut last_reported,
+        );
+        assert_eq!(pending.keys().collect::<Vec<_>>(), vec!["ns/kept"]);
+        assert_eq!(last_reported.keys().collect::<Vec<_>>(), vec!["ns/kept"]);
+    }
+
+    #[test]
+    fn test_classify_status_changes() {
+        let before = CoreDBStatus {
+            running: true,
+            trunk_installs: Some(vec![
+                trunk_install("pg_partman", false, true),
+                trunk_install("pgmq", false, false),
+            ]),
+            ..CoreDBStatus::default()
+        };
+        let after = CoreDBStatus {
+            running: false,
+            extensions: Some(vec![enabled_extension("pg_partman")]),
+            trunk_installs: Some(vec![
+                trunk_install("pg_partman", false, false),
+                trunk_install("pgmq", false, false),
+                trunk_install("postgis", true, false),
+            ]),
+            first_recoverability_time: Some(Utc::now()),
+            ..CoreDBStatus::default()
+        };
+
+        let mut changes = Vec::new();
+        classify_status_changes(&before, &after, &mut changes);
+
+        assert_eq!(
+            changes,
+            vec![
+                StatusChange::Stopped,
+                StatusChange::ExtensionEnabled {
+                    name: "pg_partman".to_string(),
+                    database: "postgres".to_string(),
+                },
+                StatusChange::ExtensionInstalled {
+                    name: "pg_partman".to_string(),
+                    version: Some("1.0.0".to_string()),
+                },
+                StatusChange::TrunkInstallFailed {
+                    name: "postgis".to_string(),
+                    error_message: Some("failed".to_string()),
+                },
+                StatusChange::RecoverabilityTimeChanged {
+                    first_recoverability_time: after.first_recoverability_time,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify_changes_falls_back_to_other() {
+        let coredb = CoreDB::new("test", Default::default());
+        let previous = ReportedState {
+            fingerprint: fingerprint(&coredb),
+            status: Some(CoreDBStatus::default()),
+        };
+        let current = ReportedState {
+            fingerprint: serde_json::json!({ "spec": previous.fingerprint["spec"], "status": {} }),
+            status: Some(CoreDBStatus::default()),
+        };
+        assert_eq!(
+            classify_changes(&previous, &current),
+            vec![StatusChange::Other]
+        );
+    }
 }
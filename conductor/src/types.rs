@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::types;
@@ -14,6 +15,8 @@ pub struct CRUDevent {
     pub backups_read_path: Option<String>,
     pub backups_write_path: Option<String>,
     pub spec: Option<CoreDBSpec>,
+    /// sizing tier for the namespace quota and limits
+    pub tier: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -35,6 +38,27 @@ pub enum Event {
     Restored,
 }
 
+/// what conductor did with the last message it read for a namespace
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventOutcome {
+    Completed,
+    Requeued,
+    Archived,
+    Failed,
+}
+
+impl EventOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventOutcome::Completed => "completed",
+            EventOutcome::Requeued => "requeued",
+            EventOutcome::Archived => "archived",
+            EventOutcome::Failed => "failed",
+        }
+    }
+}
+
 /// message returned to control plane
 /// reports state of data plane
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,6 +70,35 @@ pub struct StateToControlPlane {
     pub spec: Option<CoreDBSpec>,
     pub status: Option<CoreDBStatus>,
     pub connection: Option<types::ConnectionInfo>,
+    /// classified changes since the last report, empty when not known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<StatusChange>,
+}
+
+/// a classified change between two reported states of an instance
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum StatusChange {
+    Started,
+    Stopped,
+    ExtensionEnabled {
+        name: String,
+        database: String,
+    },
+    ExtensionInstalled {
+        name: String,
+        version: Option<String>,
+    },
+    TrunkInstallFailed {
+        name: String,
+        error_message: Option<String>,
+    },
+    RecoverabilityTimeChanged {
+        first_recoverability_time: Option<DateTime<Utc>>,
+    },
+    SpecChanged,
+    // a status change which doesn't fit any of the above
+    Other,
 }
 
 #[derive(Debug)]
@@ -64,3 +117,54 @@ pub struct ConnectionInfo {
     pub app_user: String,
     pub app_password: String,
 }
+
+/// periodic snapshot of every instance found in the data plane,
+/// used by control plane to detect drift from its own records
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InventorySnapshot {
+    pub data_plane_id: String,
+    pub taken_at: DateTime<Utc>,
+    pub instances: Vec<InventoryInstance>,
+    pub orphans: Vec<Orphan>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InventoryInstance {
+    pub namespace: String,
+    pub org_id: Option<String>,
+    pub inst_id: Option<String>,
+    // namespace exists and carries the tembo.io/instance_id label
+    pub labelled_namespace: bool,
+    pub namespace_phase: Option<String>,
+    pub has_coredb: bool,
+    // CoreDB carries the tembo.io org and instance annotations
+    pub coredb_annotated: bool,
+    pub running: Option<bool>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Orphan {
+    pub namespace: String,
+    pub kind: OrphanKind,
+    pub action: OrphanAction,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OrphanKind {
+    // namespace or CoreDB still present after a completed Delete event
+    DeletedInstance,
+    // labelled namespace that has no CoreDB in it
+    NamespaceWithoutCoreDB,
+    // CoreDB without the annotations set by conductor
+    UnmanagedCoreDB,
+    // cloudformation stack left behind after a completed Delete event
+    CloudFormationStack,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OrphanAction {
+    Flagged,
+    Deleting,
+    DeleteFailed,
+}
@@ -157,6 +157,7 @@ mod test {
             namespace: namespace.clone(),
             backups_read_path: None,
             backups_write_path: None,
+            tier: None,
             data_plane_id: "aws_data_1_use1".to_owned(),
             org_id: "org_02s3owPQskuGXHE8vYsGSY".to_owned(),
             inst_id: "inst_02s4UKVbRy34SAYVSwZq2H".to_owned(),
@@ -256,6 +257,7 @@ mod test {
             namespace: namespace.clone(),
             backups_read_path: None,
             backups_write_path: None,
+            tier: None,
             data_plane_id: "aws_data_1_use1".to_owned(),
             org_id: "org_02s3owPQskuGXHE8vYsGSY".to_owned(),
             inst_id: "inst_02s4UKVbRy34SAYVSwZq2H".to_owned(),
@@ -331,6 +333,7 @@ mod test {
             namespace: namespace.clone(),
             backups_read_path: None,
             backups_write_path: None,
+            tier: None,
             data_plane_id: "aws_data_1_use1".to_owned(),
             org_id: "org_02s3owPQskuGXHE8vYsGSY".to_owned(),
             inst_id: "inst_02s4UKVbRy34SAYVSwZq2H".to_owned(),
@@ -382,6 +385,7 @@ mod test {
         let msg = types::CRUDevent {
             namespace: namespace.clone(),
             backups_write_path: None,
+            tier: None,
             backups_read_path: None,
             data_plane_id: "aws_data_1_use1".to_owned(),
             org_id: "org_02s3owPQskuGXHE8vYsGSY".to_owned(),
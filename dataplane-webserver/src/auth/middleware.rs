@@ -0,0 +1,69 @@
+use crate::auth::{Authorizer, Scope};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{web, Error, HttpMessage};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+/// Route middleware which requires a scope, e.g.
+/// `#[get("/secrets", wrap = "RequireScope(Scope::SecretsRead)")]`.
+///
+/// The app must have an [`Authorizer`] in its data, requests fail otherwise.
+pub struct RequireScope(pub Scope);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service: Rc::new(service),
+            scope: self.0,
+        }))
+    }
+}
+
+pub struct RequireScopeMiddleware<S> {
+    service: Rc<S>,
+    scope: Scope,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+        Box::pin(async move {
+            let result = match req.app_data::<web::Data<Authorizer>>().cloned() {
+                Some(authorizer) => authorizer.authorize(req.request(), scope).await,
+                None => Err(ErrorInternalServerError("Authorization is not configured")),
+            };
+            // Denials are responses, so they go through the other middleware like handler errors
+            match result {
+                Ok(principal) => {
+                    // Handlers which record the caller read it from the extensions
+                    req.extensions_mut().insert(principal);
+                    Ok(service.call(req).await?.map_into_left_body())
+                }
+                Err(e) => Ok(req.error_response(e).map_into_right_body()),
+            }
+        })
+    }
+}
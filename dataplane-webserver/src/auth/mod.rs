@@ -0,0 +1,291 @@
+use crate::backups::coredb::fetch_coredb;
+use crate::backups::find_instance_namespace;
+use crate::config::Config;
+use actix_web::error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized};
+use actix_web::{Error, HttpRequest};
+use controller::apis::coredb_types::CoreDB;
+use kube::Client as KubeClient;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod jwks;
+pub mod middleware;
+pub mod policy;
+
+pub use middleware::RequireScope;
+
+const ORGANIZATION_ID_ANNOTATION: &str = "tembo.io/organization_id";
+// Older instances use this annotation, the operator renames it on the Cluster
+const ORG_ID_ANNOTATION: &str = "tembo.io/org_id";
+const INSTANCE_ID_ANNOTATION: &str = "tembo.io/instance_id";
+
+/// An action a route performs, which the caller must be granted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    SecretsRead,
+    SecretsWrite,
+    BackupsRead,
+    BackupsWrite,
+    MetricsRead,
+    LogsRead,
+    SqlRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::SecretsRead => "secrets:read",
+            Scope::SecretsWrite => "secrets:write",
+            Scope::BackupsRead => "backups:read",
+            Scope::BackupsWrite => "backups:write",
+            Scope::MetricsRead => "metrics:read",
+            Scope::LogsRead => "logs:read",
+            Scope::SqlRead => "sql:read",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secrets:read" => Ok(Scope::SecretsRead),
+            "secrets:write" => Ok(Scope::SecretsWrite),
+            "backups:read" => Ok(Scope::BackupsRead),
+            "backups:write" => Ok(Scope::BackupsWrite),
+            "metrics:read" => Ok(Scope::MetricsRead),
+            "logs:read" => Ok(Scope::LogsRead),
+            "sql:read" => Ok(Scope::SqlRead),
+            _ => Err(format!("Unknown scope {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The caller of a request, as established from its bearer token
+#[derive(Clone, Debug, Deserialize)]
+pub struct Principal {
+    pub subject: String,
+    /// Organizations the caller may act on
+    pub organizations: HashSet<String>,
+    /// Instances the caller may act on, all instances of its organizations when not set
+    #[serde(default)]
+    pub instances: Option<HashSet<String>>,
+    pub scopes: HashSet<Scope>,
+}
+
+impl Principal {
+    fn allows(&self, org_id: &str, instance_id: &str) -> bool {
+        self.organizations.contains(org_id)
+            && self
+                .instances
+                .as_ref()
+                .is_none_or(|instances| instances.contains(instance_id))
+    }
+}
+
+/// Turns a bearer token into a principal, or rejects it
+pub trait TokenValidator: Send + Sync {
+    fn validate(&self, token: &str) -> Result<Principal, String>;
+}
+
+/// Checks requests have a scope, for an instance their token allows.
+///
+/// Without a validator, authorization is left to the ingress and every request is allowed.
+pub struct Authorizer {
+    validator: Option<Box<dyn TokenValidator>>,
+}
+
+impl Authorizer {
+    pub fn new(validator: Box<dyn TokenValidator>) -> Self {
+        Self {
+            validator: Some(validator),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { validator: None }
+    }
+
+    /// Builds the authorizer from a JWKS file or a policy file, whichever is configured
+    pub fn from_config(cfg: &Config) -> Result<Self, String> {
+        match (&cfg.auth_jwks_path, &cfg.auth_policy_path) {
+            (Some(_), Some(_)) => {
+                Err("Only one of AUTH_JWKS_PATH and AUTH_POLICY_PATH may be set".to_string())
+            }
+            (Some(path), None) => Ok(Self::new(Box::new(jwks::JwksValidator::from_file(
+                path,
+                cfg.auth_jwt_issuer.clone(),
+                cfg.auth_jwt_audience.clone(),
+            )?))),
+            (None, Some(path)) => Ok(Self::new(Box::new(policy::PolicyValidator::from_file(
+                path,
+            )?))),
+            (None, None) => Ok(Self::disabled()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.validator.is_some()
+    }
+
+    /// Checks the request's token grants the scope, for the instance in the request path.
+    ///
+    /// The instance is identified by the `org_id` and `instance_id` or the `namespace` path
+    /// segments, and its organization and instance are read from the CoreDB annotations.
+    pub async fn authorize(&self, req: &HttpRequest, scope: Scope) -> Result<Principal, Error> {
+        let Some(validator) = &self.validator else {
+            return Ok(Principal {
+                subject: "anonymous".to_string(),
+                organizations: HashSet::new(),
+                instances: None,
+                scopes: HashSet::new(),
+            });
+        };
+
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(|hv| hv.strip_prefix("Bearer "))
+            .ok_or_else(|| ErrorUnauthorized("Missing bearer token"))?;
+        let principal = validator.validate(token).map_err(|e| {
+            warn!("Rejected token for {}: {}", req.path(), e);
+            ErrorUnauthorized("Invalid token")
+        })?;
+
+        let deny = |reason: &str| {
+            warn!(
+                "Denied scope {} to {} on {}: {}",
+                scope,
+                principal.subject,
+                req.path(),
+                reason
+            );
+            ErrorForbidden(format!("Not authorized for scope {}", scope))
+        };
+        if !principal.scopes.contains(&scope) {
+            return Err(deny("scope not granted"));
+        }
+
+        let match_info = req.match_info();
+        let path_ids = match (match_info.get("org_id"), match_info.get("instance_id")) {
+            (Some(org_id), Some(instance_id)) => Some((org_id, instance_id)),
+            _ => None,
+        };
+        // Skip the Kubernetes lookups when the path already rules the caller out
+        if let Some((org_id, instance_id)) = path_ids {
+            if !principal.allows(org_id, instance_id) {
+                return Err(deny("organization or instance not granted"));
+            }
+        }
+
+        let kube_client = KubeClient::try_default().await.map_err(|e| {
+            ErrorInternalServerError(format!("Failed to create Kubernetes client: {}", e))
+        })?;
+        let namespace = match (path_ids, match_info.get("namespace")) {
+            (Some((org_id, instance_id)), _) => {
+                find_instance_namespace(&kube_client, org_id, instance_id)
+                    .await
+                    .map_err(|_| deny("instance not found"))?
+            }
+            (None, Some(namespace)) => namespace.to_string(),
+            (None, None) => return Err(deny("request has no instance")),
+        };
+        // Unknown namespaces are denied rather than not found, so they can't be probed
+        let coredb = fetch_coredb(&kube_client, &namespace)
+            .await
+            .map_err(|_| deny("instance not found"))?;
+        let (org_id, instance_id) =
+            coredb_ids(&coredb).ok_or_else(|| deny("instance has no organization annotations"))?;
+        if let Some(path_ids) = path_ids {
+            if path_ids != (org_id, instance_id) {
+                return Err(deny("instance annotations don't match the request"));
+            }
+        }
+        if !principal.allows(org_id, instance_id) {
+            return Err(deny("organization or instance not granted"));
+        }
+        Ok(principal)
+    }
+}
+
+// The organization and instance IDs from the CoreDB annotations
+fn coredb_ids(coredb: &CoreDB) -> Option<(&str, &str)> {
+    let annotations = coredb.metadata.annotations.as_ref()?;
+    let org_id = annotations
+        .get(ORGANIZATION_ID_ANNOTATION)
+        .or_else(|| annotations.get(ORG_ID_ANNOTATION))?;
+    let instance_id = annotations.get(INSTANCE_ID_ANNOTATION)?;
+    Some((org_id.as_str(), instance_id.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::ObjectMeta;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_scope_round_trip() {
+        for scope in [
+            Scope::SecretsRead,
+            Scope::SecretsWrite,
+            Scope::BackupsRead,
+            Scope::BackupsWrite,
+            Scope::MetricsRead,
+            Scope::LogsRead,
+            Scope::SqlRead,
+        ] {
+            assert_eq!(scope.as_str().parse::<Scope>(), Ok(scope));
+        }
+        assert!("secrets:delete".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn test_principal_allows() {
+        let mut principal = Principal {
+            subject: "user_1".to_string(),
+            organizations: HashSet::from(["org_1".to_string()]),
+            instances: None,
+            scopes: HashSet::from([Scope::MetricsRead]),
+        };
+        assert!(principal.allows("org_1", "inst_1"));
+        assert!(!principal.allows("org_2", "inst_1"));
+
+        principal.instances = Some(HashSet::from(["inst_2".to_string()]));
+        assert!(!principal.allows("org_1", "inst_1"));
+        assert!(principal.allows("org_1", "inst_2"));
+    }
+
+    #[test]
+    fn test_coredb_ids() {
+        let mut coredb = CoreDB::new("org-foo-inst-bar", Default::default());
+        assert_eq!(coredb_ids(&coredb), None);
+
+        coredb.metadata = ObjectMeta {
+            annotations: Some(BTreeMap::from([
+                (ORG_ID_ANNOTATION.to_string(), "org_1".to_string()),
+                (INSTANCE_ID_ANNOTATION.to_string(), "inst_1".to_string()),
+            ])),
+            ..ObjectMeta::default()
+        };
+        assert_eq!(coredb_ids(&coredb), Some(("org_1", "inst_1")));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::auth::{Principal, TokenValidator};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Maps static bearer tokens to principals, for tests and local development.
+///
+/// The policy file is JSON:
+/// ```json
+/// {"tokens": {"metrics-reader": {"subject": "ci", "organizations": ["org_1"], "scopes": ["metrics:read"]}}}
+/// ```
+#[derive(Deserialize)]
+pub struct PolicyValidator {
+    tokens: HashMap<String, Principal>,
+}
+
+impl PolicyValidator {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read policy file {}: {}", path, e))?;
+        contents.parse()
+    }
+}
+
+impl std::str::FromStr for PolicyValidator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|e| format!("Failed to parse policy: {}", e))
+    }
+}
+
+impl TokenValidator for PolicyValidator {
+    fn validate(&self, token: &str) -> Result<Principal, String> {
+        self.tokens
+            .get(token)
+            .cloned()
+            .ok_or_else(|| "Token is not in the policy".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+
+    #[test]
+    fn test_policy() {
+        let policy: PolicyValidator = r#"{"tokens": {"reader": {
+            "subject": "ci",
+            "organizations": ["org_1"],
+            "instances": ["inst_1"],
+            "scopes": ["metrics:read", "logs:read"]
+        }}}"#
+            .parse()
+            .unwrap();
+        let principal = policy.validate("reader").unwrap();
+        assert_eq!(principal.subject, "ci");
+        assert!(principal.allows("org_1", "inst_1"));
+        assert!(!principal.allows("org_1", "inst_2"));
+        assert!(principal.scopes.contains(&Scope::LogsRead));
+        assert!(policy.validate("writer").is_err());
+
+        let unknown_scope = r#"{"tokens": {"reader": {
+            "subject": "ci", "organizations": [], "scopes": ["secrets:delete"]
+        }}}"#;
+        assert!(unknown_scope.parse::<PolicyValidator>().is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use log::error;
 use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -9,6 +11,22 @@ pub struct Config {
     pub backup_uri_timeout: i32,
     pub temback_image: String,
     pub temback_version: String,
+    pub prometheus_max_range_sec: u64,
+    pub prometheus_min_step_sec: u64,
+    pub prometheus_max_series: usize,
+    pub prometheus_denied_functions: Vec<String>,
+    pub prometheus_queries_per_minute: u32,
+    pub loki_url: String,
+    pub loki_timeout_ms: u64,
+    pub loki_max_lines: u32,
+    pub postgres_connect_timeout_sec: u64,
+    pub sql_query_timeout_ms: u64,
+    pub sql_query_max_rows: u32,
+    pub sql_audit_log_path: Option<String>,
+    pub auth_jwks_path: Option<String>,
+    pub auth_policy_path: Option<String>,
+    pub auth_jwt_issuer: Option<String>,
+    pub auth_jwt_audience: Option<String>,
 }
 
 impl Default for Config {
@@ -45,6 +63,37 @@ impl Default for Config {
             },
             temback_image: from_env_default("TEMBACK_IMAGE", "quay.io/tembo/temback"),
             temback_version: from_env_default("TEMBACK_VERSION", "v0.3.0"),
+            // 31 days
+            prometheus_max_range_sec: from_env_parse("PROMETHEUS_MAX_RANGE_SEC", 2_678_400),
+            prometheus_min_step_sec: from_env_parse("PROMETHEUS_MIN_STEP_SEC", 15),
+            prometheus_max_series: from_env_parse("PROMETHEUS_MAX_SERIES", 1000),
+            prometheus_denied_functions: from_env_default(
+                "PROMETHEUS_DENIED_FUNCTIONS",
+                "count_values,holt_winters,predict_linear,quantile_over_time",
+            )
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect(),
+            // 0 disables rate limiting
+            prometheus_queries_per_minute: from_env_parse("PROMETHEUS_QUERIES_PER_MINUTE", 300),
+            // The default value is the service name in kubernetes
+            loki_url: from_env_default(
+                "LOKI_URL",
+                "http://loki-gateway.monitoring.svc.cluster.local",
+            ),
+            loki_timeout_ms: from_env_parse("LOKI_TIMEOUT_MS", 5000),
+            loki_max_lines: from_env_parse("LOKI_MAX_LINES", 5000),
+            postgres_connect_timeout_sec: from_env_parse("POSTGRES_CONNECT_TIMEOUT_SEC", 10),
+            sql_query_timeout_ms: from_env_parse("SQL_QUERY_TIMEOUT_MS", 10000),
+            sql_query_max_rows: from_env_parse("SQL_QUERY_MAX_ROWS", 1000),
+            // The SQL audit log is written to stdout when not set
+            sql_audit_log_path: env::var("SQL_AUDIT_LOG_PATH").ok(),
+            // Authorization is left to the ingress when neither file is set
+            auth_jwks_path: env::var("AUTH_JWKS_PATH").ok(),
+            auth_policy_path: env::var("AUTH_POLICY_PATH").ok(),
+            auth_jwt_issuer: env::var("AUTH_JWT_ISSUER").ok(),
+            auth_jwt_audience: env::var("AUTH_JWT_AUDIENCE").ok(),
         }
     }
 }
@@ -53,3 +102,24 @@ impl Default for Config {
 fn from_env_default(key: &str, default: &str) -> String {
     env::var(key).unwrap_or_else(|_| default.to_owned())
 }
+
+/// source and parse a variable from environment - use default if not exists or invalid
+fn from_env_parse<T>(key: &str, default: T) -> T
+where
+    T: FromStr + Display,
+    T::Err: Display,
+{
+    match env::var(key) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(n) => n,
+            Err(e) => {
+                error!(
+                    "Environment variable {} is invalid, using {}: {}",
+                    key, default, e
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
@@ -1,5 +1,9 @@
+pub mod auth;
 pub mod backups;
 pub mod config;
+pub mod logs;
 pub mod metrics;
+pub mod postgres;
 pub mod routes;
 pub mod secrets;
+pub mod sql;
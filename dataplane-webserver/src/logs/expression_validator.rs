@@ -0,0 +1,253 @@
+use actix_web::HttpResponse;
+use log::{error, info, warn};
+
+// https://grafana.com/docs/loki/latest/query/log_queries/#log-stream-selector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchOp {
+    Equal,
+    NotEqual,
+    Re,
+    NotRe,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+}
+
+struct Cursor<'a> {
+    chars: &'a [char],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    // Comments run to the end of the line
+    fn skip_comment(&mut self) {
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.pos += 1;
+        }
+    }
+
+    // Reads a "double quoted" or `raw` string. Escapes are kept as they are,
+    // so an escaped value never matches a namespace.
+    fn read_string(&mut self) -> Result<String, String> {
+        let quote = match self.peek() {
+            Some(c @ ('"' | '`')) => c,
+            _ => return Err("Expected a quoted label value".to_string()),
+        };
+        self.pos += 1;
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == quote {
+                return Ok(value);
+            }
+            value.push(c);
+            if c == '\\' && quote == '"' {
+                if let Some(escaped) = self.peek() {
+                    value.push(escaped);
+                    self.pos += 1;
+                }
+            }
+        }
+        Err("Unterminated string".to_string())
+    }
+
+    fn read_label_name(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("Expected a label name".to_string());
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn read_op(&mut self) -> Result<MatchOp, String> {
+        let op = match (self.peek(), self.chars.get(self.pos + 1)) {
+            (Some('='), Some('~')) => MatchOp::Re,
+            (Some('!'), Some('~')) => MatchOp::NotRe,
+            (Some('!'), Some('=')) => MatchOp::NotEqual,
+            (Some('='), _) => MatchOp::Equal,
+            _ => return Err("Expected a label matcher operator".to_string()),
+        };
+        self.pos += if op == MatchOp::Equal { 1 } else { 2 };
+        Ok(op)
+    }
+
+    // Reads the matchers of a stream selector, after its opening brace
+    fn read_selector(&mut self) -> Result<Vec<LabelMatcher>, String> {
+        let mut matchers = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some('}') && matchers.is_empty() {
+                self.pos += 1;
+                return Ok(matchers);
+            }
+            let name = self.read_label_name()?;
+            self.skip_whitespace();
+            let op = self.read_op()?;
+            self.skip_whitespace();
+            let value = self.read_string()?;
+            matchers.push(LabelMatcher { name, op, value });
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(matchers);
+                }
+                _ => return Err("Expected ',' or '}' in stream selector".to_string()),
+            }
+        }
+    }
+}
+
+// Finds every stream selector in a LogQL query. Log and metric queries can
+// only use braces for stream selectors, braces in line_format templates and
+// other strings are skipped.
+pub fn stream_selectors(query: &str) -> Result<Vec<Vec<LabelMatcher>>, String> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut cursor = Cursor {
+        chars: &chars,
+        pos: 0,
+    };
+    let mut selectors = vec![];
+    while let Some(c) = cursor.peek() {
+        match c {
+            '"' | '`' => {
+                cursor.read_string()?;
+            }
+            '#' => cursor.skip_comment(),
+            '{' => {
+                cursor.pos += 1;
+                selectors.push(cursor.read_selector()?);
+            }
+            '}' => return Err("Unbalanced braces".to_string()),
+            _ => cursor.pos += 1,
+        }
+    }
+    Ok(selectors)
+}
+
+// Same as the PromQL validator, every stream selector needs a namespace
+// label equal to the namespace in the path
+fn validate_stream_selector(namespace: &str, matchers: &[LabelMatcher]) -> bool {
+    matchers
+        .iter()
+        .any(|m| m.name == "namespace" && m.op == MatchOp::Equal && m.value == namespace)
+}
+
+// Returns the query if it's valid
+// otherwise returns an error in the form of HttpResponse
+#[allow(clippy::result_large_err)]
+pub fn check_query_only_accesses_namespace(
+    query: &str,
+    namespace: &str,
+) -> Result<String, HttpResponse> {
+    let selectors = match stream_selectors(query) {
+        Ok(selectors) => selectors,
+        Err(e) => {
+            error!("LogQL parse error: {}", e);
+            return Err(HttpResponse::UnprocessableEntity().json("Failed to parse LogQL query"));
+        }
+    };
+
+    if !selectors.is_empty()
+        && selectors
+            .iter()
+            .all(|matchers| validate_stream_selector(namespace, matchers))
+    {
+        info!(
+            "Authorized request: namespace '{}', query '{}'",
+            namespace, query
+        );
+        Ok(query.to_string())
+    } else {
+        warn!(
+            "Unauthorized request: namespace '{}', query '{}'",
+            namespace, query
+        );
+        Err(HttpResponse::Forbidden().json("Must include namespace in all stream selectors"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_selectors() {
+        let selectors = stream_selectors(
+            r#"{namespace="org-foo", app=~"post.*"} |= "error" | line_format "{{.msg}}""#,
+        )
+        .unwrap();
+        assert_eq!(
+            selectors,
+            vec![vec![
+                LabelMatcher {
+                    name: "namespace".to_string(),
+                    op: MatchOp::Equal,
+                    value: "org-foo".to_string(),
+                },
+                LabelMatcher {
+                    name: "app".to_string(),
+                    op: MatchOp::Re,
+                    value: "post.*".to_string(),
+                },
+            ]]
+        );
+
+        let selectors = stream_selectors(
+            "sum(count_over_time({namespace=`org-foo`}[5m])) / count_over_time({namespace!=\"x\"}[5m])",
+        )
+        .unwrap();
+        assert_eq!(selectors.len(), 2);
+        assert_eq!(selectors[1][0].op, MatchOp::NotEqual);
+
+        assert!(stream_selectors(r#"{namespace="org-foo""#).is_err());
+        assert!(stream_selectors(r#"{namespace="org-foo"}}"#).is_err());
+        assert!(stream_selectors(r#"{namespace=org-foo}"#).is_err());
+    }
+
+    #[test]
+    fn test_check_query_only_accesses_namespace() {
+        let check = |query: &str| check_query_only_accesses_namespace(query, "org-foo").is_ok();
+
+        assert!(check(r#"{namespace="org-foo"}"#));
+        assert!(check(
+            r#"rate({namespace="org-foo", pod="org-foo-1"} |= "}" | json [1m])"#
+        ));
+        assert!(check("{namespace=\"org-foo\"} # {namespace=\"org-bar\"}"));
+
+        assert!(!check(r#"{namespace="org-bar"}"#));
+        assert!(!check(r#"{namespace=~"org-foo"}"#));
+        assert!(!check(r#"{namespace="org-foo\"}"#));
+        assert!(!check(r#"{app="postgres"}"#));
+        assert!(!check(r#""{namespace=\"org-foo\"}""#));
+        assert!(!check(
+            r#"count_over_time({namespace="org-foo"}[1m]) + count_over_time({namespace="org-bar"}[1m])"#
+        ));
+        // a quote in a comment doesn't hide the selectors after it
+        assert!(!check(
+            "count_over_time({namespace=\"org-foo\"}[1m]) # \"\n+ count_over_time({namespace=\"org-bar\"}[1m]) # \""
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::config::Config;
+use crate::logs::types::{LogInstantQuery, LogRangeQuery, TailQuery};
+use actix_web::web::{Data, Query};
+use actix_web::HttpResponse;
+use futures::StreamExt;
+use log::error;
+use reqwest::{Client, Response};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message as LokiMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+pub mod expression_validator;
+pub mod types;
+
+pub type LokiStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Loki's default, when the request doesn't set a limit
+const DEFAULT_LIMIT: u32 = 100;
+
+async fn loki_response(response: Response) -> HttpResponse {
+    let status_code = response.status();
+    // Loki errors are plain text
+    match status_code.as_u16() {
+        200 => match response.json::<Value>().await {
+            Ok(json_response) => HttpResponse::Ok().json(json_response),
+            Err(e) => {
+                error!("Failed to parse Loki response: {}", e);
+                HttpResponse::InternalServerError().json("Failed to parse Loki response")
+            }
+        },
+        400 => {
+            let body = response.text().await.unwrap_or_default();
+            HttpResponse::BadRequest().json(format!(
+                "Loki reported the query is malformed: {}",
+                body.trim()
+            ))
+        }
+        429 => HttpResponse::TooManyRequests().json("Loki is rate limiting queries"),
+        504 | 503 => HttpResponse::GatewayTimeout().json("Loki timeout"),
+        _ => {
+            let body = response.text().await.unwrap_or_default();
+            error!("{:?}: {:?}", status_code, body);
+            HttpResponse::InternalServerError()
+                .json(format!("Unexpected response from Loki: {}", status_code))
+        }
+    }
+}
+
+// Parameters shared by instant and range queries
+#[allow(clippy::result_large_err)]
+fn common_params(
+    cfg: &Config,
+    query: String,
+    limit: Option<u32>,
+    direction: Option<&str>,
+) -> Result<Vec<(&'static str, String)>, HttpResponse> {
+    let mut params = vec![
+        ("query", query),
+        (
+            "limit",
+            limit
+                .unwrap_or(DEFAULT_LIMIT)
+                .min(cfg.loki_max_lines)
+                .to_string(),
+        ),
+    ];
+    match direction {
+        Some(direction @ ("forward" | "backward")) => {
+            params.push(("direction", direction.to_string()))
+        }
+        Some(_) => {
+            return Err(HttpResponse::BadRequest().json("Direction must be forward or backward"))
+        }
+        None => (),
+    }
+    Ok(params)
+}
+
+async fn send_loki_query(
+    cfg: &Config,
+    http_client: &Client,
+    path: &str,
+    params: &[(&'static str, String)],
+) -> HttpResponse {
+    let query_url = format!("{}{}", cfg.loki_url.trim_end_matches('/'), path);
+    let response = http_client
+        .get(&query_url)
+        .query(params)
+        .timeout(Duration::from_millis(cfg.loki_timeout_ms))
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => loki_response(response).await,
+        Err(e) => {
+            error!("Failed to query Loki: {}", e);
+            HttpResponse::GatewayTimeout().json("Failed to query Loki")
+        }
+    }
+}
+
+pub async fn query_loki_instant(
+    cfg: Data<Config>,
+    http_client: Data<Client>,
+    instant_query: Query<LogInstantQuery>,
+    namespace: String,
+) -> HttpResponse {
+    let query = match expression_validator::check_query_only_accesses_namespace(
+        &instant_query.query,
+        &namespace,
+    ) {
+        Ok(value) => value,
+        Err(http_response) => return http_response,
+    };
+
+    let mut params = match common_params(
+        &cfg,
+        query,
+        instant_query.limit,
+        instant_query.direction.as_deref(),
+    ) {
+        Ok(params) => params,
+        Err(http_response) => return http_response,
+    };
+    if let Some(time) = &instant_query.time {
+        params.push(("time", time.clone()));
+    }
+
+    send_loki_query(&cfg, &http_client, "/loki/api/v1/query", &params).await
+}
+
+pub async fn query_loki_range(
+    cfg: Data<Config>,
+    http_client: Data<Client>,
+    range_query: Query<LogRangeQuery>,
+    namespace: String,
+) -> HttpResponse {
+    let query = match expression_validator::check_query_only_accesses_namespace(
+        &range_query.query,
+        &namespace,
+    ) {
+        Ok(value) => value,
+        Err(http_response) => return http_response,
+    };
+
+    let mut params = match common_params(
+        &cfg,
+        query,
+        range_query.limit,
+        range_query.direction.as_deref(),
+    ) {
+        Ok(params) => params,
+        Err(http_response) => return http_response,
+    };
+    for (name, value) in [
+        ("start", &range_query.start),
+        ("end", &range_query.end),
+        ("step", &range_query.step),
+    ] {
+        if let Some(value) = value {
+            params.push((name, value.clone()));
+        }
+    }
+
+    send_loki_query(&cfg, &http_client, "/loki/api/v1/query_range", &params).await
+}
+
+fn websocket_url(http_url: &str) -> String {
+    if let Some(rest) = http_url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = http_url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        http_url.to_string()
+    }
+}
+
+// Opens a tail of the query on Loki, the query must already be validated
+pub async fn connect_loki_tail(
+    cfg: &Config,
+    tail_query: &TailQuery,
+    query: String,
+) -> Result<LokiStream, HttpResponse> {
+    let mut params = vec![
+        ("query", query),
+        (
+            "limit",
+            tail_query
+                .limit
+                .unwrap_or(DEFAULT_LIMIT)
+                .min(cfg.loki_max_lines)
+                .to_string(),
+        ),
+    ];
+    if let Some(start) = &tail_query.start {
+        params.push(("start", start.clone()));
+    }
+    if let Some(delay_for) = tail_query.delay_for {
+        // Loki allows at most 5 seconds
+        params.push(("delay_for", delay_for.min(5).to_string()));
+    }
+
+    let base_url = websocket_url(cfg.loki_url.trim_end_matches('/'));
+    let tail_url =
+        match reqwest::Url::parse_with_params(&format!("{}/loki/api/v1/tail", base_url), &params) {
+            Ok(url) => url,
+            Err(e) => {
+                error!("Invalid Loki tail URL: {}", e);
+                return Err(HttpResponse::InternalServerError().json("Invalid Loki URL"));
+            }
+        };
+
+    let connect = tokio_tungstenite::connect_async(tail_url.as_str());
+    match tokio::time::timeout(Duration::from_millis(cfg.loki_timeout_ms), connect).await {
+        Ok(Ok((loki_stream, _))) => Ok(loki_stream),
+        Ok(Err(e)) => {
+            error!("Failed to tail Loki: {}", e);
+            Err(HttpResponse::BadGateway().json("Failed to tail Loki"))
+        }
+        Err(_) => Err(HttpResponse::GatewayTimeout().json("Loki timeout")),
+    }
+}
+
+// Relays log lines from the Loki tail to the client until either side closes
+pub async fn relay_tail(
+    mut session: actix_ws::Session,
+    mut client_stream: actix_ws::MessageStream,
+    mut loki_stream: LokiStream,
+) {
+    loop {
+        tokio::select! {
+            message = loki_stream.next() => match message {
+                Some(Ok(LokiMessage::Text(text))) => {
+                    if session.text(text.as_str().to_owned()).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(LokiMessage::Close(_))) | None => break,
+                Some(Ok(_)) => (),
+                Some(Err(e)) => {
+                    error!("Loki tail failed: {}", e);
+                    break;
+                }
+            },
+            message = client_stream.next() => match message {
+                Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+    let _ = loki_stream.close(None).await;
+    let _ = session.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websocket_url() {
+        assert_eq!(
+            websocket_url("http://loki-gateway.monitoring.svc.cluster.local"),
+            "ws://loki-gateway.monitoring.svc.cluster.local"
+        );
+        assert_eq!(websocket_url("https://loki:3100"), "wss://loki:3100");
+    }
+
+    #[test]
+    fn test_common_params() {
+        let cfg = Config {
+            loki_max_lines: 1000,
+            ..Config::default()
+        };
+        let params = common_params(&cfg, "{}".to_string(), Some(5000), Some("forward")).unwrap();
+        assert_eq!(
+            params,
+            vec![
+                ("query", "{}".to_string()),
+                ("limit", "1000".to_string()),
+                ("direction", "forward".to_string()),
+            ]
+        );
+        assert!(common_params(&cfg, "{}".to_string(), None, Some("sideways")).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use serde::Deserialize;
+
+// Times are passed through to Loki, which accepts unix nanoseconds, unix
+// seconds or RFC3339
+#[derive(Deserialize, Clone)]
+pub struct LogRangeQuery {
+    pub query: String,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub step: Option<String>,
+    pub limit: Option<u32>,
+    pub direction: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LogInstantQuery {
+    pub query: String,
+    pub time: Option<String>,
+    pub limit: Option<u32>,
+    pub direction: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TailQuery {
+    pub query: String,
+    pub start: Option<String>,
+    pub limit: Option<u32>,
+    pub delay_for: Option<u32>,
+}
@@ -2,15 +2,26 @@ use actix_web::{middleware, web, App, HttpServer};
 
 use actix_cors::Cors;
 
-use dataplane_webserver::secrets::types::{AvailableSecret, PasswordString};
+use dataplane_webserver::auth::Authorizer;
+use dataplane_webserver::metrics::limits::QueryRateLimiter;
+use dataplane_webserver::metrics::types::{
+    MetricSample, MetricSeries, NamedMetric, NamedMetricResult,
+};
+use dataplane_webserver::secrets::types::{
+    AvailableSecret, PasswordString, RotatePasswordRequest, RotatePasswordResponse,
+};
+use dataplane_webserver::sql::audit::SqlAuditLog;
+use dataplane_webserver::sql::types::{
+    NamedSqlQuery, NamedSqlQueryRequest, SqlQueryRequest, SqlQueryResult,
+};
 use dataplane_webserver::{
     config,
     routes::health::{lively, ready},
     routes::root,
 };
-use log::info;
+use log::{info, warn};
 
-use dataplane_webserver::routes::{backups, metrics, secrets};
+use dataplane_webserver::routes::{backups, logs, metrics, secrets, sql};
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_redoc::{Redoc, Servable};
@@ -32,6 +43,15 @@ async fn main() -> std::io::Result<()> {
     let http_client = reqwest::Client::builder()
         .build()
         .expect("Failed to create HTTP client");
+    // Shared by all workers, so the limit is per namespace across the server
+    let rate_limiter = web::Data::new(QueryRateLimiter::new(cfg.prometheus_queries_per_minute));
+    let authorizer =
+        web::Data::new(Authorizer::from_config(&cfg).expect("Failed to configure authorization"));
+    let sql_audit_log =
+        web::Data::new(SqlAuditLog::from_config(&cfg).expect("Failed to open the SQL audit log"));
+    if !authorizer.is_enabled() {
+        warn!("Neither AUTH_JWKS_PATH nor AUTH_POLICY_PATH is set, authorization is left to the ingress");
+    }
 
     #[derive(OpenApi)]
     #[openapi(
@@ -39,12 +59,31 @@ async fn main() -> std::io::Result<()> {
               secrets::get_secret_v1,
               secrets::get_secret_names_v1,
               secrets::update_postgres_password,
+              secrets::rotate_role_password,
+              sql::query,
+              sql::list_named,
+              sql::run_named,
               metrics::query_range,
               metrics::query,
+              metrics::list_named,
+              metrics::named,
+              logs::query_range,
+              logs::query,
+              logs::tail,
         ),
         components(schemas(
             AvailableSecret,
-            PasswordString
+            PasswordString,
+            RotatePasswordRequest,
+            RotatePasswordResponse,
+            SqlQueryRequest,
+            NamedSqlQueryRequest,
+            NamedSqlQuery,
+            SqlQueryResult,
+            NamedMetric,
+            NamedMetricResult,
+            MetricSeries,
+            MetricSample
         )),
         modifiers(&SecurityAddon),
         security(("jwt_token" = [])),
@@ -93,6 +132,9 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(web::Data::new(cfg.clone()))
             .app_data(web::Data::new(http_client.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(authorizer.clone())
+            .app_data(sql_audit_log.clone())
             .wrap(cors)
             .wrap(middleware::Logger::default())
             .service(web::scope("/").service(root::ok))
@@ -101,13 +143,25 @@ async fn main() -> std::io::Result<()> {
                     .service(secrets::get_secret_names_v1)
                     .service(secrets::get_secret_v1)
                     .service(secrets::update_postgres_password)
+                    .service(secrets::rotate_role_password)
                     .service(backups::trigger_instance_backup)
                     .service(backups::get_backup_status)
+                    .service(sql::query)
+                    .service(sql::list_named)
+                    .service(sql::run_named)
             )
             .service(
                 web::scope("/{namespace}/metrics")
                     .service(metrics::query_range)
-                    .service(metrics::query),
+                    .service(metrics::query)
+                    .service(metrics::list_named)
+                    .service(metrics::named),
+            )
+            .service(
+                web::scope("/{namespace}/logs")
+                    .service(logs::query_range)
+                    .service(logs::query)
+                    .service(logs::tail),
             )
             .service(web::scope("/health").service(ready).service(lively))
             .service(SwaggerUi::new("/swagger-ui/{_:.*}").urls(vec![(
@@ -6,8 +6,9 @@ use crate::metrics::types::RangeQuery;
 use actix_web::web::Query;
 use actix_web::HttpResponse;
 use log::{error, info, warn};
-use promql_parser::label::MatchOp;
+use promql_parser::label::{MatchOp, METRIC_NAME};
 use promql_parser::parser;
+use std::time::Duration;
 
 // https://prometheus.io/docs/prometheus/latest/querying/api/
 pub struct NamespaceVisitor {
@@ -89,6 +90,7 @@ impl ExprVisitor for NamespaceVisitor {
 
 // Returns the query if it's valid
 // otherwise returns an error in the form of HttpResponse
+#[allow(clippy::result_large_err)]
 pub fn check_query_only_accesses_namespace<T: PromQuery>(
     query: &Query<T>,
     namespace: &String,
@@ -132,3 +134,141 @@ pub fn check_query_only_accesses_namespace<T: PromQuery>(
     }
     Ok(query_str.to_string())
 }
+
+// This checks that prometheus queries aren't too expensive to run
+pub struct CostVisitor<'a> {
+    pub denied_functions: &'a [String],
+    pub max_range: Duration,
+}
+
+// Selectors need a metric name, a regex on the name or no name at all selects
+// every series in the namespace
+fn validate_selector_cardinality(vector_selector: &VectorSelector) -> Result<(), String> {
+    let mut has_name = vector_selector.name.is_some();
+    for matcher in &vector_selector.matchers.matchers {
+        if matcher.name != METRIC_NAME {
+            continue;
+        }
+        match matcher.op {
+            MatchOp::Equal => has_name = true,
+            MatchOp::Re(_) | MatchOp::NotRe(_) => {
+                return Err("Regex matchers on the metric name are not allowed".to_string())
+            }
+            _ => (),
+        }
+    }
+    if has_name {
+        Ok(())
+    } else {
+        Err("All vector selectors must include a metric name".to_string())
+    }
+}
+
+impl CostVisitor<'_> {
+    fn validate_range(&self, range: Duration) -> Result<(), String> {
+        if range > self.max_range {
+            return Err(format!(
+                "Range selectors and subqueries can look back at most {}s",
+                self.max_range.as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ExprVisitor for CostVisitor<'_> {
+    type Error = String;
+
+    fn pre_visit(&mut self, expr: &Expr) -> Result<bool, Self::Error> {
+        match expr {
+            Expr::VectorSelector(vector_selector) => {
+                validate_selector_cardinality(vector_selector)?;
+            }
+            Expr::MatrixSelector(matrix_selector) => {
+                validate_selector_cardinality(&matrix_selector.vs)?;
+                self.validate_range(matrix_selector.range)?;
+            }
+            Expr::Subquery(subquery) => {
+                self.validate_range(subquery.range)?;
+            }
+            Expr::Aggregate(aggregate) => {
+                let op = aggregate.op.to_string();
+                if self.denied_functions.contains(&op) {
+                    return Err(format!("The aggregation {} is not allowed", op));
+                }
+            }
+            Expr::Call(call) => {
+                if self.denied_functions.iter().any(|f| f == call.func.name) {
+                    return Err(format!("The function {} is not allowed", call.func.name));
+                }
+                // walk_expr doesn't descend into function arguments
+                for arg in &call.args.args {
+                    walk_expr(self, arg)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(true)
+    }
+}
+
+// Returns an error in the form of HttpResponse if the query uses a denied
+// function, a high cardinality selector or looks back further than max_range
+#[allow(clippy::result_large_err)]
+pub fn check_query_cost(
+    query_str: &str,
+    denied_functions: &[String],
+    max_range: Duration,
+) -> Result<(), HttpResponse> {
+    let abstract_syntax_tree = match parser::parse(query_str) {
+        Ok(ast) => ast,
+        Err(e) => {
+            error!("Query parse error: {}", e);
+            return Err(HttpResponse::UnprocessableEntity().json("Failed to parse PromQL query"));
+        }
+    };
+
+    let mut visitor = CostVisitor {
+        denied_functions,
+        max_range,
+    };
+    if let Err(e) = walk_expr(&mut visitor, &abstract_syntax_tree) {
+        warn!("Denied expensive query '{}': {}", query_str, e);
+        return Err(HttpResponse::BadRequest().json(e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::result_large_err)]
+    fn check(query: &str) -> Result<(), HttpResponse> {
+        let denied_functions = vec!["count_values".to_string(), "holt_winters".to_string()];
+        check_query_cost(query, &denied_functions, Duration::from_secs(86400))
+    }
+
+    #[test]
+    fn test_check_query_cost_allows() {
+        assert!(check("sum(rate(http_requests_total{namespace=\"org-foo\"}[5m]))").is_ok());
+        assert!(check("cnpg_backends_total{namespace=\"org-foo\", pod=~\"org-foo-.*\"}").is_ok());
+        assert!(check("{__name__=\"up\", namespace=\"org-foo\"}").is_ok());
+        assert!(check("max_over_time(up{namespace=\"org-foo\"}[1d])").is_ok());
+    }
+
+    #[test]
+    fn test_check_query_cost_denies() {
+        // denied functions, also when nested
+        assert!(check("count_values(\"v\", up{namespace=\"org-foo\"})").is_err());
+        assert!(check("holt_winters(up{namespace=\"org-foo\"}[1h], 0.5, 0.5)").is_err());
+        assert!(check("abs(holt_winters(up{namespace=\"org-foo\"}[1h], 0.5, 0.5))").is_err());
+        // high cardinality selectors
+        assert!(check("{namespace=\"org-foo\"}").is_err());
+        assert!(check("{__name__=~\".+\", namespace=\"org-foo\"}").is_err());
+        assert!(check("rate({__name__=~\"pg_.*\", namespace=\"org-foo\"}[5m])").is_err());
+        // look back too far
+        assert!(check("max_over_time(up{namespace=\"org-foo\"}[2d])").is_err());
+        assert!(check("max_over_time(up{namespace=\"org-foo\"}[2d:5m])").is_err());
+    }
+}
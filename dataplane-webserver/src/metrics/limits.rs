@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Stale windows are dropped once this many namespaces are tracked
+const MAX_TRACKED_NAMESPACES: usize = 1024;
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+// Limits how many metrics queries each namespace can make per minute, so one
+// tenant's dashboards can't overload Prometheus
+#[derive(Debug)]
+pub struct QueryRateLimiter {
+    queries_per_minute: u32,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl QueryRateLimiter {
+    // 0 queries per minute disables the limit
+    pub fn new(queries_per_minute: u32) -> Self {
+        Self {
+            queries_per_minute,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Counts a query, returns the seconds until the namespace can query again
+    // when it's over the limit
+    pub fn check(&self, namespace: &str) -> Option<u64> {
+        self.check_at(namespace, Instant::now())
+    }
+
+    fn check_at(&self, namespace: &str, now: Instant) -> Option<u64> {
+        if self.queries_per_minute == 0 {
+            return None;
+        }
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_TRACKED_NAMESPACES && !windows.contains_key(namespace) {
+            windows.retain(|_, window| now.duration_since(window.started) < RATE_WINDOW);
+        }
+
+        let window = windows.entry(namespace.to_string()).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        let elapsed = now.duration_since(window.started);
+        if elapsed >= RATE_WINDOW {
+            window.started = now;
+            window.count = 0;
+        }
+        if window.count >= self.queries_per_minute {
+            return Some((RATE_WINDOW - elapsed.min(RATE_WINDOW)).as_secs().max(1));
+        }
+        window.count += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_per_namespace() {
+        let limiter = QueryRateLimiter::new(2);
+        let now = Instant::now();
+        assert_eq!(limiter.check_at("org-foo", now), None);
+        assert_eq!(limiter.check_at("org-foo", now), None);
+        assert_eq!(limiter.check_at("org-foo", now), Some(60));
+        assert_eq!(
+            limiter.check_at("org-foo", now + Duration::from_secs(45)),
+            Some(15)
+        );
+        // other namespaces have their own window
+        assert_eq!(limiter.check_at("org-bar", now), None);
+        // the window resets after a minute
+        assert_eq!(
+            limiter.check_at("org-foo", now + Duration::from_secs(60)),
+            None
+        );
+
+        let unlimited = QueryRateLimiter::new(0);
+        for _ in 0..10 {
+            assert_eq!(unlimited.check_at("org-foo", now), None);
+        }
+    }
+}
@@ -1,44 +1,83 @@
 use crate::config::Config;
+use crate::metrics::limits::QueryRateLimiter;
 use crate::metrics::types::{InstantQuery, RangeQuery};
 use actix_web::web::{Data, Query};
 use actix_web::HttpResponse;
-use log::error;
+use log::{error, warn};
 use reqwest::{Client, Response};
 use serde_json::Value;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 pub mod expression_validator;
+pub mod limits;
+pub mod named;
 pub mod types;
 
-async fn prometheus_response(response: Response) -> HttpResponse {
+// Rate limits the namespace and denies queries which are too expensive for
+// Prometheus, returns an error in the form of HttpResponse
+#[allow(clippy::result_large_err)]
+fn check_query_limits(
+    cfg: &Config,
+    rate_limiter: &QueryRateLimiter,
+    query: &str,
+    namespace: &str,
+) -> Result<(), HttpResponse> {
+    if let Some(retry_after) = rate_limiter.check(namespace) {
+        warn!("Rate limited metrics query for namespace '{}'", namespace);
+        return Err(HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json("Too many metrics queries, please retry later"));
+    }
+    expression_validator::check_query_cost(
+        query,
+        &cfg.prometheus_denied_functions,
+        Duration::from_secs(cfg.prometheus_max_range_sec),
+    )
+}
+
+async fn prometheus_result(response: Response, max_series: usize) -> Result<Value, HttpResponse> {
     let status_code = response.status();
     let json_response: Value = match response.json().await {
         Ok(response) => response,
         Err(e) => {
             error!("Failed to parse Prometheus response: {}", e);
-            return HttpResponse::InternalServerError().json("Failed to parse Prometheus response");
+            return Err(
+                HttpResponse::InternalServerError().json("Failed to parse Prometheus response")
+            );
         }
     };
 
     match status_code.as_u16() {
-        200 => HttpResponse::Ok().json(json_response),
-        400 => HttpResponse::BadRequest().json("Prometheus reported the query is malformed"),
-        504 | 503 => HttpResponse::GatewayTimeout().json("Prometheus timeout"),
+        200 => {
+            let result_type = json_response["data"]["resultType"].as_str();
+            let series = json_response["data"]["result"].as_array().map(Vec::len);
+            match (result_type, series) {
+                (Some("matrix" | "vector"), Some(series)) if series > max_series => {
+                    Err(HttpResponse::BadRequest().json(format!(
+                        "Query returned more than {} series. Please aggregate or filter the query.",
+                        max_series
+                    )))
+                }
+                _ => Ok(json_response),
+            }
+        }
+        400 => Err(HttpResponse::BadRequest().json("Prometheus reported the query is malformed")),
+        504 | 503 => Err(HttpResponse::GatewayTimeout().json("Prometheus timeout")),
         422 => {
             if json_response["error"]
                 .to_string()
                 .contains("context deadline exceeded")
             {
-                HttpResponse::GatewayTimeout().json("Prometheus timeout")
+                Err(HttpResponse::GatewayTimeout().json("Prometheus timeout"))
             } else {
-                HttpResponse::BadRequest().json("Expression cannot be executed on Prometheus")
+                Err(HttpResponse::BadRequest().json("Expression cannot be executed on Prometheus"))
             }
         }
         _ => {
             error!("{:?}: {:?}", status_code, &json_response);
-            HttpResponse::InternalServerError().json(format!(
+            Err(HttpResponse::InternalServerError().json(format!(
                 "Unexpected response from Prometheus: {}",
                 status_code
-            ))
+            )))
         }
     }
 }
@@ -46,15 +85,27 @@ async fn prometheus_response(response: Response) -> HttpResponse {
 pub async fn query_prometheus_instant(
     cfg: Data<Config>,
     http_client: Data<Client>,
+    rate_limiter: Data<QueryRateLimiter>,
     instant_query: Query<InstantQuery>,
     namespace: String,
 ) -> HttpResponse {
+    match fetch_prometheus_instant(cfg, http_client, rate_limiter, instant_query, namespace).await {
+        Ok(json_response) => HttpResponse::Ok().json(json_response),
+        Err(http_response) => http_response,
+    }
+}
+
+// Validates and runs an instant query, returns the Prometheus response body
+pub async fn fetch_prometheus_instant(
+    cfg: Data<Config>,
+    http_client: Data<Client>,
+    rate_limiter: Data<QueryRateLimiter>,
+    instant_query: Query<InstantQuery>,
+    namespace: String,
+) -> Result<Value, HttpResponse> {
     let query =
-        match expression_validator::check_query_only_accesses_namespace(&instant_query, &namespace)
-        {
-            Ok(value) => value,
-            Err(http_response) => return http_response,
-        };
+        expression_validator::check_query_only_accesses_namespace(&instant_query, &namespace)?;
+    check_query_limits(&cfg, &rate_limiter, &query, &namespace)?;
 
     let time = instant_query.time.unwrap_or_else(|| {
         SystemTime::now()
@@ -65,10 +116,14 @@ pub async fn query_prometheus_instant(
 
     let timeout = format!("{}ms", cfg.prometheus_timeout_ms);
     let query_url = format!("{}/api/v1/query", cfg.prometheus_url.trim_end_matches('/'));
+    // Prometheus stops returning series past the limit, one extra tells us
+    // the limit was exceeded
+    let limit = (cfg.prometheus_max_series + 1).to_string();
     let query_params = [
         ("query", &query),
         ("time", &time.to_string()),
         ("timeout", &timeout),
+        ("limit", &limit),
     ];
 
     let response = http_client
@@ -81,10 +136,10 @@ pub async fn query_prometheus_instant(
         .await;
 
     match response {
-        Ok(response) => prometheus_response(response).await,
+        Ok(response) => prometheus_result(response, cfg.prometheus_max_series).await,
         Err(e) => {
             error!("Failed to query Prometheus: {}", e);
-            HttpResponse::GatewayTimeout().json("Failed to query Prometheus")
+            Err(HttpResponse::GatewayTimeout().json("Failed to query Prometheus"))
         }
     }
 }
@@ -92,14 +147,27 @@ pub async fn query_prometheus_instant(
 pub async fn query_prometheus(
     cfg: Data<Config>,
     http_client: Data<Client>,
+    rate_limiter: Data<QueryRateLimiter>,
     range_query: Query<RangeQuery>,
     namespace: String,
 ) -> HttpResponse {
+    match fetch_prometheus_range(cfg, http_client, rate_limiter, range_query, namespace).await {
+        Ok(json_response) => HttpResponse::Ok().json(json_response),
+        Err(http_response) => http_response,
+    }
+}
+
+// Validates and runs a range query, returns the Prometheus response body
+pub async fn fetch_prometheus_range(
+    cfg: Data<Config>,
+    http_client: Data<Client>,
+    rate_limiter: Data<QueryRateLimiter>,
+    range_query: Query<RangeQuery>,
+    namespace: String,
+) -> Result<Value, HttpResponse> {
     let query =
-        match expression_validator::check_query_only_accesses_namespace(&range_query, &namespace) {
-            Ok(value) => value,
-            Err(http_response) => return http_response,
-        };
+        expression_validator::check_query_only_accesses_namespace(&range_query, &namespace)?;
+    check_query_limits(&cfg, &rate_limiter, &query, &namespace)?;
 
     let start = range_query.start.to_string();
     let end = range_query
@@ -113,8 +181,9 @@ pub async fn query_prometheus(
         .to_string();
 
     if end.parse::<u64>().unwrap() < start.parse::<u64>().unwrap() {
-        return HttpResponse::BadRequest()
-            .json("End time must be greater than or equal to start time");
+        return Err(
+            HttpResponse::BadRequest().json("End time must be greater than or equal to start time")
+        );
     }
 
     // Prepare step and timeout
@@ -128,18 +197,31 @@ pub async fn query_prometheus(
     // Parse step into seconds
     let step_seconds = match parse_duration(&step) {
         Ok(duration) => duration.as_secs(),
-        Err(_) => return HttpResponse::BadRequest().json("Invalid step format"),
+        Err(_) => return Err(HttpResponse::BadRequest().json("Invalid step format")),
     };
 
+    if step_seconds < cfg.prometheus_min_step_sec.max(1) {
+        return Err(HttpResponse::BadRequest().json(format!(
+            "Step must be at least {}s",
+            cfg.prometheus_min_step_sec.max(1)
+        )));
+    }
+
     // Check if the time range and step will result in too many samples
     let start_sec = start.parse::<u64>().unwrap();
     let end_sec = end.parse::<u64>().unwrap();
     let time_range_seconds = end_sec - start_sec;
+    if time_range_seconds > cfg.prometheus_max_range_sec {
+        return Err(HttpResponse::BadRequest().json(format!(
+            "Time range must be at most {}s",
+            cfg.prometheus_max_range_sec
+        )));
+    }
     let expected_samples = time_range_seconds / step_seconds;
 
     if expected_samples > 10_000 && !query.starts_with("ALERTS{") {
-        return HttpResponse::BadRequest()
-            .json("Query would result in too many samples. Please adjust time range or step to sample less than 10,000 time periods.");
+        return Err(HttpResponse::BadRequest()
+            .json("Query would result in too many samples. Please adjust time range or step to sample less than 10,000 time periods."));
     }
 
     // Construct query URL
@@ -147,12 +229,14 @@ pub async fn query_prometheus(
         "{}/api/v1/query_range",
         cfg.prometheus_url.trim_end_matches('/')
     );
+    let limit = (cfg.prometheus_max_series + 1).to_string();
     let query_params = [
         ("query", &query),
         ("start", &start),
         ("end", &end),
         ("step", &step),
         ("timeout", &timeout_ms.to_string()),
+        ("limit", &limit),
     ];
 
     // Create an HTTP request to the Prometheus backend
@@ -165,15 +249,15 @@ pub async fn query_prometheus(
 
     // Handle the response
     match response {
-        Ok(response) => prometheus_response(response).await,
+        Ok(response) => prometheus_result(response, cfg.prometheus_max_series).await,
         Err(e) => {
             error!("Failed to query Prometheus: {}", e);
-            HttpResponse::GatewayTimeout().json("Failed to query Prometheus")
+            Err(HttpResponse::GatewayTimeout().json("Failed to query Prometheus"))
         }
     }
 }
 
-fn parse_duration(duration: &str) -> Result<Duration, &'static str> {
+pub(crate) fn parse_duration(duration: &str) -> Result<Duration, &'static str> {
     if duration.is_empty() {
         return Err("Duration cannot be empty");
     }
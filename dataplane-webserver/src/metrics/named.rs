@@ -0,0 +1,281 @@
+use crate::config::Config;
+use crate::metrics::limits::QueryRateLimiter;
+use crate::metrics::types::{
+    InstantQuery, MetricSample, MetricSeries, NamedMetric, NamedMetricResult, NamedQueryParams,
+    RangeQuery,
+};
+use crate::metrics::{fetch_prometheus_instant, fetch_prometheus_range, parse_duration};
+use actix_web::web::{Data, Query};
+use actix_web::HttpResponse;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Client;
+use serde_json::Value;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL: &str = "5m";
+
+lazy_static! {
+    // Namespaces are DNS labels, so they can't break out of a label matcher
+    static ref NAMESPACE_RE: Regex = Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$").unwrap();
+}
+
+// A metric clients can request by name instead of writing PromQL.
+// $namespace and $interval are replaced when the query is expanded.
+pub struct NamedQuery {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub unit: &'static str,
+    template: &'static str,
+}
+
+pub const NAMED_QUERIES: &[NamedQuery] = &[
+    NamedQuery {
+        name: "connections",
+        description: "Connections to Postgres, by pod",
+        unit: "connections",
+        template: r#"sum by (pod) (cnpg_backends_total{namespace="$namespace"})"#,
+    },
+    NamedQuery {
+        name: "cpu",
+        description: "CPU used by the Postgres container, by pod",
+        unit: "cores",
+        template: r#"sum by (pod) (rate(container_cpu_usage_seconds_total{namespace="$namespace", container="postgres"}[$interval]))"#,
+    },
+    NamedQuery {
+        name: "memory",
+        description: "Working set memory of the Postgres container, by pod",
+        unit: "bytes",
+        template: r#"sum by (pod) (container_memory_working_set_bytes{namespace="$namespace", container="postgres"})"#,
+    },
+    NamedQuery {
+        name: "disk_usage",
+        description: "Used space of each volume",
+        unit: "percent",
+        template: r#"100 * sum by (persistentvolumeclaim) (kubelet_volume_stats_used_bytes{namespace="$namespace"}) / sum by (persistentvolumeclaim) (kubelet_volume_stats_capacity_bytes{namespace="$namespace"})"#,
+    },
+    NamedQuery {
+        name: "transactions_per_second",
+        description: "Committed and rolled back transactions per second, by pod",
+        unit: "transactions/s",
+        template: r#"sum by (pod) (rate(cnpg_pg_stat_database_xact_commit{namespace="$namespace"}[$interval])) + sum by (pod) (rate(cnpg_pg_stat_database_xact_rollback{namespace="$namespace"}[$interval]))"#,
+    },
+    NamedQuery {
+        name: "replication_lag",
+        description: "Replication lag of each replica",
+        unit: "seconds",
+        template: r#"max by (pod) (cnpg_pg_replication_lag{namespace="$namespace"})"#,
+    },
+    NamedQuery {
+        name: "cache_hit_ratio",
+        description: "Blocks read from shared buffers instead of disk, by pod",
+        unit: "percent",
+        template: r#"100 * sum by (pod) (rate(cnpg_pg_stat_database_blks_hit{namespace="$namespace"}[$interval])) / (sum by (pod) (rate(cnpg_pg_stat_database_blks_hit{namespace="$namespace"}[$interval])) + sum by (pod) (rate(cnpg_pg_stat_database_blks_read{namespace="$namespace"}[$interval])))"#,
+    },
+];
+
+impl NamedQuery {
+    pub fn expand(&self, namespace: &str, interval: Duration) -> String {
+        self.template
+            .replace("$namespace", namespace)
+            .replace("$interval", &format!("{}s", interval.as_secs()))
+    }
+}
+
+pub fn find_named_query(name: &str) -> Option<&'static NamedQuery> {
+    NAMED_QUERIES.iter().find(|query| query.name == name)
+}
+
+pub fn named_metrics() -> Vec<NamedMetric> {
+    NAMED_QUERIES
+        .iter()
+        .map(|query| NamedMetric {
+            name: query.name.to_string(),
+            description: query.description.to_string(),
+            unit: query.unit.to_string(),
+        })
+        .collect()
+}
+
+// Converts a Prometheus matrix or vector result into series
+pub fn to_series(json_response: &Value) -> Vec<MetricSeries> {
+    let Some(result) = json_response["data"]["result"].as_array() else {
+        return vec![];
+    };
+    result
+        .iter()
+        .map(|series| {
+            let labels = series["metric"]
+                .as_object()
+                .map(|metric| {
+                    metric
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            Some((name.clone(), value.as_str()?.to_string()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            // Range queries return values, instant queries a single value
+            let samples = match series["values"].as_array() {
+                Some(values) => values.iter().collect(),
+                None => vec![&series["value"]],
+            };
+            MetricSeries {
+                labels,
+                values: samples.into_iter().filter_map(to_sample).collect(),
+            }
+        })
+        .collect()
+}
+
+fn to_sample(sample: &Value) -> Option<MetricSample> {
+    Some(MetricSample {
+        time: sample[0].as_f64()?,
+        value: sample[1]
+            .as_str()?
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite()),
+    })
+}
+
+pub async fn query_named(
+    cfg: Data<Config>,
+    http_client: Data<Client>,
+    rate_limiter: Data<QueryRateLimiter>,
+    params: Query<NamedQueryParams>,
+    namespace: String,
+    name: String,
+) -> HttpResponse {
+    let Some(named_query) = find_named_query(&name) else {
+        return HttpResponse::NotFound().json(format!("Unknown metric {}", name));
+    };
+    if !NAMESPACE_RE.is_match(&namespace) {
+        return HttpResponse::BadRequest().json("Invalid namespace");
+    }
+
+    let interval = match parse_duration(params.interval.as_deref().unwrap_or(DEFAULT_INTERVAL)) {
+        Ok(interval) if interval.as_secs() > 0 => interval,
+        _ => return HttpResponse::BadRequest().json("Invalid interval format"),
+    };
+    let query = named_query.expand(&namespace, interval);
+
+    let result = match params.start {
+        Some(start) => {
+            let range_query = RangeQuery {
+                query,
+                start,
+                end: params.end,
+                step: params.step.clone(),
+            };
+            fetch_prometheus_range(
+                cfg,
+                http_client,
+                rate_limiter,
+                Query(range_query),
+                namespace,
+            )
+            .await
+        }
+        None => {
+            let instant_query = InstantQuery {
+                query,
+                time: params.time,
+            };
+            fetch_prometheus_instant(
+                cfg,
+                http_client,
+                rate_limiter,
+                Query(instant_query),
+                namespace,
+            )
+            .await
+        }
+    };
+
+    match result {
+        Ok(json_response) => HttpResponse::Ok().json(NamedMetricResult {
+            name: named_query.name.to_string(),
+            unit: named_query.unit.to_string(),
+            series: to_series(&json_response),
+        }),
+        Err(http_response) => http_response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::expression_validator::{
+        check_query_cost, check_query_only_accesses_namespace,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_named_queries_are_valid() {
+        let cfg = Config::default();
+        let namespace = "org-foo-inst-bar".to_string();
+        for named_query in NAMED_QUERIES {
+            let query = named_query.expand(&namespace, Duration::from_secs(300));
+            assert!(!query.contains('$'), "{}", named_query.name);
+            let instant_query = Query(InstantQuery {
+                query: query.clone(),
+                time: None,
+            });
+            assert!(
+                check_query_only_accesses_namespace(&instant_query, &namespace).is_ok(),
+                "{}",
+                named_query.name
+            );
+            assert!(
+                check_query_cost(
+                    &query,
+                    &cfg.prometheus_denied_functions,
+                    Duration::from_secs(cfg.prometheus_max_range_sec)
+                )
+                .is_ok(),
+                "{}",
+                named_query.name
+            );
+        }
+        assert!(find_named_query("cpu").is_some());
+        assert!(find_named_query("up").is_none());
+        assert!(!NAMESPACE_RE.is_match("org-foo\"} or up{namespace=\"org-bar"));
+    }
+
+    #[test]
+    fn test_to_series() {
+        let matrix = json!({
+            "status": "success",
+            "data": {
+                "resultType": "matrix",
+                "result": [{
+                    "metric": {"pod": "org-foo-1"},
+                    "values": [[1435781430, "2"], [1435781445, "NaN"]]
+                }]
+            }
+        });
+        let series = to_series(&matrix);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].labels["pod"], "org-foo-1");
+        assert_eq!(series[0].values.len(), 2);
+        assert_eq!(series[0].values[0].value, Some(2.0));
+        assert_eq!(series[0].values[1].value, None);
+
+        let vector = json!({
+            "status": "success",
+            "data": {
+                "resultType": "vector",
+                "result": [{
+                    "metric": {"persistentvolumeclaim": "org-foo-1"},
+                    "value": [1435781430.5, "41.5"]
+                }]
+            }
+        });
+        let series = to_series(&vector);
+        assert_eq!(series[0].values.len(), 1);
+        assert_eq!(series[0].values[0].time, 1435781430.5);
+        assert_eq!(series[0].values[0].value, Some(41.5));
+    }
+}
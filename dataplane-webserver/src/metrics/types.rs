@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 
 #[derive(Deserialize, Clone)]
 pub struct RangeQuery {
@@ -13,3 +15,43 @@ pub struct InstantQuery {
     pub query: String,
     pub time: Option<u64>,
 }
+
+#[derive(Deserialize, Clone)]
+pub struct NamedQueryParams {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub step: Option<String>,
+    pub time: Option<u64>,
+    pub interval: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NamedMetric {
+    /// Name used in the metric's path
+    pub name: String,
+    pub description: String,
+    /// Unit of every value, such as bytes or percent
+    pub unit: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MetricSample {
+    /// Unix timestamp
+    pub time: f64,
+    /// null when Prometheus returned NaN or infinity
+    pub value: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MetricSeries {
+    /// Labels identifying the series, such as pod or persistentvolumeclaim
+    pub labels: BTreeMap<String, String>,
+    pub values: Vec<MetricSample>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NamedMetricResult {
+    pub name: String,
+    pub unit: String,
+    pub series: Vec<MetricSeries>,
+}
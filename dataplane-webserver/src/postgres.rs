@@ -0,0 +1,140 @@
+use crate::config::Config;
+use crate::secrets::byte_string_to_string;
+use actix_web::{error::ErrorInternalServerError, Error};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client as KubeClient};
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use std::time::Duration;
+use tokio_postgres::config::SslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// Reads one key of a secret in the instance namespace.
+pub async fn secret_value(
+    kube_client: &KubeClient,
+    namespace: &str,
+    secret_name: &str,
+    key: &str,
+) -> Result<String, Error> {
+    let secrets_api: Api<Secret> = Api::namespaced(kube_client.clone(), namespace);
+    let secret = secrets_api.get(secret_name).await.map_err(|e| {
+        ErrorInternalServerError(format!("Failed to get secret {}: {}", secret_name, e))
+    })?;
+    let value = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(key))
+        .ok_or_else(|| {
+            ErrorInternalServerError(format!("Secret {} has no key {}", secret_name, key))
+        })?;
+    byte_string_to_string(value)
+        .map_err(|_| ErrorInternalServerError(format!("Secret {} is not UTF-8", secret_name)))
+}
+
+/// Connects to the primary of the instance in the namespace.
+///
+/// The connection uses TLS, and the server certificate is verified against the
+/// instance CA in the `<namespace>-ca1` secret. The CoreDB is named after its namespace.
+///
+/// # Arguments
+/// * `kube_client` - Kubernetes client
+/// * `config` - Application configuration
+/// * `namespace` - Instance namespace
+/// * `user` - Role to connect as
+/// * `password` - Password of the role
+/// * `database` - Database to connect to
+pub async fn connect(
+    kube_client: &KubeClient,
+    config: &Config,
+    namespace: &str,
+    user: &str,
+    password: &str,
+    database: &str,
+) -> Result<tokio_postgres::Client, Error> {
+    let ca = secret_value(
+        kube_client,
+        namespace,
+        &format!("{}-ca1", namespace),
+        "ca.crt",
+    )
+    .await?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(ca.as_bytes()) {
+        let cert = cert.map_err(|e| ErrorInternalServerError(format!("Invalid CA: {}", e)))?;
+        roots
+            .add(cert)
+            .map_err(|e| ErrorInternalServerError(format!("Invalid CA: {}", e)))?;
+    }
+    let tls_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let host = format!("{}-rw.{}.svc.cluster.local", namespace, namespace);
+    let (client, connection) = tokio_postgres::Config::new()
+        .host(&host)
+        .port(5432)
+        .user(user)
+        .password(password)
+        .dbname(database)
+        .application_name("tembo-dataplane-webserver")
+        .ssl_mode(SslMode::Require)
+        .connect_timeout(Duration::from_secs(config.postgres_connect_timeout_sec))
+        .connect(MakeRustlsConnect::new(tls_config))
+        .await
+        .map_err(|e| {
+            tracing::error!(namespace = %namespace, error = %e, "Failed to connect to Postgres");
+            ErrorInternalServerError("Failed to connect to Postgres")
+        })?;
+
+    let connection_namespace = namespace.to_string();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!(
+                namespace = %connection_namespace,
+                error = %e,
+                "Postgres connection failed"
+            );
+        }
+    });
+    Ok(client)
+}
+
+/// Connects to the primary of the instance as the superuser, with the password
+/// from the `<namespace>-connection` secret.
+pub async fn connect_superuser(
+    kube_client: &KubeClient,
+    config: &Config,
+    namespace: &str,
+) -> Result<tokio_postgres::Client, Error> {
+    let password = secret_value(
+        kube_client,
+        namespace,
+        &format!("{}-connection", namespace),
+        "password",
+    )
+    .await?;
+    connect(
+        kube_client,
+        config,
+        namespace,
+        "postgres",
+        &password,
+        "postgres",
+    )
+    .await
+}
+
+/// Quotes an identifier, such as a role name, for use in SQL.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("postgres"), "\"postgres\"");
+        assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+    }
+}
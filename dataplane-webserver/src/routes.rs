@@ -1,5 +1,7 @@
 pub mod backups;
 pub mod health;
+pub mod logs;
 pub mod metrics;
 pub mod root;
 pub mod secrets;
+pub mod sql;
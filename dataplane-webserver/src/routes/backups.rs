@@ -1,3 +1,4 @@
+use crate::auth::{RequireScope, Scope};
 use crate::{
     backups::coredb::{fetch_coredb, get_backup_path_from_coredb},
     backups::find_instance_namespace,
@@ -37,7 +38,7 @@ use uuid::Uuid;
 ///     "status": "processing"
 /// }
 /// ```
-#[post("/backup")]
+#[post("/backup", wrap = "RequireScope(Scope::BackupsWrite)")]
 pub async fn trigger_instance_backup(
     _req: HttpRequest,
     path: web::Path<(String, String)>,
@@ -57,7 +58,7 @@ pub async fn trigger_instance_backup(
     // Create S3 client
     let region_provider = RegionProviderChain::default_provider()
         .or_else(Region::new(config.backup_bucket_region.clone()));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
+    let shared_config = aws_config::defaults(BehaviorVersion::latest())
         .region(region_provider)
         .load()
         .await;
@@ -201,7 +202,7 @@ pub async fn trigger_instance_backup(
 /// * `400 Bad Request` - If org_id or inst_id contain invalid characters
 /// * `404 Not Found` - If the backup job doesn't exist
 /// * `500 Internal Server Error` - If there's an error accessing S3 or parsing metadata
-#[get("/backup/{job_id}")]
+#[get("/backup/{job_id}", wrap = "RequireScope(Scope::BackupsRead)")]
 pub async fn get_backup_status(
     _req: HttpRequest,
     path: web::Path<(String, String, String)>,
@@ -218,7 +219,7 @@ pub async fn get_backup_status(
     // Create S3 client
     let region_provider = RegionProviderChain::default_provider()
         .or_else(Region::new(config.backup_bucket_region.clone()));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
+    let shared_config = aws_config::defaults(BehaviorVersion::latest())
         .region(region_provider)
         .load()
         .await;
@@ -0,0 +1,149 @@
+use crate::auth::{RequireScope, Scope};
+use crate::{config, logs};
+
+use crate::logs::types::{LogInstantQuery, LogRangeQuery, TailQuery};
+use actix_web::{get, web, Error, HttpRequest, HttpResponse};
+
+use reqwest::Client;
+
+#[utoipa::path(
+    context_path = "/{namespace}/logs",
+    params(
+        ("namespace" = String, Path, example="org-coredb-inst-control-plane-dev", description = "Instance namespace"),
+        ("query" = inline(String), Query, example="{namespace=\"org-coredb-inst-control-plane-dev\", container=\"postgres\"} |= \"error\"", description = "LogQL query, all stream selectors must include a 'namespace' label matching the query path"),
+        ("start" = inline(Option<String>), Query, example="1686780828000000000", description = "Range start, unix nanoseconds or RFC3339. Default is an hour ago."),
+        ("end" = inline(Option<String>), Query, example="1686862041000000000", description = "Range end, unix nanoseconds or RFC3339. Default is now."),
+        ("step" = inline(Option<String>), Query, example="60s", description = "Step size for metric queries"),
+        ("limit" = inline(Option<u32>), Query, example="100", description = "Maximum number of log lines, defaults to 100"),
+        ("direction" = inline(Option<String>), Query, example="backward", description = "forward or backward, defaults to backward"),
+    ),
+    responses(
+        (status = 200, description = "Success range query to Loki, please see Loki documentation for response format details. https://grafana.com/docs/loki/latest/reference/loki-http-api/#query-logs-within-a-range-of-time", body = Value,
+        example = json!({
+            "status": "success",
+            "data": {
+                "resultType": "streams",
+                "result": [
+                    {
+                        "stream": {
+                            "namespace": "org-coredb-inst-control-plane-dev",
+                            "container": "postgres"
+                        },
+                        "values": [
+                            [
+                                "1686862041000000000",
+                                "ERROR: relation \"foo\" does not exist"
+                            ]
+                        ]
+                    }
+                ]
+            }
+        }),
+        ),
+        (status = 400, description = "Parameters are missing or incorrect"),
+        (status = 403, description = "Not authorized for query"),
+        (status = 422, description = "Incorrectly formatted query"),
+        (status = 504, description = "Request timed out on logs backend"),
+    )
+)]
+#[get("/query_range", wrap = "RequireScope(Scope::LogsRead)")]
+pub async fn query_range(
+    cfg: web::Data<config::Config>,
+    http_client: web::Data<Client>,
+    range_query: web::Query<LogRangeQuery>,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse, Error> {
+    let (namespace,) = path.into_inner();
+
+    Ok(logs::query_loki_range(cfg, http_client, range_query, namespace).await)
+}
+
+#[utoipa::path(
+    context_path = "/{namespace}/logs",
+    params(
+        ("namespace" = String, Path, example="org-coredb-inst-control-plane-dev", description = "Instance namespace"),
+        ("query" = inline(String), Query, example="sum(count_over_time({namespace=\"org-coredb-inst-control-plane-dev\"} |= \"error\" [5m]))", description = "LogQL query, all stream selectors must include a 'namespace' label matching the query path"),
+        ("time" = inline(Option<String>), Query, example="1686862041000000000", description = "Evaluation time, unix nanoseconds or RFC3339. Default is now."),
+        ("limit" = inline(Option<u32>), Query, example="100", description = "Maximum number of log lines, defaults to 100"),
+        ("direction" = inline(Option<String>), Query, example="backward", description = "forward or backward, defaults to backward"),
+    ),
+    responses(
+        (status = 200, description = "Success instant query to Loki, please see Loki documentation for response format details. https://grafana.com/docs/loki/latest/reference/loki-http-api/#query-logs-at-a-single-point-in-time", body = Value,
+        example = json!({
+            "status": "success",
+            "data": {
+                "resultType": "vector",
+                "result": [
+                    {
+                        "metric": {},
+                        "value": [
+                            1686862041.0,
+                            "3"
+                        ]
+                    }
+                ]
+            }
+        }),
+        ),
+        (status = 400, description = "Parameters are missing or incorrect"),
+        (status = 403, description = "Not authorized for query"),
+        (status = 422, description = "Incorrectly formatted query"),
+        (status = 504, description = "Request timed out on logs backend"),
+    )
+)]
+#[get("/query", wrap = "RequireScope(Scope::LogsRead)")]
+pub async fn query(
+    cfg: web::Data<config::Config>,
+    http_client: web::Data<Client>,
+    instant_query: web::Query<LogInstantQuery>,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse, Error> {
+    let (namespace,) = path.into_inner();
+
+    Ok(logs::query_loki_instant(cfg, http_client, instant_query, namespace).await)
+}
+
+#[utoipa::path(
+    context_path = "/{namespace}/logs",
+    params(
+        ("namespace" = String, Path, example="org-coredb-inst-control-plane-dev", description = "Instance namespace"),
+        ("query" = inline(String), Query, example="{namespace=\"org-coredb-inst-control-plane-dev\", container=\"postgres\"}", description = "LogQL log query, all stream selectors must include a 'namespace' label matching the query path"),
+        ("start" = inline(Option<String>), Query, example="1686862041000000000", description = "Start of the tail, unix nanoseconds or RFC3339. Default is an hour ago."),
+        ("limit" = inline(Option<u32>), Query, example="100", description = "Maximum number of log lines sent when the tail starts, defaults to 100"),
+        ("delay_for" = inline(Option<u32>), Query, example="0", description = "Seconds to delay log lines by, at most 5"),
+    ),
+    responses(
+        (status = 101, description = "Switching to a websocket which streams tailed log lines from Loki, please see Loki documentation for message format details. https://grafana.com/docs/loki/latest/reference/loki-http-api/#stream-logs"),
+        (status = 403, description = "Not authorized for query"),
+        (status = 422, description = "Incorrectly formatted query"),
+        (status = 502, description = "Failed to tail logs backend"),
+        (status = 504, description = "Request timed out on logs backend"),
+    )
+)]
+#[get("/tail", wrap = "RequireScope(Scope::LogsRead)")]
+pub async fn tail(
+    cfg: web::Data<config::Config>,
+    req: HttpRequest,
+    body: web::Payload,
+    tail_query: web::Query<TailQuery>,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse, Error> {
+    let (namespace,) = path.into_inner();
+
+    let log_query = match logs::expression_validator::check_query_only_accesses_namespace(
+        &tail_query.query,
+        &namespace,
+    ) {
+        Ok(value) => value,
+        Err(http_response) => return Ok(http_response),
+    };
+    // Connect to Loki before upgrading, so failures are still HTTP errors
+    let loki_stream = match logs::connect_loki_tail(&cfg, &tail_query, log_query).await {
+        Ok(loki_stream) => loki_stream,
+        Err(http_response) => return Ok(http_response),
+    };
+
+    let (response, session, client_stream) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(logs::relay_tail(session, client_stream, loki_stream));
+    Ok(response)
+}
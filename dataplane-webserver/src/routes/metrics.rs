@@ -1,6 +1,8 @@
+use crate::auth::{RequireScope, Scope};
 use crate::{config, metrics};
 
-use crate::metrics::types::{InstantQuery, RangeQuery};
+use crate::metrics::limits::QueryRateLimiter;
+use crate::metrics::types::{InstantQuery, NamedQueryParams, RangeQuery};
 use actix_web::{get, web, Error, HttpRequest, HttpResponse};
 
 use reqwest::Client;
@@ -40,23 +42,25 @@ use reqwest::Client;
             "status": "success"
         }),
         ),
-        (status = 400, description = "Parameters are missing or incorrect"),
+        (status = 400, description = "Parameters are missing or incorrect, or the query is too expensive"),
         (status = 403, description = "Not authorized for query"),
         (status = 422, description = "Incorrectly formatted query"),
+        (status = 429, description = "Too many queries for the namespace, retry after the Retry-After header"),
         (status = 504, description = "Request timed out on metrics backend"),
     )
 )]
-#[get("/query_range")]
+#[get("/query_range", wrap = "RequireScope(Scope::MetricsRead)")]
 pub async fn query_range(
     cfg: web::Data<config::Config>,
     http_client: web::Data<Client>,
+    rate_limiter: web::Data<QueryRateLimiter>,
     _req: HttpRequest,
     range_query: web::Query<RangeQuery>,
     path: web::Path<(String,)>,
 ) -> Result<HttpResponse, Error> {
     let (namespace,) = path.into_inner();
 
-    Ok(metrics::query_prometheus(cfg, http_client, range_query, namespace).await)
+    Ok(metrics::query_prometheus(cfg, http_client, rate_limiter, range_query, namespace).await)
 }
 
 #[utoipa::path(
@@ -85,21 +89,90 @@ pub async fn query_range(
             "status": "success"
         }),
         ),
-        (status = 400, description = "Parameters are missing or incorrect"),
+        (status = 400, description = "Parameters are missing or incorrect, or the query is too expensive"),
         (status = 403, description = "Not authorized for query"),
         (status = 422, description = "Incorrectly formatted query"),
+        (status = 429, description = "Too many queries for the namespace, retry after the Retry-After header"),
         (status = 504, description = "Request timed out on metrics backend"),
     )
 )]
-#[get("/query")]
+#[get("/query", wrap = "RequireScope(Scope::MetricsRead)")]
 pub async fn query(
     cfg: web::Data<config::Config>,
     http_client: web::Data<Client>,
+    rate_limiter: web::Data<QueryRateLimiter>,
     instant_query: web::Query<InstantQuery>,
     _req: HttpRequest,
     path: web::Path<(String,)>,
 ) -> Result<HttpResponse, Error> {
     let (namespace,) = path.into_inner();
 
-    Ok(metrics::query_prometheus_instant(cfg, http_client, instant_query, namespace).await)
+    Ok(
+        metrics::query_prometheus_instant(cfg, http_client, rate_limiter, instant_query, namespace)
+            .await,
+    )
+}
+
+#[utoipa::path(
+    context_path = "/{namespace}/metrics",
+    params(
+        ("namespace" = String, Path, example="org-coredb-inst-control-plane-dev", description = "Instance namespace"),
+    ),
+    responses(
+        (status = 200, description = "Metrics which can be requested by name from /{namespace}/metrics/named/{name}", body = Vec<metrics::types::NamedMetric>,
+        example = json!([
+            {"name": "connections", "description": "Connections to Postgres, by pod", "unit": "connections"},
+            {"name": "disk_usage", "description": "Used space of each volume", "unit": "percent"}
+        ])),
+    )
+)]
+#[get("/named", wrap = "RequireScope(Scope::MetricsRead)")]
+pub async fn list_named() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(metrics::named::named_metrics()))
+}
+
+#[utoipa::path(
+    context_path = "/{namespace}/metrics",
+    params(
+        ("namespace" = String, Path, example="org-coredb-inst-control-plane-dev", description = "Instance namespace"),
+        ("name" = String, Path, example="connections", description = "Metric name, one of connections, cpu, memory, disk_usage, transactions_per_second, replication_lag or cache_hit_ratio"),
+        ("start" = inline(Option<u64>), Query, example="1686780828", description = "Range start, unix timestamp. Without a start, the metric is only queried at time."),
+        ("end" = inline(Option<u64>), Query, example="1686862041", description = "Range end, unix timestamp. Default is now."),
+        ("step" = inline(Option<String>), Query, example="60s", description = "Step size duration string, defaults to 60s"),
+        ("time" = inline(Option<u64>), Query, example="1686862041", description = "Evaluation time when there is no start, unix timestamp. Default is now."),
+        ("interval" = inline(Option<String>), Query, example="5m", description = "Window for rates, defaults to 5m"),
+    ),
+    responses(
+        (status = 200, description = "Series of the metric in the instance namespace", body = metrics::types::NamedMetricResult,
+        example = json!({
+            "name": "connections",
+            "unit": "connections",
+            "series": [
+                {
+                    "labels": {"pod": "org-coredb-inst-control-plane-dev-1"},
+                    "values": [
+                        {"time": 1435781430, "value": 12.0},
+                        {"time": 1435781445, "value": 14.0}
+                    ]
+                }
+            ]
+        }),
+        ),
+        (status = 400, description = "Parameters are missing or incorrect"),
+        (status = 404, description = "Unknown metric"),
+        (status = 429, description = "Too many queries for the namespace, retry after the Retry-After header"),
+        (status = 504, description = "Request timed out on metrics backend"),
+    )
+)]
+#[get("/named/{name}", wrap = "RequireScope(Scope::MetricsRead)")]
+pub async fn named(
+    cfg: web::Data<config::Config>,
+    http_client: web::Data<Client>,
+    rate_limiter: web::Data<QueryRateLimiter>,
+    params: web::Query<NamedQueryParams>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (namespace, name) = path.into_inner();
+
+    Ok(metrics::named::query_named(cfg, http_client, rate_limiter, params, namespace, name).await)
 }
@@ -1,8 +1,13 @@
-use crate::secrets::types::{AvailableSecret, PasswordString};
+use crate::auth::{RequireScope, Scope};
+use crate::backups::find_instance_namespace;
+use crate::secrets::rotation::{find_managed_role, new_password, rotate_password};
+use crate::secrets::types::{
+    AvailableSecret, PasswordString, RotatePasswordRequest, RotatePasswordResponse,
+};
 use crate::secrets::validate_requested_secret;
 use crate::{config, secrets};
 use actix_web::error::ErrorInternalServerError;
-use actix_web::{get, patch, web, Error, HttpRequest, HttpResponse};
+use actix_web::{get, patch, post, web, Error, HttpRequest, HttpResponse};
 use base64::prelude::*;
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use k8s_openapi::api::core::v1::Namespace;
@@ -75,7 +80,7 @@ pub struct Claims {
         (status = 403, description = "Not authorized for query"),
     )
 )]
-#[get("/secrets")]
+#[get("/secrets", wrap = "RequireScope(Scope::SecretsRead)")]
 pub async fn get_secret_names_v1() -> Result<HttpResponse, Error> {
     let allow_list = SECRETS_ALLOW_LIST.deref();
     Ok(HttpResponse::Ok().json(allow_list))
@@ -96,7 +101,7 @@ pub async fn get_secret_names_v1() -> Result<HttpResponse, Error> {
         (status = 403, description = "Not authorized for query"),
     )
 )]
-#[get("/secrets/{secret_name}")]
+#[get("/secrets/{secret_name}", wrap = "RequireScope(Scope::SecretsRead)")]
 pub async fn get_secret_v1(
     _cfg: web::Data<config::Config>,
     _req: HttpRequest,
@@ -187,7 +192,7 @@ pub fn is_valid_id(s: &str) -> bool {
             description = "Not authorized for query"),
     )
 )]
-#[patch("/secrets/{secret_name}")]
+#[patch("/secrets/{secret_name}", wrap = "RequireScope(Scope::SecretsWrite)")]
 async fn update_postgres_password(
     path: web::Path<(String, String, String)>,
     updated_password: web::Json<PasswordString>,
@@ -289,3 +294,83 @@ async fn update_postgres_password(
         }
     }
 }
+
+// Checks the bearer token is from an admin of the organization
+fn require_org_admin(req: &HttpRequest, org_id: &str) -> Result<(), Error> {
+    let auth_header = req
+        .headers()
+        .get("Authorization")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|hv| hv.strip_prefix("Bearer "))
+        .unwrap_or("");
+
+    let decoding_key = DecodingKey::from_secret("".as_ref());
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.insecure_disable_signature_validation();
+
+    match decode::<Claims>(auth_header, &decoding_key, &validation) {
+        Ok(token_data) => match token_data.claims.organizations.get(org_id) {
+            Some(role) if role == "admin" => Ok(()),
+            _ => Err(actix_web::error::ErrorForbidden("Not authorized")),
+        },
+        Err(err) => {
+            warn!("Error decoding token: {:?}", err);
+            Err(actix_web::error::ErrorBadRequest("Invalid token"))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/orgs/{org_id}/instances/{instance_id}",
+    params(
+        ("org_id" = String, Path, example="org_2T7FJA0DpaNBnELVLU1IS4XzZG0", description = "Tembo Cloud Organization ID"),
+        ("instance_id" = String, Path, example="inst_1696253936968_TblNOY_6", description = "Tembo Cloud Instance ID"),
+        ("secret_name", example="readonly-role", description = "Secret name of the role: superuser-role, app-role, readonly-role or exporter-role")
+    ),
+    request_body = RotatePasswordRequest,
+    responses(
+        (status = 200,
+            description = "Password rotated in Postgres and in the secret, connection poolers and AppServices using it are restarted.",
+            body = RotatePasswordResponse,
+            example = json!({ "secret_name": "readonly-role", "role": "readonly", "rotated_at": "2024-05-01T12:00:00+00:00" })),
+        (status = 400, description = "Invalid password or token"),
+        (status = 403, description = "Not authorized for query"),
+        (status = 409, description = "The secret changed during the rotation"),
+    )
+)]
+#[post(
+    "/secrets/{secret_name}/rotate",
+    wrap = "RequireScope(Scope::SecretsWrite)"
+)]
+pub async fn rotate_role_password(
+    path: web::Path<(String, String, String)>,
+    request: web::Json<RotatePasswordRequest>,
+    cfg: web::Data<config::Config>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (org_id, instance_id, secret_name) = path.into_inner();
+    require_org_admin(&req, &org_id)?;
+    if !is_valid_id(&org_id) || !is_valid_id(&instance_id) {
+        return Ok(HttpResponse::BadRequest()
+            .json("org_id and instance_id must be alphanumeric or underscore only"));
+    }
+
+    let Some(role) = find_managed_role(&secret_name) else {
+        return Ok(HttpResponse::Forbidden().json(
+            "Password can only be rotated for roles. Ex: superuser-role, app-role, readonly-role, exporter-role",
+        ));
+    };
+    let password = new_password(request.password.as_deref())?;
+
+    let kubernetes_client = Client::try_default().await.map_err(|e| {
+        ErrorInternalServerError(format!("Failed to create Kubernetes client: {}", e))
+    })?;
+    let namespace = find_instance_namespace(&kubernetes_client, &org_id, &instance_id).await?;
+
+    let rotated_at = rotate_password(&kubernetes_client, &cfg, &namespace, role, &password).await?;
+    Ok(HttpResponse::Ok().json(RotatePasswordResponse {
+        secret_name,
+        role: role.role.to_string(),
+        rotated_at,
+    }))
+}
@@ -0,0 +1,152 @@
+use crate::auth::{Principal, RequireScope, Scope};
+use crate::backups::find_instance_namespace;
+use crate::config;
+use crate::routes::secrets::is_valid_id;
+use crate::sql::audit::SqlAuditLog;
+use crate::sql::types::{NamedSqlQueryRequest, SqlQueryRequest};
+use crate::sql::{named, normalize_statement, run_query, DEFAULT_DATABASE};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{get, post, web, Error, HttpMessage, HttpRequest, HttpResponse};
+use kube::Client as KubeClient;
+
+// Runs a statement on the instance and records it, whether or not it succeeds
+#[allow(clippy::too_many_arguments)]
+async fn execute(
+    req: &HttpRequest,
+    cfg: &config::Config,
+    audit_log: &SqlAuditLog,
+    org_id: &str,
+    instance_id: &str,
+    database: Option<&str>,
+    statement: &str,
+    limit: Option<u32>,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_id(org_id) || !is_valid_id(instance_id) {
+        return Ok(HttpResponse::BadRequest()
+            .json("org_id and instance_id must be alphanumeric or underscore only"));
+    }
+    let statement = match normalize_statement(statement) {
+        Ok(statement) => statement,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+    let database = database.unwrap_or(DEFAULT_DATABASE);
+    let limit = limit
+        .unwrap_or(cfg.sql_query_max_rows)
+        .min(cfg.sql_query_max_rows);
+    let principal = req
+        .extensions()
+        .get::<Principal>()
+        .cloned()
+        .ok_or_else(|| ErrorInternalServerError("Request was not authorized"))?;
+
+    let kube_client = KubeClient::try_default().await.map_err(|e| {
+        ErrorInternalServerError(format!("Failed to create Kubernetes client: {}", e))
+    })?;
+    let namespace = find_instance_namespace(&kube_client, org_id, instance_id).await?;
+
+    let result = run_query(&kube_client, cfg, &namespace, database, statement, limit).await;
+    audit_log.record(&principal, &namespace, database, statement, &result)?;
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/orgs/{org_id}/instances/{instance_id}",
+    params(
+        ("org_id" = String, Path, example="org_2T7FJA0DpaNBnELVLU1IS4XzZG0", description = "Tembo Cloud Organization ID"),
+        ("instance_id" = String, Path, example="inst_1696253936968_TblNOY_6", description = "Tembo Cloud Instance ID"),
+    ),
+    request_body = SqlQueryRequest,
+    responses(
+        (status = 200, description = "Rows returned by the statement, which ran in a read-only transaction with a statement timeout", body = crate::sql::types::SqlQueryResult,
+        example = json!({
+            "columns": ["state", "connections"],
+            "rows": [["active", "3"], ["idle", "12"], [null, "5"]],
+            "truncated": false,
+            "duration_ms": 4
+        })),
+        (status = 400, description = "The statement is invalid or failed, the body has the error from Postgres"),
+        (status = 403, description = "Not authorized for query"),
+        (status = 504, description = "The statement exceeded the statement timeout"),
+    )
+)]
+#[post("/sql", wrap = "RequireScope(Scope::SqlRead)")]
+pub async fn query(
+    cfg: web::Data<config::Config>,
+    audit_log: web::Data<SqlAuditLog>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    request: web::Json<SqlQueryRequest>,
+) -> Result<HttpResponse, Error> {
+    let (org_id, instance_id) = path.into_inner();
+
+    execute(
+        &req,
+        &cfg,
+        &audit_log,
+        &org_id,
+        &instance_id,
+        request.database.as_deref(),
+        &request.query,
+        request.limit,
+    )
+    .await
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/orgs/{org_id}/instances/{instance_id}",
+    params(
+        ("org_id" = String, Path, example="org_2T7FJA0DpaNBnELVLU1IS4XzZG0", description = "Tembo Cloud Organization ID"),
+        ("instance_id" = String, Path, example="inst_1696253936968_TblNOY_6", description = "Tembo Cloud Instance ID"),
+    ),
+    responses(
+        (status = 200, description = "Diagnostic queries which can be run by name", body = Vec<crate::sql::types::NamedSqlQuery>,
+        example = json!([
+            {"name": "connections", "description": "Sessions by database, user and state", "query": "SELECT datname, usename, state, count(*) AS connections FROM pg_stat_activity WHERE backend_type = 'client backend' GROUP BY datname, usename, state ORDER BY connections DESC"}
+        ])),
+    )
+)]
+#[get("/sql/named", wrap = "RequireScope(Scope::SqlRead)")]
+pub async fn list_named() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(named::named_queries()))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/orgs/{org_id}/instances/{instance_id}",
+    params(
+        ("org_id" = String, Path, example="org_2T7FJA0DpaNBnELVLU1IS4XzZG0", description = "Tembo Cloud Organization ID"),
+        ("instance_id" = String, Path, example="inst_1696253936968_TblNOY_6", description = "Tembo Cloud Instance ID"),
+        ("name" = String, Path, example="blocked_queries", description = "Query name, one of activity, connections, blocked_queries, locks, table_bloat, unused_indexes, database_sizes or replication"),
+    ),
+    request_body(content = NamedSqlQueryRequest, description = "Optional database and row limit"),
+    responses(
+        (status = 200, description = "Rows returned by the query", body = crate::sql::types::SqlQueryResult),
+        (status = 403, description = "Not authorized for query"),
+        (status = 404, description = "Unknown query"),
+        (status = 504, description = "The query exceeded the statement timeout"),
+    )
+)]
+#[post("/sql/named/{name}", wrap = "RequireScope(Scope::SqlRead)")]
+pub async fn run_named(
+    cfg: web::Data<config::Config>,
+    audit_log: web::Data<SqlAuditLog>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    request: Option<web::Json<NamedSqlQueryRequest>>,
+) -> Result<HttpResponse, Error> {
+    let (org_id, instance_id, name) = path.into_inner();
+    let Some(named_query) = named::find_named_query(&name) else {
+        return Ok(HttpResponse::NotFound().json(format!("Unknown query {}", name)));
+    };
+
+    execute(
+        &req,
+        &cfg,
+        &audit_log,
+        &org_id,
+        &instance_id,
+        request.as_ref().and_then(|r| r.database.as_deref()),
+        named_query.query,
+        request.as_ref().and_then(|r| r.limit),
+    )
+    .await
+}
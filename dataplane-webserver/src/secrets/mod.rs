@@ -6,6 +6,7 @@ use kube::{Api, Client};
 use log::error;
 use std::collections::BTreeMap;
 
+pub mod rotation;
 pub mod types;
 
 pub async fn get_secret_data_from_kubernetes(
@@ -36,6 +37,7 @@ pub fn validate_requested_secret(secret_name: &String) -> Result<&AvailableSecre
     Ok(secret_config)
 }
 
+#[allow(clippy::result_large_err)]
 pub fn byte_string_to_string(byte_string: &ByteString) -> Result<String, HttpResponse> {
     match String::from_utf8(byte_string.0.clone()) {
         Ok(value) => Ok(value),
@@ -25,3 +25,19 @@ impl AvailableSecret {
         (self.formatter)(instance_name)
     }
 }
+
+#[derive(Deserialize, ToSchema)]
+pub struct RotatePasswordRequest {
+    /// The new password, one is generated when this is not set
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RotatePasswordResponse {
+    /// The rotated secret
+    pub secret_name: String,
+    /// The Postgres role which has a new password
+    pub role: String,
+    /// Time of the rotation, RFC3339
+    pub rotated_at: String,
+}
@@ -0,0 +1,164 @@
+use crate::auth::Principal;
+use crate::config::Config;
+use crate::sql::types::SqlQueryResult;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::Error;
+use chrono::Utc;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// A statement someone ran on an instance, and its outcome
+#[derive(Serialize)]
+struct SqlAuditRecord<'a> {
+    time: String,
+    subject: &'a str,
+    namespace: &'a str,
+    database: &'a str,
+    query: &'a str,
+    outcome: &'static str,
+    rows: Option<usize>,
+    duration_ms: Option<u64>,
+    error: Option<String>,
+}
+
+/// Appends a JSON line for every statement to the SQL audit log.
+///
+/// The log is written directly rather than through the logger, so `RUST_LOG` can't filter it.
+/// It is the file at `SQL_AUDIT_LOG_PATH`, or stdout when that isn't set.
+pub struct SqlAuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl SqlAuditLog {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn from_config(cfg: &Config) -> io::Result<Self> {
+        Ok(match &cfg.sql_audit_log_path {
+            Some(path) => Self::new(Box::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => Self::new(Box::new(io::stdout())),
+        })
+    }
+
+    /// Records who ran a statement on which instance, and its outcome.
+    ///
+    /// Results must not be returned when this fails, so every statement that ran is audited.
+    pub fn record(
+        &self,
+        principal: &Principal,
+        namespace: &str,
+        database: &str,
+        statement: &str,
+        result: &Result<SqlQueryResult, Error>,
+    ) -> Result<(), Error> {
+        let mut record = SqlAuditRecord {
+            time: Utc::now().to_rfc3339(),
+            subject: &principal.subject,
+            namespace,
+            database,
+            query: statement,
+            outcome: "executed",
+            rows: None,
+            duration_ms: None,
+            error: None,
+        };
+        match result {
+            Ok(result) => {
+                record.rows = Some(result.rows.len());
+                record.duration_ms = Some(result.duration_ms);
+            }
+            Err(e) => {
+                record.outcome = "failed";
+                record.error = Some(e.to_string());
+            }
+        }
+
+        let mut line = serde_json::to_vec(&record).map_err(ErrorInternalServerError)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer
+            .write_all(&line)
+            .and_then(|_| writer.flush())
+            .map_err(|e| {
+                tracing::error!(
+                    namespace = %namespace,
+                    error = %e,
+                    "Failed to write the SQL audit log"
+                );
+                ErrorInternalServerError("Failed to audit query")
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    // Keeps what was written, so the test can read it back
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let buffer = Buffer::default();
+        let audit_log = SqlAuditLog::new(Box::new(buffer.clone()));
+        let principal = Principal {
+            subject: "ci".to_string(),
+            organizations: HashSet::new(),
+            instances: None,
+            scopes: HashSet::new(),
+        };
+        let result = Ok(SqlQueryResult {
+            columns: vec!["?column?".to_string()],
+            rows: vec![vec![Some("1".to_string())]],
+            truncated: false,
+            duration_ms: 3,
+        });
+        audit_log
+            .record(&principal, "org-1-inst-1", "postgres", "SELECT 1", &result)
+            .unwrap();
+        audit_log
+            .record(
+                &principal,
+                "org-1-inst-1",
+                "postgres",
+                "SELECT nope",
+                &Err(actix_web::error::ErrorBadRequest(
+                    "column \"nope\" does not exist",
+                )),
+            )
+            .unwrap();
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["subject"], "ci");
+        assert_eq!(lines[0]["query"], "SELECT 1");
+        assert_eq!(lines[0]["outcome"], "executed");
+        assert_eq!(lines[0]["rows"], 1);
+        assert_eq!(lines[1]["outcome"], "failed");
+        assert_eq!(lines[1]["error"], "column \"nope\" does not exist");
+    }
+}
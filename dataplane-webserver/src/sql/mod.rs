@@ -0,0 +1,213 @@
+use crate::config::Config;
+use crate::postgres::{connect, secret_value};
+use crate::secrets::rotation::find_managed_role;
+use crate::sql::types::SqlQueryResult;
+use actix_web::error::{ErrorBadRequest, ErrorGatewayTimeout, ErrorInternalServerError};
+use actix_web::Error;
+use kube::Client as KubeClient;
+use std::time::Instant;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::SimpleQueryMessage;
+
+pub mod audit;
+pub mod named;
+pub mod types;
+
+/// Managed role queries run as. It has pg_monitor, so it can read statistics and
+/// locks of every session, but not table data.
+const QUERY_ROLE: &str = "exporter-role";
+const CURSOR_NAME: &str = "diagnostic_query";
+pub const DEFAULT_DATABASE: &str = "postgres";
+
+/// Trims whitespace and trailing semicolons from a statement.
+pub fn normalize_statement(statement: &str) -> Result<&str, String> {
+    let statement = statement
+        .trim()
+        .trim_end_matches(|c: char| c == ';' || c.is_whitespace());
+    if statement.is_empty() {
+        return Err("Query is empty".to_string());
+    }
+    Ok(statement)
+}
+
+fn query_error(namespace: &str, e: tokio_postgres::Error) -> Error {
+    match e.as_db_error() {
+        Some(db_error) if db_error.code() == &SqlState::QUERY_CANCELED => {
+            ErrorGatewayTimeout("Query exceeded the statement timeout")
+        }
+        // Errors from Postgres describe the statement, so they're returned to the caller
+        Some(db_error) => ErrorBadRequest(db_error.message().to_string()),
+        None => {
+            tracing::error!(namespace = %namespace, error = %e, "Failed to run query");
+            ErrorInternalServerError("Failed to run query")
+        }
+    }
+}
+
+fn collect_rows(messages: Vec<SimpleQueryMessage>) -> (Vec<String>, Vec<Vec<Option<String>>>) {
+    let mut columns = vec![];
+    let mut rows = vec![];
+    for message in messages {
+        match message {
+            SimpleQueryMessage::RowDescription(description) => {
+                columns = description
+                    .iter()
+                    .map(|column| column.name().to_string())
+                    .collect();
+            }
+            SimpleQueryMessage::Row(row) => rows.push(
+                (0..row.len())
+                    .map(|i| row.get(i).map(str::to_string))
+                    .collect(),
+            ),
+            _ => (),
+        }
+    }
+    (columns, rows)
+}
+
+/// Runs a statement in a read-only transaction with a statement timeout.
+///
+/// The statement is declared as a cursor, and at most `limit` + 1 rows are fetched from it,
+/// so callers can tell the result was truncated. The transaction is rolled back.
+pub async fn fetch_statement(
+    client: &mut tokio_postgres::Client,
+    timeout_ms: u64,
+    statement: &str,
+    limit: u32,
+) -> Result<Vec<SimpleQueryMessage>, tokio_postgres::Error> {
+    let transaction = client.build_transaction().read_only(true).start().await?;
+    transaction
+        .batch_execute(&format!("SET LOCAL statement_timeout = {}", timeout_ms))
+        .await?;
+    // The extended protocol rejects multiple statements, so nothing can follow the cursor
+    transaction
+        .execute(
+            &format!("DECLARE {} NO SCROLL CURSOR FOR {}", CURSOR_NAME, statement),
+            &[],
+        )
+        .await?;
+    // The simple protocol returns every value as text, whatever its type
+    let messages = transaction
+        .simple_query(&format!(
+            "FETCH FORWARD {} FROM {}",
+            u64::from(limit) + 1,
+            CURSOR_NAME
+        ))
+        .await?;
+    // Nothing was written, dropping the transaction would also roll it back
+    let _ = transaction.rollback().await;
+    Ok(messages)
+}
+
+/// Runs a statement on the primary of the instance.
+///
+/// The statement runs in a read-only transaction, as a role without access to table data,
+/// with the configured statement timeout. It is declared as a cursor, which only allows a
+/// single SELECT or VALUES statement, and at most `limit` rows are fetched from it.
+///
+/// # Arguments
+/// * `kube_client` - Kubernetes client
+/// * `config` - Application configuration
+/// * `namespace` - Instance namespace
+/// * `database` - Database to run the statement in
+/// * `statement` - Normalized statement
+/// * `limit` - Maximum number of rows
+pub async fn run_query(
+    kube_client: &KubeClient,
+    config: &Config,
+    namespace: &str,
+    database: &str,
+    statement: &str,
+    limit: u32,
+) -> Result<SqlQueryResult, Error> {
+    let role = find_managed_role(QUERY_ROLE).expect("The query role is a managed role");
+    let password = secret_value(
+        kube_client,
+        namespace,
+        &role.secret_name(namespace),
+        "password",
+    )
+    .await?;
+    let mut client = connect(
+        kube_client,
+        config,
+        namespace,
+        role.role,
+        &password,
+        database,
+    )
+    .await?;
+
+    let started = Instant::now();
+    let messages = fetch_statement(&mut client, config.sql_query_timeout_ms, statement, limit)
+        .await
+        .map_err(|e| query_error(namespace, e))?;
+
+    let (columns, mut rows) = collect_rows(messages);
+    let truncated = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+    Ok(SqlQueryResult {
+        columns,
+        rows,
+        truncated,
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_statement() {
+        assert_eq!(normalize_statement("  SELECT 1;\n"), Ok("SELECT 1"));
+        assert_eq!(normalize_statement("SELECT 1 ; ;"), Ok("SELECT 1"));
+        assert!(normalize_statement(" ; ").is_err());
+    }
+
+    // Needs a Postgres, e.g.
+    // SQL_TEST_DATABASE_URL="host=localhost user=postgres" cargo test -- --ignored
+    #[ignore]
+    #[tokio::test]
+    async fn test_fetch_statement() {
+        let url = std::env::var("SQL_TEST_DATABASE_URL").expect("SQL_TEST_DATABASE_URL is not set");
+        let (mut client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls)
+            .await
+            .expect("Failed to connect to Postgres");
+        tokio::spawn(connection);
+
+        let messages = fetch_statement(&mut client, 1000, "VALUES (1), (2), (3)", 2)
+            .await
+            .unwrap();
+        let (columns, rows) = collect_rows(messages);
+        assert_eq!(columns, vec!["column1"]);
+        // one row more than the limit, so the result can be marked truncated
+        assert_eq!(rows.len(), 3);
+
+        // Nothing may follow the statement the cursor is declared for
+        for statement in [
+            "SELECT 1; SELECT 2",
+            "SELECT 1; DROP TABLE pg_class",
+            "SELECT 1; COMMIT; CREATE TABLE escaped ()",
+        ] {
+            let statement = normalize_statement(statement).unwrap();
+            let e = fetch_statement(&mut client, 1000, statement, 10)
+                .await
+                .expect_err(statement);
+            assert_eq!(
+                e.as_db_error().map(|e| e.code()),
+                Some(&SqlState::SYNTAX_ERROR),
+                "{}: {}",
+                statement,
+                e
+            );
+        }
+
+        // Only queries can be declared as cursors
+        let e = fetch_statement(&mut client, 1000, "CREATE TABLE escaped ()", 10)
+            .await
+            .unwrap_err();
+        assert!(e.as_db_error().is_some(), "{}", e);
+    }
+}
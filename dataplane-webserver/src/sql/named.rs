@@ -0,0 +1,87 @@
+use crate::sql::types::NamedSqlQuery;
+
+// A diagnostic query support staff can run by name.
+// The queries only need the pg_monitor role, they don't read any table data.
+pub struct NamedQuery {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub query: &'static str,
+}
+
+pub const NAMED_QUERIES: &[NamedQuery] = &[
+    NamedQuery {
+        name: "activity",
+        description: "Sessions which aren't idle, longest running first",
+        query: r#"SELECT pid, usename, datname, application_name, client_addr, state, wait_event_type, wait_event, now() - xact_start AS transaction_age, now() - query_start AS query_age, left(query, 1000) AS query FROM pg_stat_activity WHERE state <> 'idle' AND pid <> pg_backend_pid() ORDER BY query_start NULLS LAST"#,
+    },
+    NamedQuery {
+        name: "connections",
+        description: "Sessions by database, user and state",
+        query: r#"SELECT datname, usename, state, count(*) AS connections FROM pg_stat_activity WHERE backend_type = 'client backend' GROUP BY datname, usename, state ORDER BY connections DESC"#,
+    },
+    NamedQuery {
+        name: "blocked_queries",
+        description: "Sessions waiting on a lock, and the sessions blocking them",
+        query: r#"SELECT blocked.pid AS blocked_pid, blocked.usename AS blocked_user, now() - blocked.query_start AS blocked_for, left(blocked.query, 1000) AS blocked_query, blocking.pid AS blocking_pid, blocking.usename AS blocking_user, blocking.state AS blocking_state, left(blocking.query, 1000) AS blocking_query FROM pg_stat_activity blocked JOIN pg_stat_activity blocking ON blocking.pid = ANY(pg_blocking_pids(blocked.pid)) ORDER BY blocked.query_start"#,
+    },
+    NamedQuery {
+        name: "locks",
+        description: "Locks held or awaited, by relation and mode",
+        query: r#"SELECT l.locktype, l.relation::regclass AS relation, l.mode, l.granted, count(*) AS locks FROM pg_locks l WHERE l.pid <> pg_backend_pid() GROUP BY l.locktype, l.relation, l.mode, l.granted ORDER BY l.granted, locks DESC"#,
+    },
+    NamedQuery {
+        name: "table_bloat",
+        description: "Estimated bloat of the largest tables, from their dead tuples",
+        query: r#"SELECT schemaname, relname, pg_size_pretty(pg_table_size(relid)) AS table_size, n_live_tup, n_dead_tup, round(100.0 * n_dead_tup / nullif(n_live_tup + n_dead_tup, 0), 1) AS dead_tuple_percent, pg_size_pretty((pg_table_size(relid) * n_dead_tup / nullif(n_live_tup + n_dead_tup, 0))::bigint) AS estimated_bloat, last_autovacuum, last_vacuum FROM pg_stat_user_tables ORDER BY pg_table_size(relid) * n_dead_tup / nullif(n_live_tup + n_dead_tup, 0) DESC NULLS LAST LIMIT 50"#,
+    },
+    NamedQuery {
+        name: "unused_indexes",
+        description: "Indexes which have never been scanned, largest first",
+        query: r#"SELECT schemaname, relname, indexrelname, pg_size_pretty(pg_relation_size(indexrelid)) AS index_size FROM pg_stat_user_indexes WHERE idx_scan = 0 ORDER BY pg_relation_size(indexrelid) DESC"#,
+    },
+    NamedQuery {
+        name: "database_sizes",
+        description: "Size of each database",
+        query: r#"SELECT datname, pg_size_pretty(pg_database_size(datname)) AS size FROM pg_database WHERE datallowconn ORDER BY pg_database_size(datname) DESC"#,
+    },
+    NamedQuery {
+        name: "replication",
+        description: "Replicas streaming from the primary, and their lag",
+        query: r#"SELECT application_name, client_addr, state, sync_state, write_lag, flush_lag, replay_lag, pg_size_pretty(pg_wal_lsn_diff(pg_current_wal_lsn(), replay_lsn)) AS replay_lag_size FROM pg_stat_replication"#,
+    },
+];
+
+pub fn find_named_query(name: &str) -> Option<&'static NamedQuery> {
+    NAMED_QUERIES.iter().find(|query| query.name == name)
+}
+
+pub fn named_queries() -> Vec<NamedSqlQuery> {
+    NAMED_QUERIES
+        .iter()
+        .map(|query| NamedSqlQuery {
+            name: query.name.to_string(),
+            description: query.description.to_string(),
+            query: query.query.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::normalize_statement;
+
+    #[test]
+    fn test_named_queries_are_valid() {
+        for named_query in NAMED_QUERIES {
+            assert_eq!(
+                normalize_statement(named_query.query),
+                Ok(named_query.query),
+                "{}",
+                named_query.name
+            );
+        }
+        assert!(find_named_query("table_bloat").is_some());
+        assert!(find_named_query("pg_stat_activity").is_none());
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SqlQueryRequest {
+    /// A single SELECT or VALUES statement
+    pub query: String,
+    /// Database to run the statement in, defaults to postgres
+    pub database: Option<String>,
+    /// Maximum number of rows, capped by the server's limit
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NamedSqlQueryRequest {
+    /// Database to run the query in, defaults to postgres
+    pub database: Option<String>,
+    /// Maximum number of rows, capped by the server's limit
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NamedSqlQuery {
+    /// Name used in the query's path
+    pub name: String,
+    pub description: String,
+    pub query: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SqlQueryResult {
+    /// Column names, in order
+    pub columns: Vec<String>,
+    /// Values in Postgres' text format, null for NULL
+    pub rows: Vec<Vec<Option<String>>>,
+    /// More rows were available than the limit
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
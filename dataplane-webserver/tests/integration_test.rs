@@ -7,9 +7,13 @@ mod tests {
     use super::*;
 
     use actix_web::test;
+    use dataplane_webserver::auth::policy::PolicyValidator;
+    use dataplane_webserver::auth::Authorizer;
     use dataplane_webserver::config;
+    use dataplane_webserver::metrics::limits::QueryRateLimiter;
     use dataplane_webserver::routes::health::{lively, ready};
-    use dataplane_webserver::routes::{metrics, root};
+    use dataplane_webserver::routes::{logs, metrics, root, sql};
+    use dataplane_webserver::sql::audit::SqlAuditLog;
     use reqwest::Url;
 
     #[actix_web::test]
@@ -77,7 +81,11 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(Authorizer::disabled()))
                 .app_data(web::Data::new(http_client.clone()))
+                .app_data(web::Data::new(QueryRateLimiter::new(
+                    cfg.prometheus_queries_per_minute,
+                )))
                 .service(web::scope("/{namespace}/metrics").service(metrics::query_range)),
         )
         .await;
@@ -151,6 +159,15 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         // It should be a client error if we try to request a namespace we do not own
         assert!(resp.status().is_client_error());
+
+        // It should be a client error to use a denied function
+        let query = "count_values(\"value\", pg_stat_activity_count{namespace=\"org-coredb-inst-control-plane-dev\"})";
+        let query_url = format_prometheus_query(url, query, start);
+        let req = test::TestRequest::get()
+            .uri(query_url.as_str())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
     }
 
     #[actix_web::test]
@@ -163,7 +180,11 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(Authorizer::disabled()))
                 .app_data(web::Data::new(http_client.clone()))
+                .app_data(web::Data::new(QueryRateLimiter::new(
+                    cfg.prometheus_queries_per_minute,
+                )))
                 .service(web::scope("/{namespace}/metrics").service(metrics::query)),
         )
         .await;
@@ -178,4 +199,143 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_logs_query_unauthorized() {
+        let cfg = config::Config::default();
+        let http_client = reqwest::Client::builder()
+            .build()
+            .expect("Failed to create HTTP client");
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(Authorizer::disabled()))
+                .app_data(web::Data::new(http_client.clone()))
+                .service(
+                    web::scope("/{namespace}/logs")
+                        .service(logs::query_range)
+                        .service(logs::query)
+                        .service(logs::tail),
+                ),
+        )
+        .await;
+
+        // Stream selectors for another namespace are rejected before Loki is queried
+        for path in ["query_range", "query", "tail"] {
+            let url = format!("/org-coredb-inst-control-plane-dev/logs/{}", path);
+            let query_url = format_prometheus_instant_query(
+                &url,
+                "{namespace=\"org-foobar-inst-control-plane-dev\"}",
+            );
+            let req = test::TestRequest::get()
+                .uri(query_url.as_str())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 403);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_scopes_required() {
+        let cfg = config::Config::default();
+        let http_client = reqwest::Client::builder()
+            .build()
+            .expect("Failed to create HTTP client");
+        let policy: PolicyValidator = r#"{"tokens": {"metrics-reader": {
+            "subject": "ci",
+            "organizations": ["org_coredb"],
+            "scopes": ["metrics:read"]
+        }}}"#
+            .parse()
+            .expect("Failed to parse policy");
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(Authorizer::new(Box::new(policy))))
+                .app_data(web::Data::new(http_client.clone()))
+                .service(web::scope("/{namespace}/logs").service(logs::query)),
+        )
+        .await;
+
+        let url = "/org-coredb-inst-control-plane-dev/logs/query";
+        let query_url = format_prometheus_instant_query(
+            url,
+            "{namespace=\"org-coredb-inst-control-plane-dev\"}",
+        );
+
+        // Requests without a known token are unauthenticated
+        for token in [None, Some("unknown")] {
+            let mut req = test::TestRequest::get().uri(query_url.as_str());
+            if let Some(token) = token {
+                req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), 401);
+        }
+
+        // The token may query metrics but not logs, denied before the instance is looked up
+        let req = test::TestRequest::get()
+            .uri(query_url.as_str())
+            .insert_header(("Authorization", "Bearer metrics-reader"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_sql_scope_required() {
+        let cfg = config::Config::default();
+        let policy: PolicyValidator = r#"{"tokens": {
+            "metrics-reader": {
+                "subject": "ci",
+                "organizations": ["org_coredb"],
+                "scopes": ["metrics:read", "logs:read"]
+            },
+            "other-org": {
+                "subject": "ci",
+                "organizations": ["org_other"],
+                "scopes": ["sql:read"]
+            }
+        }}"#
+        .parse()
+        .expect("Failed to parse policy");
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(Authorizer::new(Box::new(policy))))
+                .app_data(web::Data::new(SqlAuditLog::new(Box::new(std::io::sink()))))
+                .service(
+                    web::scope("/api/v1/orgs/{org_id}/instances/{instance_id}")
+                        .service(sql::query)
+                        .service(sql::run_named),
+                ),
+        )
+        .await;
+
+        let url = "/api/v1/orgs/org_coredb/instances/inst_control_plane_dev";
+        let payload = serde_json::json!({"query": "SELECT 1"});
+
+        let req = test::TestRequest::post()
+            .uri(&format!("{}/sql", url))
+            .set_json(&payload)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401);
+
+        // Neither token may run statements on the instance, denied before it is looked up
+        for token in ["metrics-reader", "other-org"] {
+            for path in ["sql", "sql/named/activity"] {
+                let req = test::TestRequest::post()
+                    .uri(&format!("{}/{}", url, path))
+                    .insert_header(("Authorization", format!("Bearer {}", token)))
+                    .set_json(&payload)
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), 403, "{} on {}", token, path);
+            }
+        }
+    }
 }
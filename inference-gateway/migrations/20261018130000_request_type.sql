@@ -0,0 +1,2 @@
+-- distinguish chat/text completions from embeddings, existing rows are all completions
+ALTER TABLE inference.requests ADD COLUMN request_type text not null DEFAULT 'completion';
@@ -0,0 +1,12 @@
+-- per-organization limits when instance_id is null, otherwise per-instance
+-- a null limit is unlimited
+CREATE TABLE IF NOT EXISTS inference.rate_limits (
+    organization_id text not null,
+    instance_id text,
+    requests_per_minute integer,
+    tokens_per_day bigint,
+    tokens_per_month bigint,
+    last_updated_at timestamp with time zone not null default now()
+);
+
+CREATE UNIQUE INDEX ON inference.rate_limits (organization_id, COALESCE(instance_id, ''));
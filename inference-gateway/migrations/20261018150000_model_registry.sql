@@ -0,0 +1,17 @@
+-- models served by the gateway, in addition to those in MODEL_SERVICE_PORT_MAP
+CREATE TABLE IF NOT EXISTS inference.models (
+    name text PRIMARY KEY,
+    -- base urls of the OpenAI compatible services hosting the model
+    backends text[] not null,
+    enabled bool not null default true,
+    -- organizations allowed to use the model, null allows every organization
+    allowed_organizations text[],
+    last_updated_at timestamp with time zone not null default now()
+);
+
+-- alternative names for models, in addition to those in MODEL_REWRITES
+CREATE TABLE IF NOT EXISTS inference.model_aliases (
+    alias text PRIMARY KEY,
+    model text not null,
+    last_updated_at timestamp with time zone not null default now()
+);
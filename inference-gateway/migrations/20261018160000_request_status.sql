@@ -0,0 +1,2 @@
+-- failed requests are logged too, only successful requests are billed
+ALTER TABLE inference.requests ADD COLUMN status_code integer not null DEFAULT 200;
@@ -0,0 +1,18 @@
+-- API keys resolve the organization and instance of a request from a bearer token
+-- only a sha256 hash of each key is stored
+CREATE TABLE IF NOT EXISTS inference.api_keys (
+    id text PRIMARY KEY,
+    key_hash text UNIQUE NOT NULL,
+    organization_id text not null,
+    -- null allows every instance of the organization
+    instance_id text,
+    name text,
+    created_at timestamp with time zone not null default now(),
+    expires_at timestamp with time zone,
+    revoked_at timestamp with time zone
+);
+
+CREATE INDEX ON inference.api_keys (organization_id);
+
+-- the key each request was made with, null for requests from the trusted proxy
+ALTER TABLE inference.requests ADD COLUMN api_key_id text;
@@ -0,0 +1,9 @@
+-- the organization of each instance, maintained by the control plane, so org-wide
+-- API keys can only be used for their organization's instances
+CREATE TABLE IF NOT EXISTS inference.instances (
+    instance_id text primary key,
+    organization_id text not null,
+    last_updated_at timestamp with time zone not null default now()
+);
+
+CREATE INDEX ON inference.instances (organization_id);
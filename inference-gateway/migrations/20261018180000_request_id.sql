@@ -0,0 +1,8 @@
+-- identifies each request, for per request billing events
+
+-- a volatile default on ADD COLUMN rewrites every partition under lock, so add the
+-- column without one, then set the default which only applies to new rows.
+-- existing rows are given an id in batches by db::backfill_request_ids, before
+-- 20261018180500_request_id_not_null makes the column not null
+ALTER TABLE inference.requests ADD COLUMN IF NOT EXISTS id uuid;
+ALTER TABLE inference.requests ALTER COLUMN id SET DEFAULT gen_random_uuid();
@@ -0,0 +1,5 @@
+-- every row has an id once db::backfill_request_ids has run, this only scans the table
+ALTER TABLE inference.requests ALTER COLUMN id SET NOT NULL;
+
+-- finds the request a per request billing event was sent for
+CREATE INDEX IF NOT EXISTS requests_id_idx ON inference.requests (id);
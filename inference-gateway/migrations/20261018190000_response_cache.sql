@@ -0,0 +1,27 @@
+-- orgs and models opt in to caching responses to deterministic requests
+-- null organization_id or model applies to every org or model, the most specific row wins
+CREATE TABLE IF NOT EXISTS inference.response_cache_settings (
+    organization_id text,
+    model text,
+    enabled bool not null default true,
+    -- overrides RESPONSE_CACHE_TTL_SEC
+    ttl_sec integer,
+    last_updated_at timestamp with time zone not null default now()
+);
+
+CREATE UNIQUE INDEX ON inference.response_cache_settings (COALESCE(organization_id, ''), COALESCE(model, ''));
+
+-- responses when RESPONSE_CACHE_STORE=postgres, keyed on a hash of the org, model and normalized request
+CREATE TABLE IF NOT EXISTS inference.response_cache (
+    key text PRIMARY KEY,
+    organization_id text not null,
+    model text not null,
+    response jsonb not null,
+    created_at timestamp with time zone not null default now(),
+    expires_at timestamp with time zone not null
+);
+
+CREATE INDEX ON inference.response_cache (expires_at);
+
+-- requests served from the cache are recorded with zero tokens
+ALTER TABLE inference.requests ADD COLUMN cache_hit bool not null DEFAULT false;
@@ -0,0 +1,56 @@
+-- orgs which record their prompts and completions, when AUDIT_LOG_ENABLED is set
+CREATE TABLE IF NOT EXISTS inference.audit_settings (
+    organization_id text PRIMARY KEY,
+    enabled bool not null default true,
+    -- at most the retention policy of inference.audit_log below
+    retention_days integer not null default 30 CHECK (retention_days BETWEEN 1 AND 365),
+    last_updated_at timestamp with time zone not null default now()
+);
+
+-- matches are replaced before prompts and completions are stored
+-- a null organization_id applies to every org
+CREATE TABLE IF NOT EXISTS inference.audit_redaction_rules (
+    id serial PRIMARY KEY,
+    organization_id text,
+    -- rust regex syntax
+    pattern text not null,
+    replacement text not null default '[REDACTED]',
+    last_updated_at timestamp with time zone not null default now()
+);
+
+INSERT INTO inference.audit_redaction_rules (organization_id, pattern, replacement)
+VALUES
+    (NULL, '[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}', '[EMAIL]'),
+    (NULL, '\b\d{3}-\d{2}-\d{4}\b', '[SSN]'),
+    (NULL, '\b(?:\d[ -]?){13,16}\b', '[CARD]');
+
+CREATE TABLE IF NOT EXISTS inference.audit_log (
+    organization_id text not null,
+    instance_id text not null,
+    api_key_id text,
+    model text not null,
+    request_type text not null,
+    status_code integer not null,
+    prompt jsonb not null,
+    completion jsonb,
+    completed_at timestamp with time zone not null default now()
+) PARTITION BY RANGE (completed_at);
+
+CREATE INDEX ON inference.audit_log (organization_id, completed_at);
+
+SELECT enable_ts_table(
+    target_table_id => 'inference.audit_log',
+    partition_duration => '1 day',
+    partition_lead_time => '7 days'
+);
+
+-- partitions older than the longest retention are dropped
+SELECT set_ts_retention_policy('inference.audit_log', '1 year');
+
+-- entries older than their org's retention are deleted every hour
+SELECT cron.schedule('purge-audit-log', '0 * * * *', $$
+    DELETE FROM inference.audit_log AS log
+    USING inference.audit_settings AS settings
+    WHERE log.organization_id = settings.organization_id
+        AND log.completed_at < now() - make_interval(days => settings.retention_days);
+$$);
@@ -0,0 +1,6 @@
+-- the aggregation each hour was reported to billing with, so a backfill can't
+-- re-send an hour with the other aggregation's idempotency keys
+CREATE TABLE billing.reported_hours (
+    hour timestamp with time zone PRIMARY KEY,
+    aggregation text NOT NULL
+);
@@ -0,0 +1,320 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::errors::{AuthError, PlatformError};
+use crate::routes::forward::required_header;
+
+const KEY_PREFIX: &str = "tembo_";
+
+/// An active API key, scoped to an organization and optionally a single instance
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKey {
+    pub id: String,
+    pub organization_id: String,
+    /// None allows every instance of the organization in `inference.instances`, which is then
+    /// taken from `X-TEMBO-INSTANCE`
+    pub instance_id: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The organization and instance a request is attributed to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Caller {
+    pub organization_id: String,
+    pub instance_id: String,
+    pub api_key_id: Option<String>,
+    /// The only instance the caller's API key may act on, None for org-wide keys
+    pub instance_scope: Option<String>,
+}
+
+/// Hashes of the keys which have not been revoked, and the organization of each instance,
+/// reloaded by the cache refresher
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: RwLock<HashMap<String, ApiKey>>,
+    instances: RwLock<HashMap<String, String>>,
+}
+
+impl ApiKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn refresh(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT id, key_hash, organization_id, instance_id, expires_at
+            FROM inference.api_keys
+            WHERE revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > now())"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        log::debug!("Refreshing API keys with {} rows", rows.len());
+        let keys = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.key_hash,
+                    ApiKey {
+                        id: row.id,
+                        organization_id: row.organization_id,
+                        instance_id: row.instance_id,
+                        expires_at: row.expires_at,
+                    },
+                )
+            })
+            .collect();
+        *self.keys.write().await = keys;
+
+        let rows = sqlx::query!("SELECT instance_id, organization_id FROM inference.instances")
+            .fetch_all(pool)
+            .await?;
+        *self.instances.write().await = rows
+            .into_iter()
+            .map(|row| (row.instance_id, row.organization_id))
+            .collect();
+        Ok(())
+    }
+
+    /// Find the key for a bearer token
+    pub async fn authenticate(&self, token: &str, now: DateTime<Utc>) -> Result<ApiKey, AuthError> {
+        let keys = self.keys.read().await;
+        let key = keys
+            .get(&hash_key(token))
+            .ok_or_else(|| AuthError::NotAuthorized("Invalid API key".to_string()))?;
+        match key.expires_at {
+            Some(expires_at) if expires_at <= now => {
+                Err(AuthError::NotAuthorized("API key has expired".to_string()))
+            }
+            _ => Ok(key.clone()),
+        }
+    }
+
+    /// The caller a key resolves to. Org-wide keys take the instance from `X-TEMBO-INSTANCE`,
+    /// which must be one of the organization's instances, so usage can't be attributed to
+    /// another organization's instance or spread across made up ones.
+    pub async fn caller_for_key(
+        &self,
+        key: ApiKey,
+        req: &HttpRequest,
+    ) -> Result<Caller, PlatformError> {
+        let instance_id = match &key.instance_id {
+            Some(instance_id) => instance_id.clone(),
+            None => {
+                let instance_id = required_header(req, "X-TEMBO-INSTANCE")?;
+                let instances = self.instances.read().await;
+                if instances.get(instance_id) != Some(&key.organization_id) {
+                    return Err(AuthError::Forbidden(format!(
+                        "Instance {} does not belong to the API key's organization",
+                        instance_id
+                    ))
+                    .into());
+                }
+                instance_id.to_string()
+            }
+        };
+        Ok(Caller {
+            organization_id: key.organization_id,
+            instance_id,
+            api_key_id: Some(key.id),
+            instance_scope: key.instance_id,
+        })
+    }
+}
+
+/// Resolve the caller from the request's API key when API key auth is enabled,
+/// otherwise trust the `X-TEMBO-ORG` and `X-TEMBO-INSTANCE` headers
+pub async fn resolve_caller(
+    req: &HttpRequest,
+    config: &Config,
+    keys: &ApiKeyStore,
+) -> Result<Caller, PlatformError> {
+    if !config.api_key_auth_enabled {
+        return Ok(Caller {
+            organization_id: required_header(req, "X-TEMBO-ORG")?.to_string(),
+            instance_id: required_header(req, "X-TEMBO-INSTANCE")?.to_string(),
+            api_key_id: None,
+            instance_scope: None,
+        });
+    }
+
+    let token = bearer_token(req)?;
+    let key = keys.authenticate(token, Utc::now()).await?;
+    keys.caller_for_key(key, req).await
+}
+
+fn bearer_token(req: &HttpRequest) -> Result<&str, AuthError> {
+    let header = req
+        .headers()
+        .get("Authorization")
+        .ok_or_else(|| AuthError::NotAuthorized("Missing API key".to_string()))?
+        .to_str()
+        .map_err(|_| AuthError::ParsingError("Invalid Authorization header".to_string()))?;
+    header
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| AuthError::TokenError("Expected a bearer token".to_string()))
+}
+
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// A new random key, only its hash is stored
+pub fn generate_key() -> String {
+    format!(
+        "{}{}{}",
+        KEY_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Store a new key, returning its id and the key to hand to the user
+pub async fn create_key(
+    pool: &PgPool,
+    organization_id: &str,
+    instance_id: Option<&str>,
+    name: Option<&str>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(String, String), sqlx::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let key = generate_key();
+    sqlx::query!(
+        "INSERT INTO inference.api_keys ( id, key_hash, organization_id, instance_id, name, expires_at )
+        VALUES ($1, $2, $3, $4, $5, $6)",
+        id,
+        hash_key(&key),
+        organization_id,
+        instance_id,
+        name,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok((id, key))
+}
+
+/// Revoke a key, returning false when there is no active key with the id
+pub async fn revoke_key(pool: &PgPool, id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE inference.api_keys
+        SET revoked_at = now()
+        WHERE id = $1 AND revoked_at IS NULL",
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::Duration;
+
+    async fn store_with(token: &str, expires_at: Option<DateTime<Utc>>) -> ApiKeyStore {
+        let store = ApiKeyStore::new();
+        store.keys.write().await.insert(
+            hash_key(token),
+            ApiKey {
+                id: "key-1".to_string(),
+                organization_id: "org".to_string(),
+                instance_id: None,
+                expires_at,
+            },
+        );
+        store
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let key = generate_key();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + 64);
+        assert_ne!(key, generate_key());
+        assert_eq!(hash_key(&key), hash_key(&key));
+        assert_eq!(hash_key(&key).len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let now = Utc::now();
+        let store = store_with("tembo_valid", Some(now + Duration::days(1))).await;
+        assert_eq!(
+            store.authenticate("tembo_valid", now).await.unwrap().id,
+            "key-1"
+        );
+        assert!(store.authenticate("tembo_other", now).await.is_err());
+        // keys which expire between refreshes are rejected
+        assert!(store
+            .authenticate("tembo_valid", now + Duration::days(2))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_org_wide_key_instances() {
+        let store = store_with("tembo_valid", None).await;
+        store.instances.write().await.extend([
+            ("inst".to_string(), "org".to_string()),
+            ("foreign-inst".to_string(), "other-org".to_string()),
+        ]);
+        let key = store.authenticate("tembo_valid", Utc::now()).await.unwrap();
+
+        let req = TestRequest::default()
+            .insert_header(("X-TEMBO-INSTANCE", "inst"))
+            .to_http_request();
+        let caller = store.caller_for_key(key.clone(), &req).await.unwrap();
+        assert_eq!(caller.organization_id, "org");
+        assert_eq!(caller.instance_id, "inst");
+
+        // another organization's instance, or one which doesn't exist, is forbidden
+        for instance in ["foreign-inst", "made-up-inst"] {
+            let req = TestRequest::default()
+                .insert_header(("X-TEMBO-INSTANCE", instance))
+                .to_http_request();
+            let err = store.caller_for_key(key.clone(), &req).await.unwrap_err();
+            assert!(
+                matches!(err, PlatformError::AuthError(AuthError::Forbidden(_))),
+                "{instance}: {err}"
+            );
+        }
+
+        // instance scoped keys ignore the header
+        let scoped = ApiKey {
+            instance_id: Some("inst".to_string()),
+            ..key
+        };
+        let req = TestRequest::default()
+            .insert_header(("X-TEMBO-INSTANCE", "foreign-inst"))
+            .to_http_request();
+        let caller = store.caller_for_key(scoped, &req).await.unwrap();
+        assert_eq!(caller.instance_id, "inst");
+        assert_eq!(caller.instance_scope.as_deref(), Some("inst"));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Bearer tembo_abc"))
+            .to_http_request();
+        assert_eq!(bearer_token(&req).unwrap(), "tembo_abc");
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Basic dXNlcjpwYXNz"))
+            .to_http_request();
+        assert!(bearer_token(&req).is_err());
+
+        let req = TestRequest::default().to_http_request();
+        assert!(bearer_token(&req).is_err());
+    }
+}
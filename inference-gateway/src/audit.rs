@@ -0,0 +1,264 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+
+/// A regex, and what its matches are replaced with before prompts and completions are stored
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+/// A request to be recorded in `inference.audit_log`
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub organization_id: String,
+    pub instance_id: String,
+    pub api_key_id: Option<String>,
+    pub model: String,
+    pub request_type: String,
+    pub status_code: i32,
+    pub prompt: serde_json::Value,
+    pub completion: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub completed_at: String,
+    pub organization_id: String,
+    pub instance_id: String,
+    pub api_key_id: Option<String>,
+    pub model: String,
+    pub request_type: String,
+    pub status_code: i32,
+    pub prompt: serde_json::Value,
+    pub completion: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditFilter {
+    pub instance_id: Option<String>,
+    pub model: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub limit: i64,
+}
+
+/// Audit log of prompts and completions, for the orgs which opted in through
+/// `inference.audit_settings`. Settings and redaction rules are reloaded by the cache refresher.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    enabled: bool,
+    orgs: RwLock<HashSet<String>>,
+    // rules for every org are under None
+    rules: RwLock<HashMap<Option<String>, Vec<RedactionRule>>>,
+}
+
+impl AuditLog {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            enabled: cfg.audit_log_enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub async fn refresh(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let rows =
+            sqlx::query!("SELECT organization_id FROM inference.audit_settings WHERE enabled")
+                .fetch_all(pool)
+                .await?;
+        let orgs: HashSet<String> = rows.into_iter().map(|row| row.organization_id).collect();
+
+        let rows = sqlx::query!(
+            "SELECT organization_id, pattern, replacement FROM inference.audit_redaction_rules ORDER BY id"
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut rules: HashMap<Option<String>, Vec<RedactionRule>> = HashMap::new();
+        for row in rows {
+            match Regex::new(&row.pattern) {
+                Ok(pattern) => rules
+                    .entry(row.organization_id)
+                    .or_default()
+                    .push(RedactionRule {
+                        pattern,
+                        replacement: row.replacement,
+                    }),
+                Err(e) => log::error!("invalid redaction rule {}: {}", row.pattern, e),
+            }
+        }
+
+        log::debug!("Refreshing audit log settings with {} orgs", orgs.len());
+        *self.orgs.write().await = orgs;
+        *self.rules.write().await = rules;
+        Ok(())
+    }
+
+    pub async fn enabled_for(&self, org_id: &str) -> bool {
+        self.enabled && self.orgs.read().await.contains(org_id)
+    }
+
+    /// Redact and store the entry, failures are logged and don't fail the request
+    pub async fn record(&self, pool: &PgPool, mut entry: AuditEntry) {
+        {
+            let rules = self.rules.read().await;
+            let applicable: Vec<&RedactionRule> = rules
+                .get(&None)
+                .into_iter()
+                .chain(rules.get(&Some(entry.organization_id.clone())))
+                .flatten()
+                .collect();
+            redact(&mut entry.prompt, &applicable);
+            if let Some(completion) = entry.completion.as_mut() {
+                redact(completion, &applicable);
+            }
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO inference.audit_log ( organization_id, instance_id, api_key_id, model, request_type, status_code, prompt, completion )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            entry.organization_id,
+            entry.instance_id,
+            entry.api_key_id,
+            entry.model,
+            entry.request_type,
+            entry.status_code,
+            entry.prompt,
+            entry.completion
+        )
+        .execute(pool)
+        .await
+        {
+            log::error!("Failed to write audit log: {}", e);
+        }
+    }
+}
+
+/// Most recent entries first
+pub async fn query(
+    pool: &PgPool,
+    org_id: &str,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditRecord>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT completed_at, organization_id, instance_id, api_key_id, model, request_type, status_code, prompt, completion
+        FROM inference.audit_log
+        WHERE organization_id = $1
+            AND completed_at >= $2
+            AND completed_at <= $3
+            AND ($4::text IS NULL OR instance_id = $4)
+            AND ($5::text IS NULL OR model = $5)
+        ORDER BY completed_at DESC
+        LIMIT $6",
+        org_id,
+        filter.start,
+        filter.end,
+        filter.instance_id,
+        filter.model,
+        filter.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AuditRecord {
+            completed_at: row.completed_at.to_rfc3339(),
+            organization_id: row.organization_id,
+            instance_id: row.instance_id,
+            api_key_id: row.api_key_id,
+            model: row.model,
+            request_type: row.request_type,
+            status_code: row.status_code,
+            prompt: row.prompt,
+            completion: row.completion,
+        })
+        .collect())
+}
+
+/// Apply the rules to every string in the value
+fn redact(value: &mut serde_json::Value, rules: &[&RedactionRule]) {
+    match value {
+        serde_json::Value::String(s) => {
+            for rule in rules {
+                if rule.pattern.is_match(s) {
+                    *s = rule
+                        .pattern
+                        .replace_all(s, rule.replacement.as_str())
+                        .into_owned();
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                redact(value, rules);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                redact(value, rules);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(pattern: &str, replacement: &str) -> RedactionRule {
+        RedactionRule {
+            pattern: Regex::new(pattern).unwrap(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_redact() {
+        let email = rule(r"[\w.+-]+@[\w-]+\.[\w.]+", "[EMAIL]");
+        let ssn = rule(r"\b\d{3}-\d{2}-\d{4}\b", "[SSN]");
+        let mut prompt = json!({
+            "model": "llama",
+            "temperature": 0,
+            "messages": [
+                {"role": "user", "content": "Email jane.doe@example.com about 123-45-6789"},
+                {"role": "assistant", "content": "Sure"}
+            ]
+        });
+
+        redact(&mut prompt, &[&email, &ssn]);
+
+        assert_eq!(
+            prompt["messages"][0]["content"],
+            "Email [EMAIL] about [SSN]"
+        );
+        assert_eq!(prompt["messages"][1]["content"], "Sure");
+        assert_eq!(prompt["temperature"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_enabled_for() {
+        let log = AuditLog {
+            enabled: true,
+            ..Default::default()
+        };
+        log.orgs.write().await.insert("org".to_string());
+        assert!(log.enabled_for("org").await);
+        assert!(!log.enabled_for("other").await);
+
+        let disabled = AuditLog::default();
+        disabled.orgs.write().await.insert("org".to_string());
+        assert!(!disabled.enabled_for("org").await);
+    }
+}
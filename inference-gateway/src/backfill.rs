@@ -0,0 +1,51 @@
+//! Re-send billing events for a time range
+//!
+//! gateway-backfill <start> <end>
+//!
+//! e.g. gateway-backfill 2024-10-01T00:00:00Z 2024-10-02T00:00:00Z
+//!
+//! or give the requests logged before `inference.requests.id` was added an id, which the gateway
+//! also does when it runs the migrations
+//!
+//! gateway-backfill request-ids
+
+use chrono::{DateTime, Utc};
+use gateway::config::Config;
+use gateway::db;
+use gateway::events_reporter::backfill_events;
+use log::info;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command] = args.as_slice() {
+        if command == "request-ids" {
+            let cfg = Config::new().await;
+            let pool = db::connect(&cfg.pg_conn_str, 1).await?;
+            let updated = db::backfill_request_ids(&pool).await?;
+            info!("Gave {} requests an id", updated);
+            return Ok(());
+        }
+    }
+    let [start, end] = args.as_slice() else {
+        eprintln!("usage: gateway-backfill <start> <end>, as RFC 3339 timestamps, or gateway-backfill request-ids");
+        std::process::exit(2);
+    };
+    let start: DateTime<Utc> = DateTime::parse_from_rfc3339(start)?.with_timezone(&Utc);
+    let end: DateTime<Utc> = DateTime::parse_from_rfc3339(end)?.with_timezone(&Utc);
+    if start >= end {
+        eprintln!("start must be before end");
+        std::process::exit(2);
+    }
+
+    let cfg = Config::new().await;
+    info!(
+        "Backfilling {:?} billing events from {} to {} to {}",
+        cfg.billing_aggregation, start, end, cfg.billing_queue_name
+    );
+    let hours = backfill_events(&cfg, start, end).await?;
+    info!("Enqueued billing events for {} hours", hours);
+    Ok(())
+}
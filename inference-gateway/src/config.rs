@@ -3,26 +3,72 @@ use std::env;
 
 use url::Url;
 
+use crate::cache::CacheStore;
 use crate::errors::PlatformError;
+use crate::events_reporter::BillingAggregation;
+use crate::registry::ModelRegistry;
+use crate::routing::BalanceStrategy;
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub model_rewrites: HashMap<String, String>,
-    pub model_service_map: HashMap<String, Url>,
+    pub model_service_map: HashMap<String, Vec<Url>>,
     /// Postgres connection string to the timeseries database which logs token usage
     pub pg_conn_str: String,
     /// Postgres connection string for the Control Plane queue
     pub billing_queue_conn_str: String,
     /// Port to run the inference gateway on
     pub server_port: u16,
+    /// Port serving `/metrics` and `/backends`, which must not be exposed publicly
+    pub internal_port: u16,
     /// Number of actix workers to spawn
     pub server_workers: u16,
     /// Boolean to toggle billing request authorization.
     /// When true, callers must have an active payment method on file
     pub org_auth_enabled: bool,
-    /// Interval to refresh the billing authorization cache
+    /// Resolve the org and instance from an API key in the `Authorization` header,
+    /// instead of trusting the `X-TEMBO-ORG` and `X-TEMBO-INSTANCE` headers
+    pub api_key_auth_enabled: bool,
+    /// Interval to refresh the billing authorization cache, rate limits and API keys
     pub org_auth_cache_refresh_interval_sec: u64,
+    /// Enforce the per org and per instance limits in `inference.rate_limits`
+    pub rate_limits_enabled: bool,
+    /// Number of gateway replicas, requests per minute are counted per replica
+    /// so each one allows this share of the limit
+    pub rate_limit_replicas: i32,
     pub run_billing_reporter: bool,
+    /// Queue on the Control Plane which billing events are sent to
+    pub billing_queue_name: String,
+    /// Interval between billing reporter runs, each run reports every complete hour since the last
+    pub billing_reporter_period_sec: u64,
+    /// Report usage per request or as hourly rollups, `request` or `hourly`
+    pub billing_aggregation: BillingAggregation,
+    /// How requests are spread across the backends of a model, `round_robin` or `least_outstanding`
+    pub balance_strategy: BalanceStrategy,
+    /// Interval between `/health` checks of every backend
+    pub health_check_interval_sec: u64,
+    /// Consecutive errors after which a backend stops receiving requests
+    pub eject_after_failures: u32,
+    /// How long an ejected backend stops receiving requests
+    pub ejection_sec: u64,
+    /// Number of times a request which never reached a model server is retried on another backend
+    pub upstream_retries: u32,
+    pub upstream_connect_timeout_sec: u64,
+    /// Timeout for non-streamed requests to model servers
+    pub upstream_timeout_sec: u64,
+    /// Interval to reload models and aliases from the database
+    pub model_registry_refresh_interval_sec: u64,
+    /// Where responses to deterministic requests are cached, `disabled`, `memory` or `postgres`.
+    /// Orgs and models opt in through `inference.response_cache_settings`.
+    pub response_cache_store: CacheStore,
+    /// How long responses are cached, unless the setting for the org or model overrides it
+    pub response_cache_ttl_sec: u64,
+    /// Responses kept by the `memory` store, per replica
+    pub response_cache_max_entries: usize,
+    /// Record prompts and completions of the orgs enabled in `inference.audit_settings`
+    pub audit_log_enabled: bool,
+    /// OTLP endpoint to export request spans to, spans are not exported when unset
+    pub opentelemetry_endpoint_url: Option<String>,
 }
 
 impl Config {
@@ -41,21 +87,81 @@ impl Config {
             server_port: from_env_default("WEBSERVER_PORT", "8080")
                 .parse::<u16>()
                 .unwrap_or(8080),
+            internal_port: from_env_default("INTERNAL_PORT", "8081")
+                .parse::<u16>()
+                .unwrap_or(8081),
             server_workers: from_env_default("WEBSERVER_WORKERS", "8")
                 .parse::<u16>()
                 .unwrap_or(8),
             org_auth_enabled: from_env_default("ORG_AUTH_ENABLED", "false")
                 .parse()
                 .expect("ORG_AUTH_ENABLED must be a boolean"),
+            api_key_auth_enabled: from_env_default("API_KEY_AUTH_ENABLED", "false")
+                .parse()
+                .expect("API_KEY_AUTH_ENABLED must be a boolean"),
             org_auth_cache_refresh_interval_sec: from_env_default(
                 "ORG_AUTH_CACHE_REFRESH_INTERVAL_SEC",
                 "10",
             )
             .parse()
             .expect("ORG_AUTH_CACHE_REFRESH_INTERVAL_SEC must be an integer"),
+            rate_limits_enabled: from_env_default("RATE_LIMITS_ENABLED", "false")
+                .parse()
+                .expect("RATE_LIMITS_ENABLED must be a boolean"),
+            rate_limit_replicas: from_env_default("RATE_LIMIT_REPLICAS", "1")
+                .parse()
+                .expect("RATE_LIMIT_REPLICAS must be an integer"),
             run_billing_reporter: from_env_default("RUN_BILLING_REPORTER", "false")
                 .parse()
                 .unwrap(),
+            billing_queue_name: from_env_default("BILLING_QUEUE_NAME", "billing_aws_data_1_use1"),
+            billing_reporter_period_sec: from_env_default("BILLING_REPORTER_PERIOD_SEC", "3600")
+                .parse()
+                .expect("BILLING_REPORTER_PERIOD_SEC must be an integer"),
+            billing_aggregation: from_env_default("BILLING_AGGREGATION", "hourly")
+                .parse()
+                .expect("BILLING_AGGREGATION must be request or hourly"),
+            balance_strategy: from_env_default("LOAD_BALANCING_STRATEGY", "round_robin")
+                .parse()
+                .expect("LOAD_BALANCING_STRATEGY must be round_robin or least_outstanding"),
+            health_check_interval_sec: from_env_default("HEALTH_CHECK_INTERVAL_SEC", "10")
+                .parse()
+                .expect("HEALTH_CHECK_INTERVAL_SEC must be an integer"),
+            eject_after_failures: from_env_default("EJECT_AFTER_FAILURES", "3")
+                .parse()
+                .expect("EJECT_AFTER_FAILURES must be an integer"),
+            ejection_sec: from_env_default("EJECTION_SEC", "30")
+                .parse()
+                .expect("EJECTION_SEC must be an integer"),
+            upstream_retries: from_env_default("UPSTREAM_RETRIES", "1")
+                .parse()
+                .expect("UPSTREAM_RETRIES must be an integer"),
+            upstream_connect_timeout_sec: from_env_default("UPSTREAM_CONNECT_TIMEOUT_SEC", "5")
+                .parse()
+                .expect("UPSTREAM_CONNECT_TIMEOUT_SEC must be an integer"),
+            upstream_timeout_sec: from_env_default("UPSTREAM_TIMEOUT_SEC", "300")
+                .parse()
+                .expect("UPSTREAM_TIMEOUT_SEC must be an integer"),
+            model_registry_refresh_interval_sec: from_env_default(
+                "MODEL_REGISTRY_REFRESH_INTERVAL_SEC",
+                "30",
+            )
+            .parse()
+            .expect("MODEL_REGISTRY_REFRESH_INTERVAL_SEC must be an integer"),
+            response_cache_store: from_env_default("RESPONSE_CACHE_STORE", "disabled")
+                .parse()
+                .expect("RESPONSE_CACHE_STORE must be disabled, memory or postgres"),
+            response_cache_ttl_sec: from_env_default("RESPONSE_CACHE_TTL_SEC", "3600")
+                .parse()
+                .expect("RESPONSE_CACHE_TTL_SEC must be an integer"),
+            response_cache_max_entries: from_env_default("RESPONSE_CACHE_MAX_ENTRIES", "10000")
+                .parse()
+                .expect("RESPONSE_CACHE_MAX_ENTRIES must be an integer"),
+            audit_log_enabled: from_env_default("AUDIT_LOG_ENABLED", "false")
+                .parse()
+                .expect("AUDIT_LOG_ENABLED must be a boolean"),
+            opentelemetry_endpoint_url: Some(from_env_default("OPENTELEMETRY_ENDPOINT_URL", ""))
+                .filter(|url| !url.is_empty()),
         }
     }
 }
@@ -68,15 +174,16 @@ fn from_env_default(key: &str, default: &str) -> String {
 /// MODEL_NAME_SERVICE_PORT_MAP -- a comma separate list of model names and the host:port they are served at
 /// <model-name>=<host>:<port>,<model-name>=<host>:<port>
 /// e.g. meta-llama/Meta-Llama-3-8B-Instruct=llama-3-8b-instruct:8000,meta-llama/Llama-3.1-8B-Instruct=llama-3-1-8b-instruct:8000,
+/// A model listed more than once is load balanced across all of its services.
 /// Must be an OpenAI compatible interface
-fn parse_model_service_port_map() -> HashMap<String, Url> {
+fn parse_model_service_port_map() -> HashMap<String, Vec<Url>> {
     let model_mappings_values = from_env_default(
         "MODEL_SERVICE_PORT_MAP",
         "facebook/opt-125m=http://vllm:8000",
     );
 
     // Initialize an empty HashMap to store model-service-port mappings
-    let mut model_map: HashMap<String, Url> = HashMap::new();
+    let mut model_map: HashMap<String, Vec<Url>> = HashMap::new();
 
     // Split the environment variable value by semicolon to get individual mappings
     for mapping in model_mappings_values.split(',') {
@@ -84,7 +191,10 @@ fn parse_model_service_port_map() -> HashMap<String, Url> {
         if let Some((model_name, service_port)) = mapping.split_once('=') {
             let svc_port_url = Url::parse(service_port)
                 .unwrap_or_else(|_| panic!("malformed service: {service_port}"));
-            model_map.insert(model_name.to_string(), svc_port_url);
+            model_map
+                .entry(model_name.to_string())
+                .or_default()
+                .push(svc_port_url);
         }
     }
     model_map
@@ -108,43 +218,36 @@ fn parse_model_rewrite() -> HashMap<String, String> {
 pub struct MappedRequest {
     // the mapped model name
     pub model: String,
-    // url to the correct service for the model
-    pub base_url: Url,
+    // urls of the services for the model
+    pub backends: Vec<Url>,
     // request body with updated model name
     pub body: serde_json::Value,
 }
 
 pub fn rewrite_model_request(
     mut body: serde_json::Value,
-    config: &Config,
+    registry: &ModelRegistry,
+    org_id: &str,
 ) -> Result<MappedRequest, PlatformError> {
-    // map the model, if there is a mapping for it
-    let target_model = if let Some(model) = body.get("model") {
-        let requested_model = model.as_str().ok_or_else(|| {
+    let requested_model = body
+        .get("model")
+        .ok_or_else(|| {
+            PlatformError::InvalidQuery("missing `model` parameter in request body".to_string())
+        })?
+        .as_str()
+        .ok_or_else(|| {
             PlatformError::InvalidQuery("empty value in `model` parameter".to_string())
         })?;
 
-        if let Some(rewritten_model) = config.model_rewrites.get(requested_model) {
-            body["model"] = serde_json::Value::String(rewritten_model.clone());
-            rewritten_model
-        } else {
-            requested_model
-        }
-    } else {
-        Err(PlatformError::InvalidQuery(
-            "missing `model` parameter in request body".to_string(),
-        ))?
-    };
-
-    let base_url = config
-        .model_service_map
-        .get(target_model)
-        .ok_or_else(|| PlatformError::InvalidQuery(format!("model {} not found", target_model)))?
-        .clone();
+    // map the model, if it was requested by an alias
+    let target = registry.resolve(requested_model, org_id)?;
+    if target.name != requested_model {
+        body["model"] = serde_json::Value::String(target.name.clone());
+    }
 
     Ok(MappedRequest {
-        model: target_model.to_string(),
-        base_url,
+        model: target.name.clone(),
+        backends: target.backends.clone(),
         body,
     })
 }
@@ -163,14 +266,15 @@ mod tests {
         );
 
         let cfg = Config::new().await;
+        let registry = ModelRegistry::from_config(&cfg);
         let body = serde_json::json!({
             "model": "cat",
             "key": "value"
         });
 
-        let rewritten = rewrite_model_request(body.clone(), &cfg).unwrap();
+        let rewritten = rewrite_model_request(body.clone(), &registry, "org").unwrap();
         assert_eq!(rewritten.model, "dog");
-        assert_eq!(rewritten.base_url.to_string(), "http://dog:8000/");
+        assert_eq!(rewritten.backends[0].to_string(), "http://dog:8000/");
         assert_eq!(rewritten.body.get("key").unwrap(), "value");
 
         let body = serde_json::json!({
@@ -178,9 +282,9 @@ mod tests {
             "key": "value2"
         });
 
-        let rewritten = rewrite_model_request(body.clone(), &cfg).unwrap();
+        let rewritten = rewrite_model_request(body.clone(), &registry, "org").unwrap();
         assert_eq!(rewritten.model, "young");
-        assert_eq!(rewritten.base_url.to_string(), "http://young:8000/");
+        assert_eq!(rewritten.backends[0].to_string(), "http://young:8000/");
         assert_eq!(rewritten.body.get("key").unwrap(), "value2");
     }
 
@@ -223,7 +327,7 @@ mod tests {
         let mut expected = HashMap::new();
         expected.insert(
             "facebook/opt-125m".to_string(),
-            Url::parse("http://vllm:8000").unwrap(),
+            vec![Url::parse("http://vllm:8000").unwrap()],
         );
         assert_eq!(result, expected);
     }
@@ -236,12 +340,32 @@ mod tests {
         let mut expected = HashMap::new();
         expected.insert(
             "meta-llama/Meta-Llama-3-8B-Instruct".to_string(),
-            Url::parse("http://tembo-ai-dev-llama-3-8b-instruct.svc.cluster.local:8000").unwrap(),
+            vec![
+                Url::parse("http://tembo-ai-dev-llama-3-8b-instruct.svc.cluster.local:8000")
+                    .unwrap(),
+            ],
         );
 
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_multiple_backends() {
+        env::set_var(
+            "MODEL_SERVICE_PORT_MAP",
+            "facebook/opt-125m=http://vllm-0:8000,facebook/opt-125m=http://vllm-1:8000",
+        );
+
+        let result = parse_model_service_port_map();
+        assert_eq!(
+            result.get("facebook/opt-125m").unwrap(),
+            &vec![
+                Url::parse("http://vllm-0:8000").unwrap(),
+                Url::parse("http://vllm-1:8000").unwrap(),
+            ]
+        );
+    }
+
     #[test]
     #[should_panic(expected = "malformed service: http://vllm:invalid_port")]
     fn test_malformed_url() {
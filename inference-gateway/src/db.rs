@@ -1,9 +1,22 @@
 use crate::errors::PlatformError;
+use chrono::Duration;
+use sqlx::error::BoxDynError;
+use sqlx::migrate::{Migration, MigrationSource, Migrator};
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 use sqlx::ConnectOptions;
 use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::pin::Pin;
 use url::{ParseError, Url};
 
+/// Adds `inference.requests.id`, which [`backfill_request_ids`] fills in for existing requests
+pub const REQUEST_ID_MIGRATION: i64 = 20261018180000;
+/// Makes `inference.requests.id` not null, so it must run after the backfill
+pub const REQUEST_ID_NOT_NULL_MIGRATION: i64 = 20261018180500;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 pub async fn connect(url: &str, max_connections: u32) -> Result<Pool<Postgres>, PlatformError> {
     let options = conn_options(url)?;
     let pgp = PgPoolOptions::new()
@@ -26,3 +39,88 @@ pub fn conn_options(url: &str) -> Result<PgConnectOptions, PlatformError> {
         .log_statements(log::LevelFilter::Debug);
     Ok(options)
 }
+
+/// Runs the migrations. Requests logged before `inference.requests.id` was added are given an id
+/// between the migrations which add it and make it not null, since a migration runs in a single
+/// transaction and can't update a large table in batches.
+pub async fn migrate(pool: &Pool<Postgres>) -> Result<(), PlatformError> {
+    run_migrations(pool, 0..=REQUEST_ID_MIGRATION).await?;
+    backfill_request_ids(pool).await?;
+    MIGRATOR.run(pool).await.map_err(sqlx::Error::from)?;
+    Ok(())
+}
+
+/// Runs the migrations whose version is in `versions`
+pub async fn run_migrations(
+    pool: &Pool<Postgres>,
+    versions: RangeInclusive<i64>,
+) -> Result<(), PlatformError> {
+    let mut migrator = Migrator::new(EmbeddedMigrations(versions))
+        .await
+        .map_err(sqlx::Error::from)?;
+    // later migrations may have been applied already
+    migrator.set_ignore_missing(true);
+    migrator.run(pool).await.map_err(sqlx::Error::from)?;
+    Ok(())
+}
+
+/// The migrations embedded in the binary, limited to a range of versions
+#[derive(Debug)]
+struct EmbeddedMigrations(RangeInclusive<i64>);
+
+impl MigrationSource<'static> for EmbeddedMigrations {
+    fn resolve(
+        self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Migration>, BoxDynError>> + Send + 'static>> {
+        let migrations = MIGRATOR
+            .iter()
+            .filter(|migration| self.0.contains(&migration.version))
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(migrations) })
+    }
+}
+
+/// Gives the requests logged before `inference.requests.id` was added an id, a day at a time so
+/// every update is a short transaction of its own. Returns the number of requests updated.
+pub async fn backfill_request_ids(pool: &Pool<Postgres>) -> Result<u64, PlatformError> {
+    // nothing to do once the column is not null, without scanning the table
+    let not_null = sqlx::query_scalar!(
+        "SELECT attnotnull FROM pg_attribute
+        WHERE attrelid = 'inference.requests'::regclass AND attname = 'id'"
+    )
+    .fetch_one(pool)
+    .await?;
+    if not_null {
+        return Ok(0);
+    }
+
+    let range = sqlx::query!(
+        r#"SELECT min(completed_at) AS "start", max(completed_at) AS "end"
+        FROM inference.requests
+        WHERE id IS NULL"#
+    )
+    .fetch_one(pool)
+    .await?;
+    let (Some(mut day), Some(end)) = (range.start, range.end) else {
+        return Ok(0);
+    };
+
+    let mut updated = 0;
+    while day <= end {
+        let next = day + Duration::days(1);
+        updated += sqlx::query!(
+            "UPDATE inference.requests
+            SET id = gen_random_uuid()
+            WHERE id IS NULL AND completed_at >= $1 AND completed_at < $2",
+            day,
+            next
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        day = next;
+    }
+    log::info!("Gave {} requests an id", updated);
+    Ok(updated)
+}
@@ -74,6 +74,8 @@ pub enum PlatformError {
     Reqwest(#[from] reqwest::Error),
     #[error("S3 error: {0}")]
     S3Error(String),
+    #[error("{message}")]
+    RateLimited { message: String, retry_after: u64 },
 }
 
 // PUBLIC FACING ERROR RESPONSES
@@ -105,6 +107,23 @@ impl Serialize for ErrorResponse {
 
 impl ResponseError for PlatformError {
     fn error_response(&self) -> HttpResponse {
+        // rate limits are returned in the OpenAI format, which clients know how to back off from
+        if let PlatformError::RateLimited {
+            message,
+            retry_after,
+        } = self
+        {
+            return HttpResponse::build(self.status_code())
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({
+                    "error": {
+                        "message": message,
+                        "type": "rate_limit_exceeded",
+                        "param": null,
+                        "code": "rate_limit_exceeded",
+                    }
+                }));
+        }
         let resp = match self {
             PlatformError::AuthError(_) => ErrorResponse::NotAuthorized(self.to_string()),
             PlatformError::Conflict(_) => ErrorResponse::Conflict(self.to_string()),
@@ -129,6 +148,7 @@ impl ResponseError for PlatformError {
             PlatformError::NotFoundError(_) => StatusCode::NOT_FOUND,
             PlatformError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
             PlatformError::ValueError(_) => StatusCode::BAD_REQUEST,
+            PlatformError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
             _ => {
                 log::error!("Internal Server Error: {:?}", self);
                 StatusCode::INTERNAL_SERVER_ERROR
@@ -1,16 +1,48 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Timelike, Utc};
 use log::info;
 use pgmq::PGMQueueExt;
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Pool, Postgres};
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::time::interval;
 use uuid::Uuid;
 
+use crate::config::Config;
 use crate::db::{self};
 use crate::errors::DatabaseError;
 
+/// How usage is reported to billing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BillingAggregation {
+    /// One event per request
+    Request,
+    /// One event per organization, instance, model and request type each hour
+    Hourly,
+}
+
+impl FromStr for BillingAggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request" => Ok(Self::Request),
+            "hourly" => Ok(Self::Hourly),
+            _ => Err(format!("unknown billing aggregation: {}", s)),
+        }
+    }
+}
+
+impl BillingAggregation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Request => "request",
+            Self::Hourly => "hourly",
+        }
+    }
+}
+
 pub fn split_events(events: Vec<Events>, max_size: usize) -> Vec<Message> {
     events
         .chunks(max_size)
@@ -33,6 +65,7 @@ pub async fn get_usage(
         organization_id,
         instance_id,
         model,
+        request_type,
         MAX(completed_at) AS completed_at,
         SUM(prompt_tokens) AS prompt_tokens,
         SUM(completion_tokens) AS completion_tokens
@@ -41,10 +74,12 @@ pub async fn get_usage(
     WHERE
         completed_at >= $1
         AND completed_at <= $2
+        AND status_code < 400
     GROUP BY
         organization_id,
         instance_id,
-        model
+        model,
+        request_type
         "#,
         start_time,
         end_time
@@ -55,6 +90,39 @@ pub async fn get_usage(
     Ok(rows_to_events(rows))
 }
 
+pub async fn get_request_usage(
+    dbclient: &Pool<Postgres>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Result<Vec<Events>, DatabaseError> {
+    let rows = sqlx::query_as!(
+        RequestUsageData,
+        r#"
+        SELECT
+        id::text AS "id!",
+        organization_id,
+        instance_id,
+        model,
+        request_type,
+        completed_at,
+        prompt_tokens,
+        completion_tokens
+    FROM
+        inference.requests
+    WHERE
+        completed_at >= $1
+        AND completed_at <= $2
+        AND status_code < 400
+        "#,
+        start_time,
+        end_time
+    )
+    .fetch_all(dbclient)
+    .await?;
+
+    Ok(request_rows_to_events(rows))
+}
+
 fn rows_to_events(rows: Vec<UsageData>) -> Vec<Events> {
     rows.into_iter()
         .map(|row| {
@@ -66,13 +134,24 @@ fn rows_to_events(rows: Vec<UsageData>) -> Vec<Events> {
                 .format("%Y%m%d%H")
                 .to_string();
 
+            // completions keep their original key, so re-reporting an hour stays idempotent
+            let idempotency_key = if row.request_type == "completion" {
+                format!("{}-{}-{}", row.instance_id, row.model, completed_at)
+            } else {
+                format!(
+                    "{}-{}-{}-{}",
+                    row.instance_id, row.model, row.request_type, completed_at
+                )
+            };
+
             Events {
-                idempotency_key: format!("{}-{}-{}", row.instance_id, row.model, completed_at),
+                idempotency_key,
                 organization_id: row.organization_id,
                 instance_id: row.instance_id,
                 payload: Payload {
                     completed_at: row.completed_at.unwrap_or_default().to_string(),
                     model: row.model,
+                    request_type: row.request_type,
                     prompt_tokens: row.prompt_tokens.unwrap_or(0).to_string(),
                     completion_tokens: row.completion_tokens.unwrap_or(0).to_string(),
                 },
@@ -81,6 +160,24 @@ fn rows_to_events(rows: Vec<UsageData>) -> Vec<Events> {
         .collect()
 }
 
+fn request_rows_to_events(rows: Vec<RequestUsageData>) -> Vec<Events> {
+    rows.into_iter()
+        .map(|row| Events {
+            // the request's id never changes, so re-reporting it stays idempotent
+            idempotency_key: format!("{}-{}", row.instance_id, row.id),
+            organization_id: row.organization_id,
+            instance_id: row.instance_id,
+            payload: Payload {
+                completed_at: row.completed_at.to_string(),
+                model: row.model,
+                request_type: row.request_type,
+                prompt_tokens: row.prompt_tokens.to_string(),
+                completion_tokens: row.completion_tokens.to_string(),
+            },
+        })
+        .collect()
+}
+
 async fn get_reporter_watermark(conn: &PgPool) -> Result<Option<ReporterWatermark>> {
     sqlx::query_as!(
         ReporterWatermark,
@@ -102,7 +199,6 @@ async fn save_reporter_watermark(
     )
     .execute(inference_pool)
     .await
-    .map_err(Into::into)
     .map(|_| ())
 }
 
@@ -137,18 +233,18 @@ fn get_hourly_chunks(
     chunks
 }
 
-pub async fn run_events_reporter(pg_conn: String, billing_queue_conn: String) -> Result<()> {
-    // Run once per hour
-    const SYNC_PERIOD: Duration = Duration::from_secs(60 * 60);
-    const BILLING_QUEUE: &str = "billing_aws_data_1_use1";
-
-    let inference_pool = db::connect(&pg_conn, 2).await?;
-
-    let queue = PGMQueueExt::new(billing_queue_conn, 2).await?;
+async fn connect_queue(cfg: &Config) -> Result<PGMQueueExt> {
+    let queue = PGMQueueExt::new(cfg.billing_queue_conn_str.clone(), 2).await?;
     queue.init().await?;
-    queue.create(BILLING_QUEUE).await?;
+    queue.create(&cfg.billing_queue_name).await?;
+    Ok(queue)
+}
+
+pub async fn run_events_reporter(cfg: &Config) -> Result<()> {
+    let inference_pool = db::connect(&cfg.pg_conn_str, 2).await?;
+    let queue = connect_queue(cfg).await?;
 
-    let mut sync_interval = interval(SYNC_PERIOD);
+    let mut sync_interval = interval(Duration::from_secs(cfg.billing_reporter_period_sec));
 
     loop {
         sync_interval.tick().await;
@@ -162,33 +258,74 @@ pub async fn run_events_reporter(pg_conn: String, billing_queue_conn: String) ->
         let chunks = get_hourly_chunks(last_reported_at, now);
 
         for (start_time, end_time) in chunks {
-            enqueue_event(&inference_pool, &queue, BILLING_QUEUE, start_time, end_time).await?;
+            enqueue_event(
+                &inference_pool,
+                &queue,
+                &cfg.billing_queue_name,
+                cfg.billing_aggregation,
+                start_time,
+                end_time,
+            )
+            .await?;
+            // Save the watermark after every hour, so a failure only re-sends the hour in
+            // progress, which billing deduplicates by idempotency key
+            save_reporter_watermark(&inference_pool, end_time + ChronoDuration::nanoseconds(1))
+                .await?;
         }
+    }
+}
 
-        // Save new reporter watermark
-        save_reporter_watermark(&inference_pool, now).await?;
+/// Re-send usage for every complete hour between `start` and `end`, without moving the
+/// watermark. Events keep their idempotency keys, so hours billing already has are ignored.
+pub async fn backfill_events(
+    cfg: &Config,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<usize> {
+    let inference_pool = db::connect(&cfg.pg_conn_str, 2).await?;
+    let queue = connect_queue(cfg).await?;
+
+    let chunks = get_hourly_chunks(start, end.min(Utc::now()));
+    // Refuse the whole range before sending anything
+    if let (Some((first, _)), Some((_, last))) = (chunks.first(), chunks.last()) {
+        check_reported_aggregation(&inference_pool, cfg.billing_aggregation, *first, *last).await?;
+    }
+    for (start_time, end_time) in &chunks {
+        enqueue_event(
+            &inference_pool,
+            &queue,
+            &cfg.billing_queue_name,
+            cfg.billing_aggregation,
+            *start_time,
+            *end_time,
+        )
+        .await?;
     }
+    Ok(chunks.len())
 }
 
 async fn enqueue_event(
     pool: &Pool<Postgres>,
     queue: &PGMQueueExt,
     metrics_events_queue: &str,
+    aggregation: BillingAggregation,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
 ) -> Result<(), anyhow::Error> {
     const BATCH_SIZE: usize = 1000;
 
-    let events = get_usage(pool, start_time, end_time).await?;
+    check_reported_aggregation(pool, aggregation, start_time, end_time).await?;
+    let events = match aggregation {
+        BillingAggregation::Hourly => get_usage(pool, start_time, end_time).await?,
+        BillingAggregation::Request => get_request_usage(pool, start_time, end_time).await?,
+    };
     let metrics_to_send = split_events(events, BATCH_SIZE);
     let batches = metrics_to_send.len();
     info!(
         "Split metrics into {} chunks, each with {} results",
         batches, BATCH_SIZE
     );
-    let mut i = 1;
-
-    for event in &metrics_to_send {
+    for (i, event) in (1..).zip(metrics_to_send.iter()) {
         queue.send(metrics_events_queue, event).await?;
         info!(
             "Enqueued batch {}/{} for {} to PGMQ",
@@ -196,12 +333,61 @@ async fn enqueue_event(
             batches,
             start_time.format("%Y-%m-%d %H:%M:%S %Z")
         );
-        i += 1;
     }
+    save_reported_hour(pool, start_time, aggregation).await?;
 
     Ok(())
 }
 
+/// Fail when an hour in the range was already reported with the other aggregation.
+/// Hourly and per request events have different idempotency keys, so billing would
+/// count that usage twice.
+async fn check_reported_aggregation(
+    pool: &Pool<Postgres>,
+    aggregation: BillingAggregation,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Result<()> {
+    let hours = sqlx::query_scalar!(
+        "SELECT hour FROM billing.reported_hours
+        WHERE hour >= $1 AND hour <= $2 AND aggregation <> $3
+        ORDER BY hour",
+        start_time,
+        end_time,
+        aggregation.as_str()
+    )
+    .fetch_all(pool)
+    .await?;
+    if let (Some(first), Some(last)) = (hours.first(), hours.last()) {
+        bail!(
+            "{} hours from {} to {} were reported with another aggregation than {}, \
+            re-sending them would bill their usage twice",
+            hours.len(),
+            first,
+            last,
+            aggregation.as_str()
+        );
+    }
+    Ok(())
+}
+
+async fn save_reported_hour(
+    pool: &Pool<Postgres>,
+    hour: DateTime<Utc>,
+    aggregation: BillingAggregation,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO billing.reported_hours (hour, aggregation)
+        VALUES ($1, $2)
+        ON CONFLICT (hour) DO NOTHING",
+        hour,
+        aggregation.as_str()
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Message {
     pub id: String,
@@ -220,6 +406,7 @@ pub struct Events {
 pub struct Payload {
     pub completed_at: String,
     pub model: String,
+    pub request_type: String,
     pub prompt_tokens: String,
     pub completion_tokens: String,
 }
@@ -230,9 +417,21 @@ struct UsageData {
     prompt_tokens: Option<i64>,
     completion_tokens: Option<i64>,
     model: String,
+    request_type: String,
     completed_at: Option<DateTime<Utc>>,
 }
 
+struct RequestUsageData {
+    id: String,
+    organization_id: String,
+    instance_id: String,
+    model: String,
+    request_type: String,
+    completed_at: DateTime<Utc>,
+    prompt_tokens: i32,
+    completion_tokens: i32,
+}
+
 #[derive(sqlx::FromRow, Debug)]
 struct ReporterWatermark {
     last_reported_at: DateTime<Utc>,
@@ -244,7 +443,75 @@ mod tests {
 
     use crate::events_reporter::start_of_the_hour;
 
-    use super::get_hourly_chunks;
+    use super::{
+        get_hourly_chunks, request_rows_to_events, rows_to_events, BillingAggregation,
+        RequestUsageData, UsageData,
+    };
+
+    #[test]
+    fn test_rows_to_events_request_type() {
+        let completed_at = Some(Utc.with_ymd_and_hms(2023, 5, 10, 15, 30, 0).unwrap());
+        let rows = vec![
+            UsageData {
+                organization_id: "org".to_string(),
+                instance_id: "inst".to_string(),
+                prompt_tokens: Some(10),
+                completion_tokens: Some(20),
+                model: "llama".to_string(),
+                request_type: "completion".to_string(),
+                completed_at,
+            },
+            UsageData {
+                organization_id: "org".to_string(),
+                instance_id: "inst".to_string(),
+                prompt_tokens: Some(30),
+                completion_tokens: Some(0),
+                model: "bge".to_string(),
+                request_type: "embedding".to_string(),
+                completed_at,
+            },
+        ];
+
+        let events = rows_to_events(rows);
+        assert_eq!(events[0].idempotency_key, "inst-llama-2023051015");
+        assert_eq!(events[0].payload.request_type, "completion");
+        assert_eq!(events[1].idempotency_key, "inst-bge-embedding-2023051015");
+        assert_eq!(events[1].payload.prompt_tokens, "30");
+    }
+
+    #[test]
+    fn test_request_rows_to_events() {
+        let rows = vec![RequestUsageData {
+            id: "5f0c6c1e-7a0e-4d3a-9b8e-2f4c1d9a6b21".to_string(),
+            organization_id: "org".to_string(),
+            instance_id: "inst".to_string(),
+            model: "llama".to_string(),
+            request_type: "completion".to_string(),
+            completed_at: Utc.with_ymd_and_hms(2023, 5, 10, 15, 30, 0).unwrap(),
+            prompt_tokens: 10,
+            completion_tokens: 20,
+        }];
+
+        let events = request_rows_to_events(rows);
+        assert_eq!(
+            events[0].idempotency_key,
+            "inst-5f0c6c1e-7a0e-4d3a-9b8e-2f4c1d9a6b21"
+        );
+        assert_eq!(events[0].payload.completion_tokens, "20");
+    }
+
+    #[test]
+    fn test_billing_aggregation_from_str() {
+        assert_eq!(
+            "request".parse::<BillingAggregation>().unwrap(),
+            BillingAggregation::Request
+        );
+        assert_eq!(
+            "hourly".parse::<BillingAggregation>().unwrap(),
+            BillingAggregation::Hourly
+        );
+        assert!("daily".parse::<BillingAggregation>().is_err());
+    }
 
     #[test]
     fn test_start_of_hour_middle_of_hour() {
@@ -0,0 +1,115 @@
+//! Manage API keys for the inference gateway
+//!
+//! gateway-keys create <organization_id> [--instance <instance_id>] [--name <name>] [--expires-in-days <days>]
+//! gateway-keys list <organization_id>
+//! gateway-keys revoke <key_id>
+//! gateway-keys add-instance <organization_id> --instance <instance_id>
+
+use chrono::{Duration, Utc};
+use gateway::api_keys::{create_key, revoke_key};
+use gateway::config::Config;
+use gateway::db;
+
+const USAGE: &str = "usage:
+    gateway-keys create <organization_id> [--instance <instance_id>] [--name <name>] [--expires-in-days <days>]
+    gateway-keys list <organization_id>
+    gateway-keys revoke <key_id>
+    gateway-keys add-instance <organization_id> --instance <instance_id>";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, target) = match args.as_slice() {
+        [command, target, ..] => (command.as_str(), target.as_str()),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let cfg = Config::new().await;
+    let pool = db::connect(&cfg.pg_conn_str, 1).await?;
+
+    match command {
+        "create" => {
+            let instance = option(&args, "--instance");
+            let name = option(&args, "--name");
+            let expires_at = match option(&args, "--expires-in-days") {
+                Some(days) => Some(Utc::now() + Duration::days(days.parse()?)),
+                None => None,
+            };
+            let (id, key) = create_key(&pool, target, instance, name, expires_at).await?;
+            println!("id:  {id}");
+            println!("key: {key}");
+            eprintln!("The key is not stored and cannot be shown again");
+        }
+        "list" => {
+            let rows = sqlx::query!(
+                "SELECT id, instance_id, name, created_at, expires_at, revoked_at
+                FROM inference.api_keys
+                WHERE organization_id = $1
+                ORDER BY created_at",
+                target
+            )
+            .fetch_all(&pool)
+            .await?;
+            for row in rows {
+                let status = match (row.revoked_at, row.expires_at) {
+                    (Some(revoked_at), _) => format!("revoked {revoked_at}"),
+                    (None, Some(expires_at)) if expires_at <= Utc::now() => {
+                        format!("expired {expires_at}")
+                    }
+                    (None, Some(expires_at)) => format!("expires {expires_at}"),
+                    (None, None) => "active".to_string(),
+                };
+                println!(
+                    "{}\t{}\t{}\tcreated {}\t{}",
+                    row.id,
+                    row.instance_id.as_deref().unwrap_or("*"),
+                    row.name.as_deref().unwrap_or("-"),
+                    row.created_at,
+                    status
+                );
+            }
+        }
+        "revoke" => {
+            if revoke_key(&pool, target).await? {
+                println!("revoked {target}");
+            } else {
+                eprintln!("no active key with id {target}");
+                std::process::exit(1);
+            }
+        }
+        "add-instance" => {
+            let Some(instance) = option(&args, "--instance") else {
+                eprintln!("{USAGE}");
+                std::process::exit(2);
+            };
+            sqlx::query!(
+                "INSERT INTO inference.instances (instance_id, organization_id)
+                VALUES ($1, $2)
+                ON CONFLICT (instance_id)
+                DO UPDATE SET organization_id = excluded.organization_id, last_updated_at = now()",
+                instance,
+                target
+            )
+            .execute(&pool)
+            .await?;
+            println!("{instance} belongs to {target}");
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
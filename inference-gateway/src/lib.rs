@@ -1,7 +1,16 @@
+pub mod api_keys;
+pub mod audit;
 pub mod authorization;
+pub mod cache;
 pub mod config;
 pub mod db;
 pub mod errors;
 pub mod events_reporter;
+pub mod limits;
+pub mod metrics;
+pub mod registry;
 pub mod routes;
+pub mod routing;
 pub mod server;
+pub mod streaming;
+pub mod telemetry;
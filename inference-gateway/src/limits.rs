@@ -0,0 +1,430 @@
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Utc};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::errors::PlatformError;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// An organization, or one instance within it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LimitKey {
+    pub organization_id: String,
+    pub instance_id: Option<String>,
+}
+
+impl LimitKey {
+    fn scope(&self) -> String {
+        match &self.instance_id {
+            Some(instance_id) => format!("instance {}", instance_id),
+            None => format!("organization {}", self.organization_id),
+        }
+    }
+}
+
+/// Limits for an org or instance, None means unlimited
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub requests_per_minute: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    pub tokens_per_month: Option<i64>,
+}
+
+/// Tokens used so far in the current UTC day and month
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenUsage {
+    pub day: i64,
+    pub month: i64,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: i32,
+}
+
+impl Window {
+    // Seconds until a request fits in the window, or None when it fits now
+    fn retry_after(&mut self, now: Instant, max_requests: i32) -> Option<u64> {
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= RATE_WINDOW {
+            self.started = now;
+            self.count = 0;
+        }
+        if self.count >= max_requests {
+            Some((RATE_WINDOW - elapsed.min(RATE_WINDOW)).as_secs().max(1))
+        } else {
+            None
+        }
+    }
+}
+
+/// Enforces requests per minute and token quotas. Limits and token usage
+/// are reloaded from the database by the cache refresher, request counts are
+/// kept in memory, so each of the gateway's replicas allows its share of a
+/// requests per minute limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RwLock<HashMap<LimitKey, Limits>>,
+    usage: RwLock<HashMap<LimitKey, TokenUsage>>,
+    windows: Mutex<HashMap<LimitKey, Window>>,
+    replicas: i32,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            limits: Default::default(),
+            usage: Default::default(),
+            windows: Default::default(),
+            replicas: 1,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split requests per minute limits between this many gateway replicas
+    pub fn with_replicas(mut self, replicas: i32) -> Self {
+        self.replicas = replicas.max(1);
+        self
+    }
+
+    /// Reload limits from `inference.rate_limits` and usage from `inference.requests`
+    pub async fn refresh(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT organization_id, instance_id, requests_per_minute, tokens_per_day, tokens_per_month
+            FROM inference.rate_limits"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut limits = HashMap::new();
+        for row in rows {
+            limits.insert(
+                LimitKey {
+                    organization_id: row.organization_id,
+                    instance_id: row.instance_id,
+                },
+                Limits {
+                    requests_per_minute: row.requests_per_minute,
+                    tokens_per_day: row.tokens_per_day,
+                    tokens_per_month: row.tokens_per_month,
+                },
+            );
+        }
+
+        let mut orgs: Vec<String> = limits
+            .keys()
+            .map(|key| key.organization_id.clone())
+            .collect();
+        orgs.sort();
+        orgs.dedup();
+
+        let now = Utc::now();
+        let rows = sqlx::query!(
+            "SELECT
+                organization_id,
+                instance_id,
+                SUM(prompt_tokens + completion_tokens) FILTER (WHERE completed_at >= $1) AS day_tokens,
+                SUM(prompt_tokens + completion_tokens) AS month_tokens
+            FROM inference.requests
+            WHERE completed_at >= $2
+                AND organization_id = ANY($3)
+            GROUP BY organization_id, instance_id",
+            start_of_day(now),
+            start_of_month(now),
+            &orgs
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut usage: HashMap<LimitKey, TokenUsage> = HashMap::new();
+        for row in rows {
+            let day = row.day_tokens.unwrap_or(0);
+            let month = row.month_tokens.unwrap_or(0);
+            for instance_id in [Some(row.instance_id), None] {
+                let entry = usage
+                    .entry(LimitKey {
+                        organization_id: row.organization_id.clone(),
+                        instance_id,
+                    })
+                    .or_default();
+                entry.day += day;
+                entry.month += month;
+            }
+        }
+
+        log::debug!("Refreshing rate limits with {} rows", limits.len());
+        *self.limits.write().await = limits;
+        *self.usage.write().await = usage;
+        Ok(())
+    }
+
+    /// Check the org's and instance's limits, counting the request when it is allowed
+    pub async fn check(&self, org_id: &str, instance_id: &str) -> Result<(), PlatformError> {
+        let keys = [
+            LimitKey {
+                organization_id: org_id.to_string(),
+                instance_id: None,
+            },
+            LimitKey {
+                organization_id: org_id.to_string(),
+                instance_id: Some(instance_id.to_string()),
+            },
+        ];
+
+        let limits = self.limits.read().await;
+        let applicable: Vec<(&LimitKey, &Limits)> = keys
+            .iter()
+            .filter_map(|key| limits.get(key).map(|l| (key, l)))
+            .collect();
+        if applicable.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        {
+            let usage = self.usage.read().await;
+            for (key, limit) in &applicable {
+                let used = usage.get(key).cloned().unwrap_or_default();
+                check_tokens(key, limit, &used, now)?;
+            }
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let instant = Instant::now();
+        for (key, limit) in &applicable {
+            if let Some(max_requests) = limit.requests_per_minute {
+                let window = windows.entry((*key).clone()).or_insert(Window {
+                    started: instant,
+                    count: 0,
+                });
+                let replica_share = per_replica(max_requests, self.replicas);
+                if let Some(retry_after) = window.retry_after(instant, replica_share) {
+                    return Err(PlatformError::RateLimited {
+                        message: format!(
+                            "Rate limit of {} requests per minute exceeded for {}",
+                            max_requests,
+                            key.scope()
+                        ),
+                        retry_after,
+                    });
+                }
+            }
+        }
+        // only count the request once every limit allows it
+        for (key, limit) in &applicable {
+            if limit.requests_per_minute.is_some() {
+                if let Some(window) = windows.get_mut(*key) {
+                    window.count += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// This replica's share of a requests per minute limit, rounded up so every replica allows some
+fn per_replica(max_requests: i32, replicas: i32) -> i32 {
+    (max_requests.max(0) as u32).div_ceil(replicas as u32) as i32
+}
+
+fn check_tokens(
+    key: &LimitKey,
+    limit: &Limits,
+    used: &TokenUsage,
+    now: DateTime<Utc>,
+) -> Result<(), PlatformError> {
+    if let Some(max_tokens) = limit.tokens_per_day {
+        if used.day >= max_tokens {
+            return Err(PlatformError::RateLimited {
+                message: format!(
+                    "Daily quota of {} tokens exceeded for {}",
+                    max_tokens,
+                    key.scope()
+                ),
+                retry_after: seconds_until(now, start_of_day(now) + ChronoDuration::days(1)),
+            });
+        }
+    }
+    if let Some(max_tokens) = limit.tokens_per_month {
+        if used.month >= max_tokens {
+            return Err(PlatformError::RateLimited {
+                message: format!(
+                    "Monthly quota of {} tokens exceeded for {}",
+                    max_tokens,
+                    key.scope()
+                ),
+                retry_after: seconds_until(now, start_of_next_month(now)),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn seconds_until(now: DateTime<Utc>, then: DateTime<Utc>) -> u64 {
+    (then - now).num_seconds().max(1) as u64
+}
+
+fn start_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    // Safe unwrap since, according to chrono docs, Utc will never have double mappings
+    Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
+        .unwrap()
+}
+
+fn start_of_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .unwrap()
+}
+
+fn start_of_next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = if now.month() == 12 {
+        (now.year() + 1, 1)
+    } else {
+        (now.year(), now.month() + 1)
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn org_key(org: &str) -> LimitKey {
+        LimitKey {
+            organization_id: org.to_string(),
+            instance_id: None,
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let start = Instant::now();
+        let mut window = Window {
+            started: start,
+            count: 2,
+        };
+        assert_eq!(window.retry_after(start + Duration::from_secs(15), 3), None);
+        window.count = 3;
+        assert_eq!(
+            window.retry_after(start + Duration::from_secs(15), 3),
+            Some(45)
+        );
+        // a new window starts after a minute
+        assert_eq!(window.retry_after(start + Duration::from_secs(61), 3), None);
+        assert_eq!(window.count, 0);
+    }
+
+    #[test]
+    fn test_token_quota_retry_after() {
+        let now = Utc.with_ymd_and_hms(2024, 12, 31, 23, 0, 0).unwrap();
+        let key = org_key("org");
+        let limit = Limits {
+            requests_per_minute: None,
+            tokens_per_day: Some(100),
+            tokens_per_month: Some(1000),
+        };
+
+        let used = TokenUsage { day: 99, month: 99 };
+        assert!(check_tokens(&key, &limit, &used, now).is_ok());
+
+        let used = TokenUsage {
+            day: 100,
+            month: 100,
+        };
+        match check_tokens(&key, &limit, &used, now) {
+            Err(PlatformError::RateLimited { retry_after, .. }) => assert_eq!(retry_after, 3600),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let used = TokenUsage {
+            day: 10,
+            month: 1000,
+        };
+        match check_tokens(&key, &limit, &used, now) {
+            Err(PlatformError::RateLimited { retry_after, .. }) => assert_eq!(retry_after, 3600),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            start_of_next_month(now),
+            Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_per_minute() {
+        let limiter = RateLimiter::new();
+        limiter.limits.write().await.insert(
+            org_key("org"),
+            Limits {
+                requests_per_minute: Some(2),
+                ..Default::default()
+            },
+        );
+
+        assert!(limiter.check("org", "inst-1").await.is_ok());
+        assert!(limiter.check("org", "inst-2").await.is_ok());
+        assert!(matches!(
+            limiter.check("org", "inst-1").await,
+            Err(PlatformError::RateLimited { .. })
+        ));
+        // other orgs are unlimited
+        assert!(limiter.check("other", "inst").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_requests_per_minute_split_between_replicas() {
+        let limiter = RateLimiter::new().with_replicas(3);
+        limiter.limits.write().await.insert(
+            org_key("org"),
+            Limits {
+                requests_per_minute: Some(6),
+                ..Default::default()
+            },
+        );
+
+        assert!(limiter.check("org", "inst").await.is_ok());
+        assert!(limiter.check("org", "inst").await.is_ok());
+        assert!(matches!(
+            limiter.check("org", "inst").await,
+            Err(PlatformError::RateLimited { .. })
+        ));
+        assert_eq!(per_replica(5, 3), 2);
+        assert_eq!(per_replica(1, 3), 1);
+    }
+
+    #[tokio::test]
+    async fn test_instance_limit_does_not_count_rejected_requests() {
+        let limiter = RateLimiter::new();
+        limiter.limits.write().await.insert(
+            LimitKey {
+                organization_id: "org".to_string(),
+                instance_id: Some("inst".to_string()),
+            },
+            Limits {
+                requests_per_minute: Some(1),
+                ..Default::default()
+            },
+        );
+        limiter.limits.write().await.insert(
+            org_key("org"),
+            Limits {
+                requests_per_minute: Some(2),
+                ..Default::default()
+            },
+        );
+
+        assert!(limiter.check("org", "inst").await.is_ok());
+        assert!(limiter.check("org", "inst").await.is_err());
+        // the rejected request didn't use up the org's second request
+        assert!(limiter.check("org", "other-inst").await.is_ok());
+        assert!(limiter.check("org", "other-inst").await.is_err());
+    }
+}
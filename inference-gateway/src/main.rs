@@ -7,10 +7,13 @@ async fn main() {
     env_logger::init();
 
     let cfg = gateway::config::Config::new().await;
+    gateway::telemetry::init(&cfg.opentelemetry_endpoint_url);
     let startup_configs = gateway::server::webserver_startup_config(cfg).await;
     let server_port = startup_configs.cfg.server_port;
+    let internal_port = startup_configs.cfg.internal_port;
     let server_workers = startup_configs.cfg.server_workers;
-    let _ = HttpServer::new(move || {
+    let router = startup_configs.router.clone();
+    let server = HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
@@ -20,12 +23,28 @@ async fn main() {
             .app_data(web::Data::new(startup_configs.http_client.clone()))
             .app_data(web::Data::new(startup_configs.pool.clone()))
             .app_data(web::Data::new(startup_configs.auth_cache.clone()))
+            .app_data(web::Data::new(startup_configs.rate_limiter.clone()))
+            .app_data(web::Data::new(startup_configs.api_keys.clone()))
+            .app_data(web::Data::new(startup_configs.response_cache.clone()))
+            .app_data(web::Data::new(startup_configs.audit_log.clone()))
+            .app_data(web::Data::new(startup_configs.router.clone()))
+            .app_data(web::Data::new(startup_configs.registry.clone()))
             .configure(gateway::server::webserver_routes)
     })
     .workers(server_workers as usize)
     .keep_alive(Duration::from_secs(75))
     .bind(("0.0.0.0", server_port))
     .unwrap()
-    .run()
-    .await;
+    .run();
+    let internal_server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(router.clone()))
+            .configure(gateway::server::internal_routes)
+    })
+    .workers(1)
+    .bind(("0.0.0.0", internal_port))
+    .unwrap()
+    .run();
+    let _ = tokio::try_join!(server, internal_server);
+    gateway::telemetry::shutdown();
 }
@@ -0,0 +1,190 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, Encoder, HistogramVec, IntCounterVec, IntGaugeVec,
+    Registry, TextEncoder,
+};
+use std::time::Duration;
+
+use crate::routing::BackendStats;
+
+// Model servers can take minutes to generate long completions
+const LATENCY_BUCKETS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "inference_gateway_requests_total",
+        "Requests handled by the gateway, by response status",
+        &["model", "organization_id", "request_type", "status"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "inference_gateway_request_duration_seconds",
+        "Time until the gateway responds, which is the time to first chunk for streamed requests",
+        &["model", "organization_id", "request_type"],
+        LATENCY_BUCKETS.to_vec(),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static TOKENS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "inference_gateway_tokens_total",
+        "Prompt and completion tokens used",
+        &["model", "organization_id", "type"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "inference_gateway_upstream_requests_total",
+        "Requests sent to model servers, by response status or error",
+        &["model", "backend", "status"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "inference_gateway_upstream_request_duration_seconds",
+        "Time until a model server responds",
+        &["model", "backend"],
+        LATENCY_BUCKETS.to_vec(),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static BACKEND_HEALTHY: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "inference_gateway_backend_healthy",
+        "Whether a model server is receiving requests",
+        &["model", "backend"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static BACKEND_OUTSTANDING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "inference_gateway_backend_outstanding_requests",
+        "Requests in progress on a model server",
+        &["model", "backend"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Register every metric, so they are exported before the first request
+pub fn init() {
+    Lazy::force(&REQUESTS);
+    Lazy::force(&REQUEST_DURATION);
+    Lazy::force(&TOKENS);
+    Lazy::force(&UPSTREAM_REQUESTS);
+    Lazy::force(&UPSTREAM_DURATION);
+    Lazy::force(&BACKEND_HEALTHY);
+    Lazy::force(&BACKEND_OUTSTANDING);
+}
+
+pub fn observe_request(
+    model: &str,
+    org_id: &str,
+    request_type: &str,
+    status: u16,
+    duration: Duration,
+) {
+    REQUESTS
+        .with_label_values(&[model, org_id, request_type, &status.to_string()])
+        .inc();
+    REQUEST_DURATION
+        .with_label_values(&[model, org_id, request_type])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn observe_tokens(model: &str, org_id: &str, prompt_tokens: i32, completion_tokens: i32) {
+    TOKENS
+        .with_label_values(&[model, org_id, "prompt"])
+        .inc_by(prompt_tokens.max(0) as u64);
+    TOKENS
+        .with_label_values(&[model, org_id, "completion"])
+        .inc_by(completion_tokens.max(0) as u64);
+}
+
+/// Record a request to a model server, `status` is the response status or the kind of error
+pub fn observe_upstream(model: &str, backend: &str, status: &str, duration: Option<Duration>) {
+    UPSTREAM_REQUESTS
+        .with_label_values(&[model, backend, status])
+        .inc();
+    if let Some(duration) = duration {
+        UPSTREAM_DURATION
+            .with_label_values(&[model, backend])
+            .observe(duration.as_secs_f64());
+    }
+}
+
+fn update_backends(stats: &[BackendStats]) {
+    BACKEND_HEALTHY.reset();
+    BACKEND_OUTSTANDING.reset();
+    for backend in stats {
+        let labels = [backend.model.as_str(), backend.url.as_str()];
+        BACKEND_HEALTHY
+            .with_label_values(&labels)
+            .set((backend.healthy && !backend.ejected) as i64);
+        BACKEND_OUTSTANDING
+            .with_label_values(&labels)
+            .set(backend.outstanding as i64);
+    }
+}
+
+/// Render every metric in the Prometheus text format
+pub fn gather(backends: &[BackendStats]) -> Result<String, prometheus::Error> {
+    update_backends(backends);
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather() {
+        init();
+        observe_request("llama", "org", "completion", 429, Duration::from_millis(5));
+        observe_tokens("llama", "org", 10, 20);
+        let backends = vec![BackendStats {
+            model: "llama".to_string(),
+            url: "http://llama:8000/".to_string(),
+            healthy: true,
+            ejected: false,
+            outstanding: 3,
+            requests: 10,
+            errors: 0,
+            avg_latency_ms: 100,
+        }];
+
+        let text = gather(&backends).unwrap();
+        assert!(text.contains(
+            r#"inference_gateway_requests_total{model="llama",organization_id="org",request_type="completion",status="429"}"#
+        ));
+        assert!(text.contains(
+            r#"inference_gateway_tokens_total{model="llama",organization_id="org",type="completion"} 20"#
+        ));
+        assert!(text.contains(
+            r#"inference_gateway_backend_outstanding_requests{backend="http://llama:8000/",model="llama"} 3"#
+        ));
+    }
+}
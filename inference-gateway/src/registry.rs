@@ -0,0 +1,209 @@
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use url::Url;
+
+use crate::config::Config;
+use crate::errors::PlatformError;
+
+/// A model which can be requested through the gateway
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelEntry {
+    pub name: String,
+    pub backends: Vec<Url>,
+    pub enabled: bool,
+    /// Organizations allowed to use the model, None allows every organization
+    pub allowed_organizations: Option<Vec<String>>,
+}
+
+impl ModelEntry {
+    pub fn allows(&self, org_id: &str) -> bool {
+        self.enabled
+            && match &self.allowed_organizations {
+                Some(orgs) => orgs.iter().any(|org| org == org_id),
+                None => true,
+            }
+    }
+}
+
+/// Models and aliases from the environment, overridden by the `inference.models`
+/// and `inference.model_aliases` tables
+#[derive(Clone, Debug, Default)]
+pub struct ModelRegistry {
+    models: HashMap<String, ModelEntry>,
+    aliases: HashMap<String, String>,
+}
+
+impl ModelRegistry {
+    pub fn from_config(cfg: &Config) -> Self {
+        let models = cfg
+            .model_service_map
+            .iter()
+            .map(|(name, backends)| {
+                (
+                    name.clone(),
+                    ModelEntry {
+                        name: name.clone(),
+                        backends: backends.clone(),
+                        enabled: true,
+                        allowed_organizations: None,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            models,
+            aliases: cfg.model_rewrites.clone(),
+        }
+    }
+
+    pub async fn load(pool: &PgPool, cfg: &Config) -> Result<Self, sqlx::Error> {
+        let mut registry = Self::from_config(cfg);
+
+        let rows = sqlx::query!(
+            "SELECT name, backends, enabled, allowed_organizations FROM inference.models"
+        )
+        .fetch_all(pool)
+        .await?;
+        for row in rows {
+            let backends = row
+                .backends
+                .iter()
+                .filter_map(|backend| match Url::parse(backend) {
+                    Ok(url) => Some(url),
+                    Err(e) => {
+                        log::error!(
+                            "malformed service {} for model {}: {}",
+                            backend,
+                            row.name,
+                            e
+                        );
+                        None
+                    }
+                })
+                .collect();
+            registry.models.insert(
+                row.name.clone(),
+                ModelEntry {
+                    name: row.name,
+                    backends,
+                    enabled: row.enabled,
+                    allowed_organizations: row.allowed_organizations,
+                },
+            );
+        }
+
+        let rows = sqlx::query!("SELECT alias, model FROM inference.model_aliases")
+            .fetch_all(pool)
+            .await?;
+        for row in rows {
+            registry.aliases.insert(row.alias, row.model);
+        }
+
+        Ok(registry)
+    }
+
+    /// Find the model for a requested name or alias, if the org may use it
+    pub fn resolve(
+        &self,
+        requested_model: &str,
+        org_id: &str,
+    ) -> Result<&ModelEntry, PlatformError> {
+        let name = self
+            .aliases
+            .get(requested_model)
+            .map(String::as_str)
+            .unwrap_or(requested_model);
+        self.models
+            .get(name)
+            .filter(|model| model.allows(org_id))
+            .ok_or_else(|| PlatformError::InvalidQuery(format!("model {} not found", name)))
+    }
+
+    /// Names and aliases of the models the org may use
+    pub fn available_models(&self, org_id: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .models
+            .values()
+            .filter(|model| model.allows(org_id))
+            .map(|model| model.name.clone())
+            .chain(self.aliases.iter().filter_map(|(alias, model)| {
+                self.models
+                    .get(model)
+                    .filter(|m| m.allows(org_id))
+                    .map(|_| alias.clone())
+            }))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Backends of every enabled model, for the router
+    pub fn backend_map(&self) -> HashMap<String, Vec<Url>> {
+        self.models
+            .values()
+            .filter(|model| model.enabled)
+            .map(|model| (model.name.clone(), model.backends.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::default();
+        for (name, enabled, allowed) in [
+            ("llama", true, None),
+            ("mistral", false, None),
+            ("private", true, Some(vec!["org-a".to_string()])),
+        ] {
+            registry.models.insert(
+                name.to_string(),
+                ModelEntry {
+                    name: name.to_string(),
+                    backends: vec![Url::parse(&format!("http://{name}:8000")).unwrap()],
+                    enabled,
+                    allowed_organizations: allowed,
+                },
+            );
+        }
+        registry
+            .aliases
+            .insert("gpt-4".to_string(), "llama".to_string());
+        registry
+            .aliases
+            .insert("secret".to_string(), "private".to_string());
+        registry
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = registry();
+        assert_eq!(registry.resolve("llama", "org-b").unwrap().name, "llama");
+        assert_eq!(registry.resolve("gpt-4", "org-b").unwrap().name, "llama");
+        assert!(registry.resolve("mistral", "org-b").is_err());
+        assert!(registry.resolve("private", "org-b").is_err());
+        assert_eq!(registry.resolve("secret", "org-a").unwrap().name, "private");
+        assert!(registry.resolve("unknown", "org-a").is_err());
+    }
+
+    #[test]
+    fn test_available_models() {
+        let registry = registry();
+        assert_eq!(registry.available_models("org-b"), vec!["gpt-4", "llama"]);
+        assert_eq!(
+            registry.available_models("org-a"),
+            vec!["gpt-4", "llama", "private", "secret"]
+        );
+    }
+
+    #[test]
+    fn test_backend_map_skips_disabled() {
+        let backends = registry().backend_map();
+        assert!(backends.contains_key("llama"));
+        assert!(backends.contains_key("private"));
+        assert!(!backends.contains_key("mistral"));
+    }
+}
@@ -0,0 +1,133 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::api_keys::{resolve_caller, ApiKeyStore, Caller};
+use crate::audit::{self, AuditFilter};
+use crate::authorization;
+use crate::errors::{AuthError, PlatformError};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub instance_id: Option<String>,
+    pub model: Option<String>,
+    /// RFC 3339, defaults to a day before `end`
+    pub start: Option<String>,
+    /// RFC 3339, defaults to now
+    pub end: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Audit log entries of the calling organization, most recent first. Only served with API key
+/// auth, since the org and instance headers can't be trusted to guard stored prompts.
+#[get("/v1/audit/logs")]
+pub async fn list_audit_logs(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    config: web::Data<crate::config::Config>,
+    api_keys: web::Data<Arc<ApiKeyStore>>,
+    dbclient: web::Data<Arc<PgPool>>,
+    cache: web::Data<Arc<RwLock<HashMap<String, bool>>>>,
+) -> Result<HttpResponse, PlatformError> {
+    if !config.api_key_auth_enabled {
+        return Err(AuthError::Forbidden("The audit log requires API key auth".to_string()).into());
+    }
+    let caller = resolve_caller(&req, &config, &api_keys).await?;
+    if config.org_auth_enabled && !authorization::auth_org(&caller.organization_id, &cache).await {
+        return Err(AuthError::Forbidden("Organization is not authorized".to_string()).into());
+    }
+
+    let filter = audit_filter(&caller, &query, Utc::now())?;
+    let records = audit::query(&dbclient, &caller.organization_id, &filter).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "object": "list",
+        "data": records,
+    })))
+}
+
+// Keys scoped to an instance only read that instance's entries, whatever the query asks for
+fn audit_filter(
+    caller: &Caller,
+    query: &AuditQuery,
+    now: DateTime<Utc>,
+) -> Result<AuditFilter, PlatformError> {
+    let end = match &query.end {
+        Some(end) => parse_time("end", end)?,
+        None => now,
+    };
+    let start = match &query.start {
+        Some(start) => parse_time("start", start)?,
+        None => end - Duration::days(1),
+    };
+    if start > end {
+        return Err(PlatformError::InvalidQuery(
+            "start must be before end".to_string(),
+        ));
+    }
+    Ok(AuditFilter {
+        instance_id: caller
+            .instance_scope
+            .clone()
+            .or_else(|| query.instance_id.clone()),
+        model: query.model.clone(),
+        start,
+        end,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    })
+}
+
+fn parse_time(name: &str, value: &str) -> Result<DateTime<Utc>, PlatformError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| PlatformError::InvalidQuery(format!("invalid {}: {}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(instance_scope: Option<&str>) -> Caller {
+        Caller {
+            organization_id: "org".to_string(),
+            instance_id: "inst-1".to_string(),
+            api_key_id: Some("key-1".to_string()),
+            instance_scope: instance_scope.map(str::to_string),
+        }
+    }
+
+    fn query(instance_id: Option<&str>) -> AuditQuery {
+        AuditQuery {
+            instance_id: instance_id.map(str::to_string),
+            model: None,
+            start: None,
+            end: None,
+            limit: Some(5000),
+        }
+    }
+
+    #[test]
+    fn test_audit_filter_instance_scope() {
+        let now = Utc::now();
+
+        // org-wide keys read any instance of the org, or all of them
+        let filter = audit_filter(&caller(None), &query(Some("inst-2")), now).unwrap();
+        assert_eq!(filter.instance_id.as_deref(), Some("inst-2"));
+        let filter = audit_filter(&caller(None), &query(None), now).unwrap();
+        assert_eq!(filter.instance_id, None);
+        assert_eq!(filter.limit, MAX_LIMIT);
+        assert_eq!(filter.start, now - Duration::days(1));
+
+        // instance scoped keys only read their own instance
+        for instance_id in [None, Some("inst-2")] {
+            let filter = audit_filter(&caller(Some("inst-1")), &query(instance_id), now).unwrap();
+            assert_eq!(filter.instance_id.as_deref(), Some("inst-1"));
+        }
+    }
+}
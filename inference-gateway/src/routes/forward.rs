@@ -1,15 +1,26 @@
-use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPool;
 use sqlx::{self, Pool, Postgres};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+use crate::api_keys::{resolve_caller, ApiKeyStore};
+use crate::audit::{AuditEntry, AuditLog};
 use crate::authorization;
+use crate::cache::ResponseCache;
 use crate::config::rewrite_model_request;
 use crate::errors::{AuthError, PlatformError};
+use crate::limits::RateLimiter;
+use crate::registry::ModelRegistry;
+use crate::routing::{InFlight, ModelRouter, UpstreamRequest};
+use crate::streaming::{is_streaming, request_stream_usage, ChannelBody, SseUsageParser};
+use crate::{metrics, telemetry};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn forward_request(
     req: HttpRequest,
     body: web::Json<serde_json::Value>,
@@ -17,105 +28,458 @@ pub async fn forward_request(
     client: web::Data<reqwest::Client>,
     dbclient: web::Data<Arc<PgPool>>,
     cache: web::Data<Arc<RwLock<HashMap<String, bool>>>>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    api_keys: web::Data<Arc<ApiKeyStore>>,
+    response_cache: web::Data<Arc<ResponseCache>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    router: web::Data<Arc<ModelRouter>>,
+    registry: web::Data<Arc<RwLock<ModelRegistry>>>,
 ) -> Result<HttpResponse, PlatformError> {
-    let headers = req.headers();
-    let x_tembo_org = if let Some(header) = headers.get("X-TEMBO-ORG") {
-        header.to_str().unwrap()
-    } else {
-        return Err(
-            AuthError::Forbidden("Missing request header `X-TEMBO-ORG`".to_string()).into(),
-        );
-    };
-    let x_tembo_inst = if let Some(header) = headers.get("X-TEMBO-INSTANCE") {
-        header.to_str().unwrap()
-    } else {
-        return Err(
-            AuthError::Forbidden("Missing request header `X-TEMBO-INSTANCE`".to_string()).into(),
-        );
-    };
+    // log request duration
+    let start = std::time::Instant::now();
+    let trace_cx = telemetry::start_request_span(&req);
+    let path = req.uri().path();
+    let request_type = RequestType::from_path(path);
+    let mut model = String::new();
+    let mut org_id = String::new();
 
-    if config.org_auth_enabled {
-        let is_valid = authorization::auth_org(x_tembo_org, &cache).await;
-        if !is_valid {
-            return Err(AuthError::Forbidden("Organization is not authorized".to_string()).into());
+    let result = async {
+        let caller = resolve_caller(&req, &config, &api_keys).await?;
+        org_id.clone_from(&caller.organization_id);
+        let x_tembo_org = caller.organization_id.as_str();
+        let x_tembo_inst = caller.instance_id.as_str();
+
+        if config.org_auth_enabled {
+            let is_valid = authorization::auth_org(x_tembo_org, &cache).await;
+            if !is_valid {
+                return Err(
+                    AuthError::Forbidden("Organization is not authorized".to_string()).into(),
+                );
+            }
         }
-    }
 
-    let path = req.uri().path();
-    if path.contains("embeddings") {
-        return Ok(HttpResponse::BadRequest().body("Embedding generation is not yet supported"));
-    }
+        if config.rate_limits_enabled {
+            rate_limiter.check(x_tembo_org, x_tembo_inst).await?;
+        }
 
-    let rewrite_request = rewrite_model_request(body.clone(), &config)?;
+        let mut rewrite_request =
+            rewrite_model_request(body.clone(), &*registry.read().await, x_tembo_org)?;
+        model.clone_from(&rewrite_request.model);
+        let streaming =
+            request_type == RequestType::Completion && is_streaming(&rewrite_request.body);
+        let strip_usage_chunk = streaming && request_stream_usage(&mut rewrite_request.body);
 
-    let mut new_url = rewrite_request.base_url;
-    new_url.set_path(path);
-    new_url.set_query(req.uri().query());
+        let record = |status_code: u16| RequestRecord {
+            organization_id: x_tembo_org.to_string(),
+            instance_id: x_tembo_inst.to_string(),
+            api_key_id: caller.api_key_id.clone(),
+            model: rewrite_request.model.clone(),
+            request_type,
+            usage: Usage::default(),
+            duration_ms: start.elapsed().as_millis() as i32,
+            status_code: status_code as i32,
+            cache_hit: false,
+        };
+        let audited = audit_log.enabled_for(x_tembo_org).await;
+        let audit = |status_code: u16, completion: Option<serde_json::Value>| {
+            audited.then(|| AuditEntry {
+                organization_id: x_tembo_org.to_string(),
+                instance_id: x_tembo_inst.to_string(),
+                api_key_id: caller.api_key_id.clone(),
+                model: rewrite_request.model.clone(),
+                request_type: request_type.as_str().to_string(),
+                status_code: status_code as i32,
+                prompt: body.0.clone(),
+                completion,
+            })
+        };
 
-    // log request duration
-    let start = std::time::Instant::now();
-    let resp = client
-        .post(new_url)
-        .json(&rewrite_request.body)
-        .send()
-        .await?;
-    let duration = start.elapsed().as_millis() as i32;
-    if resp.status().is_success() {
-        let llm_resp = resp.json::<serde_json::Value>().await?;
-        let model = llm_resp
-            .get("model")
-            .ok_or_else(|| {
-                PlatformError::InvalidQuery("invalid response from model server".to_string())
-            })?
-            .as_str()
-            .ok_or_else(|| {
-                PlatformError::InvalidQuery("invalid response from model server".to_string())
-            })?;
-        let usage: Usage = serde_json::from_value(
-            llm_resp
-                .get("usage")
+        let cache_entry = if request_type == RequestType::Completion && !streaming {
+            response_cache
+                .key_for(x_tembo_org, &rewrite_request.model, &rewrite_request.body)
+                .await
+        } else {
+            None
+        };
+        if let Some(entry) = &cache_entry {
+            if let Some(cached) = response_cache.get(&dbclient, &entry.key).await {
+                let hit = RequestRecord {
+                    cache_hit: true,
+                    ..record(StatusCode::OK.as_u16())
+                };
+                record_request(hit, &dbclient).await;
+                if let Some(entry) = audit(StatusCode::OK.as_u16(), Some(cached.clone())) {
+                    audit_log.record(&dbclient, entry).await;
+                }
+                return Ok(HttpResponse::Ok()
+                    .insert_header((CACHE_HEADER, "hit"))
+                    .json(cached));
+            }
+        }
+
+        let upstream = UpstreamRequest {
+            model: &rewrite_request.model,
+            path,
+            query: req.uri().query(),
+            body: &rewrite_request.body,
+            // streams stay open for as long as the model generates, so only bound other requests
+            timeout: (!streaming).then(|| Duration::from_secs(config.upstream_timeout_sec)),
+            headers: telemetry::trace_headers(&trace_cx),
+        };
+        let (resp, in_flight) = match router.send(&client, &upstream).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                if let PlatformError::Reqwest(reqwest_error) = &e {
+                    log::error!("request to model server failed: {}", reqwest_error);
+                    let status = if reqwest_error.is_timeout() {
+                        StatusCode::GATEWAY_TIMEOUT
+                    } else {
+                        StatusCode::BAD_GATEWAY
+                    };
+                    record_request(record(status.as_u16()), &dbclient).await;
+                }
+                return Err(e);
+            }
+        };
+
+        if streaming && resp.status().is_success() {
+            let (tx, rx) = tokio::sync::mpsc::channel(STREAM_CHANNEL_CAPACITY);
+            let mut streamed = record(StatusCode::OK.as_u16());
+            let stream_audit = audit(StatusCode::OK.as_u16(), None);
+            let pool = dbclient.get_ref().clone();
+            let audit_log = audit_log.get_ref().clone();
+            let prompt_estimate = estimate_prompt_tokens(&rewrite_request.body);
+            actix_rt::spawn(async move {
+                let response = forward_stream(
+                    resp,
+                    in_flight,
+                    tx,
+                    strip_usage_chunk,
+                    stream_audit.is_some(),
+                    streamed.model.clone(),
+                    start,
+                )
+                .await;
+                if let Some(mut entry) = stream_audit {
+                    entry.model.clone_from(&response.model);
+                    entry.completion = Some(response.as_completion());
+                    audit_log.record(&pool, entry).await;
+                }
+                let usage = match response.usage {
+                    Some(usage) => usage,
+                    None => {
+                        // upstream failed before its usage chunk, bill what was generated
+                        let usage = Usage {
+                            prompt_tokens: prompt_estimate,
+                            completion_tokens: response.content_events,
+                        };
+                        log::warn!(
+                            "streamed response ended without usage, recording estimate {:?}",
+                            usage
+                        );
+                        usage
+                    }
+                };
+                streamed.model = response.model;
+                streamed.usage = usage;
+                streamed.duration_ms = response.duration_ms;
+                record_request(streamed, &pool).await;
+            });
+            return Ok(HttpResponse::Ok()
+                .content_type("text/event-stream")
+                .insert_header(("Cache-Control", "no-cache"))
+                .body(ChannelBody::new(rx)));
+        }
+        let duration = start.elapsed().as_millis() as i32;
+        if resp.status().is_success() {
+            let llm_resp = resp.json::<serde_json::Value>().await?;
+            let model = llm_resp
+                .get("model")
                 .ok_or_else(|| {
                     PlatformError::InvalidQuery("invalid response from model server".to_string())
                 })?
-                .clone(),
-        )?;
-        if let Err(e) =
-            insert_data(x_tembo_org, x_tembo_inst, model, usage, duration, &dbclient).await
-        {
-            log::error!("{}", e);
+                .as_str()
+                .ok_or_else(|| {
+                    PlatformError::InvalidQuery("invalid response from model server".to_string())
+                })?;
+            let usage: Usage = serde_json::from_value(
+                llm_resp
+                    .get("usage")
+                    .ok_or_else(|| {
+                        PlatformError::InvalidQuery(
+                            "invalid response from model server".to_string(),
+                        )
+                    })?
+                    .clone(),
+            )?;
+            let completed = RequestRecord {
+                model: model.to_string(),
+                usage,
+                duration_ms: duration,
+                ..record(StatusCode::OK.as_u16())
+            };
+            record_request(completed, &dbclient).await;
+            if let Some(entry) = audit(StatusCode::OK.as_u16(), Some(llm_resp.clone())) {
+                audit_log.record(&dbclient, entry).await;
+            }
+            let mut builder = HttpResponse::Ok();
+            if let Some(entry) = &cache_entry {
+                response_cache
+                    .put(&dbclient, entry, x_tembo_org, model, &llm_resp)
+                    .await;
+                builder.insert_header((CACHE_HEADER, "miss"));
+            }
+            Ok(builder.json(llm_resp))
+        } else {
+            let status = resp.status();
+            let error = resp.text().await?;
+            log::warn!(
+                "model server returned {} for model {}: {}",
+                status,
+                rewrite_request.model,
+                error
+            );
+            record_request(record(status.as_u16()), &dbclient).await;
+            let completion = serde_json::Value::String(error.clone());
+            if let Some(entry) = audit(status.as_u16(), Some(completion)) {
+                audit_log.record(&dbclient, entry).await;
+            }
+            Ok::<HttpResponse, PlatformError>(HttpResponse::BadRequest().body(error))
+        }
+    }
+    .await;
+
+    let status = match &result {
+        Ok(resp) => resp.status(),
+        Err(e) => e.status_code(),
+    };
+    metrics::observe_request(
+        &model,
+        &org_id,
+        request_type.as_str(),
+        status.as_u16(),
+        start.elapsed(),
+    );
+    telemetry::end_request_span(&trace_cx, &model, &org_id, status.as_u16());
+    result
+}
+
+pub(crate) fn required_header<'a>(
+    req: &'a HttpRequest,
+    name: &str,
+) -> Result<&'a str, PlatformError> {
+    match req.headers().get(name) {
+        Some(header) => header
+            .to_str()
+            .map_err(|_| AuthError::Forbidden(format!("Invalid request header `{}`", name)).into()),
+        None => Err(AuthError::Forbidden(format!("Missing request header `{}`", name)).into()),
+    }
+}
+
+const STREAM_CHANNEL_CAPACITY: usize = 32;
+
+// Set on cacheable responses, to `hit` when the response was served from the cache
+const CACHE_HEADER: &str = "X-Tembo-Cache";
+
+/// What was streamed to the client
+#[derive(Debug)]
+struct StreamedResponse {
+    model: String,
+    usage: Option<Usage>,
+    duration_ms: i32,
+    // only collected for the audit log
+    content: String,
+    content_events: i32,
+}
+
+impl StreamedResponse {
+    // The streamed response as the equivalent non-streamed response
+    fn as_completion(&self) -> serde_json::Value {
+        serde_json::json!({
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": self.content},
+            }],
+            "usage": self.usage,
+        })
+    }
+}
+
+// Pass the upstream event stream on to the client as it arrives,
+// returning the model, usage and total duration once upstream finishes.
+// Upstream is read to the end even if the client disconnects, since the
+// model keeps generating and the usage chunk comes last.
+async fn forward_stream(
+    mut resp: reqwest::Response,
+    // keeps the request counted against the backend until the stream ends
+    _in_flight: InFlight,
+    tx: tokio::sync::mpsc::Sender<web::Bytes>,
+    strip_usage_chunk: bool,
+    collect_content: bool,
+    mapped_model: String,
+    start: std::time::Instant,
+) -> StreamedResponse {
+    let mut parser = SseUsageParser::new(strip_usage_chunk);
+    if collect_content {
+        parser = parser.collecting_content();
+    }
+    let mut client_connected = true;
+    loop {
+        let out = match resp.chunk().await {
+            Ok(Some(chunk)) => parser.feed(&chunk),
+            Ok(None) => {
+                let out = parser.finish();
+                if client_connected && !out.is_empty() {
+                    let _ = tx.send(web::Bytes::from(out)).await;
+                }
+                break;
+            }
+            Err(e) => {
+                log::error!("error reading streamed response from model server: {}", e);
+                break;
+            }
+        };
+        if client_connected && !out.is_empty() && tx.send(web::Bytes::from(out)).await.is_err() {
+            log::debug!("client disconnected from streamed response, draining upstream");
+            client_connected = false;
         }
-        Ok(HttpResponse::Ok().json(llm_resp))
-    } else {
-        let error = resp.text().await?;
-        Ok(HttpResponse::BadRequest().body(error))
     }
+
+    StreamedResponse {
+        model: parser.model.unwrap_or(mapped_model),
+        usage: parser.usage,
+        duration_ms: start.elapsed().as_millis() as i32,
+        content: parser.content,
+        content_events: parser.content_events,
+    }
+}
+
+// Rough prompt size for when upstream never reports usage, at about 4 bytes per token
+fn estimate_prompt_tokens(body: &serde_json::Value) -> i32 {
+    let prompt = body.get("messages").or_else(|| body.get("prompt"));
+    let bytes = prompt.map_or(0, |prompt| prompt.to_string().len());
+    (bytes / 4) as i32
 }
 
-async fn insert_data(
-    org: &str,
-    isnt: &str,
-    model: &str,
+/// A request to be recorded in `inference.requests`
+#[derive(Debug)]
+struct RequestRecord {
+    organization_id: String,
+    instance_id: String,
+    api_key_id: Option<String>,
+    model: String,
+    request_type: RequestType,
     usage: Usage,
     duration_ms: i32,
-    con: &Pool<Postgres>,
-) -> Result<(), PlatformError> {
+    status_code: i32,
+    cache_hit: bool,
+}
+
+async fn record_request(record: RequestRecord, con: &Pool<Postgres>) {
+    metrics::observe_tokens(
+        &record.model,
+        &record.organization_id,
+        record.usage.prompt_tokens,
+        record.usage.completion_tokens,
+    );
+    if let Err(e) = insert_data(&record, con).await {
+        log::error!("{}", e);
+    }
+}
+
+async fn insert_data(record: &RequestRecord, con: &Pool<Postgres>) -> Result<(), PlatformError> {
     let _r = sqlx::query!(
-        "INSERT INTO inference.requests ( organization_id, instance_id, model, prompt_tokens, completion_tokens, duration_ms )
-        VALUES ($1, $2, $3, $4, $5, $6)",
-        org,
-        isnt,
-        model,
-        usage.prompt_tokens,
-        usage.completion_tokens,
-        duration_ms
+        "INSERT INTO inference.requests ( organization_id, instance_id, model, prompt_tokens, completion_tokens, duration_ms, request_type, status_code, api_key_id, cache_hit )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        record.organization_id,
+        record.instance_id,
+        record.model,
+        record.usage.prompt_tokens,
+        record.usage.completion_tokens,
+        record.duration_ms,
+        record.request_type.as_str(),
+        record.status_code,
+        record.api_key_id,
+        record.cache_hit
     )
     .execute(con)
     .await?;
     Ok(())
 }
 
-#[derive(Serialize, Deserialize)]
-struct Usage {
-    prompt_tokens: i32,
-    completion_tokens: i32,
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: i32,
+    // embeddings only report input tokens
+    #[serde(default)]
+    pub completion_tokens: i32,
+}
+
+/// Kind of inference request, recorded with its usage so it can be billed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestType {
+    Completion,
+    Embedding,
+}
+
+impl RequestType {
+    pub fn from_path(path: &str) -> Self {
+        if path.trim_end_matches('/').ends_with("/embeddings") {
+            RequestType::Embedding
+        } else {
+            RequestType::Completion
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestType::Completion => "completion",
+            RequestType::Embedding => "embedding",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_type_from_path() {
+        assert_eq!(
+            RequestType::from_path("/v1/chat/completions"),
+            RequestType::Completion
+        );
+        assert_eq!(
+            RequestType::from_path("/v1/completions"),
+            RequestType::Completion
+        );
+        assert_eq!(
+            RequestType::from_path("/v1/embeddings"),
+            RequestType::Embedding
+        );
+    }
+
+    #[test]
+    fn test_estimate_prompt_tokens() {
+        let body = serde_json::json!({
+            "model": "m",
+            "messages": [{"role": "user", "content": "hello there, how are you?"}],
+        });
+        assert_eq!(estimate_prompt_tokens(&body), 13);
+        assert_eq!(
+            estimate_prompt_tokens(&serde_json::json!({"prompt": "abcdefgh"})),
+            2
+        );
+        assert_eq!(estimate_prompt_tokens(&serde_json::json!({})), 0);
+    }
+
+    #[test]
+    fn test_embedding_usage() {
+        let usage: Usage =
+            serde_json::from_value(serde_json::json!({"prompt_tokens": 8, "total_tokens": 8}))
+                .unwrap();
+        assert_eq!(usage.prompt_tokens, 8);
+        assert_eq!(usage.completion_tokens, 0);
+    }
 }
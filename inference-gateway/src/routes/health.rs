@@ -1,4 +1,8 @@
-use actix_web::{get, HttpResponse, Responder};
+use actix_web::{get, web, HttpResponse, Responder};
+use std::sync::Arc;
+
+use crate::metrics;
+use crate::routing::ModelRouter;
 
 #[get("/ready")]
 async fn ready() -> impl Responder {
@@ -9,3 +13,23 @@ async fn ready() -> impl Responder {
 async fn lively() -> impl Responder {
     HttpResponse::Ok().json("alive")
 }
+
+/// Health and request statistics of every model server
+#[get("/backends")]
+async fn backends(router: web::Data<Arc<ModelRouter>>) -> impl Responder {
+    HttpResponse::Ok().json(router.stats())
+}
+
+/// Prometheus metrics for requests, token usage and model servers
+#[get("/metrics")]
+async fn prometheus_metrics(router: web::Data<Arc<ModelRouter>>) -> impl Responder {
+    match metrics::gather(&router.stats()) {
+        Ok(text) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(text),
+        Err(e) => {
+            log::error!("Failed to encode metrics: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
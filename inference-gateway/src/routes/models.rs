@@ -0,0 +1,49 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::api_keys::{resolve_caller, ApiKeyStore};
+use crate::authorization;
+use crate::errors::{AuthError, PlatformError};
+use crate::registry::ModelRegistry;
+
+/// Models the calling organization can use, in the OpenAI list format
+#[get("/v1/models")]
+pub async fn list_models(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+    cache: web::Data<Arc<RwLock<HashMap<String, bool>>>>,
+    api_keys: web::Data<Arc<ApiKeyStore>>,
+    registry: web::Data<Arc<RwLock<ModelRegistry>>>,
+) -> Result<HttpResponse, PlatformError> {
+    let caller = resolve_caller(&req, &config, &api_keys).await?;
+    let x_tembo_org = caller.organization_id.as_str();
+
+    if config.org_auth_enabled {
+        let is_valid = authorization::auth_org(x_tembo_org, &cache).await;
+        if !is_valid {
+            return Err(AuthError::Forbidden("Organization is not authorized".to_string()).into());
+        }
+    }
+
+    let models: Vec<serde_json::Value> = registry
+        .read()
+        .await
+        .available_models(x_tembo_org)
+        .into_iter()
+        .map(|id| {
+            serde_json::json!({
+                "id": id,
+                "object": "model",
+                "created": 0,
+                "owned_by": "tembo",
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "object": "list",
+        "data": models,
+    })))
+}
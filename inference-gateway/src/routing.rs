@@ -0,0 +1,525 @@
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::config::Config;
+use crate::errors::PlatformError;
+use crate::metrics;
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Upstream responses which mean the request was not processed, so it is safe to send elsewhere
+const RETRYABLE_STATUSES: [StatusCode; 3] = [
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BalanceStrategy {
+    RoundRobin,
+    LeastOutstanding,
+}
+
+impl FromStr for BalanceStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(BalanceStrategy::RoundRobin),
+            "least_outstanding" => Ok(BalanceStrategy::LeastOutstanding),
+            _ => Err(format!("unknown load balancing strategy: {s}")),
+        }
+    }
+}
+
+/// A request to be sent to one of a model's backends
+#[derive(Debug)]
+pub struct UpstreamRequest<'a> {
+    pub model: &'a str,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub body: &'a serde_json::Value,
+    pub timeout: Option<Duration>,
+    /// Extra headers, such as the trace context
+    pub headers: HashMap<String, String>,
+}
+
+/// A model server, with its health and request statistics
+#[derive(Debug)]
+pub struct Backend {
+    pub url: Url,
+    healthy: AtomicBool,
+    consecutive_failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+    outstanding: AtomicUsize,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    latency_ms_total: AtomicU64,
+}
+
+impl Backend {
+    fn new(url: Url) -> Self {
+        Self {
+            url,
+            healthy: AtomicBool::new(true),
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+            outstanding: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latency_ms_total: AtomicU64::new(0),
+        }
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| until > now)
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        self.healthy.load(Ordering::Relaxed) && !self.is_ejected(now)
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    /// Track a request sent to this backend until the returned guard is dropped
+    pub fn start(self: &Arc<Self>) -> InFlight {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        InFlight {
+            backend: self.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// A request in progress on a backend
+#[derive(Debug)]
+pub struct InFlight {
+    pub backend: Arc<Backend>,
+    started: Instant,
+}
+
+impl InFlight {
+    pub fn success(&self) {
+        let backend = &self.backend;
+        backend
+            .latency_ms_total
+            .fetch_add(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        backend.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Record an error, ejecting the backend for a while after too many in a row
+    pub fn failure(&self, router: &ModelRouter) {
+        let backend = &self.backend;
+        backend.errors.fetch_add(1, Ordering::Relaxed);
+        let failures = backend.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= router.eject_after_failures {
+            log::warn!(
+                "Ejecting backend {} for {:?} after {} consecutive failures",
+                backend.url,
+                router.ejection_duration,
+                failures
+            );
+            *backend.ejected_until.lock().unwrap() =
+                Some(Instant::now() + router.ejection_duration);
+            backend.consecutive_failures.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.backend.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BackendStats {
+    pub model: String,
+    pub url: String,
+    pub healthy: bool,
+    pub ejected: bool,
+    pub outstanding: usize,
+    pub requests: u64,
+    pub errors: u64,
+    pub avg_latency_ms: u64,
+}
+
+#[derive(Debug)]
+struct BackendPool {
+    backends: Vec<Arc<Backend>>,
+    next: AtomicUsize,
+}
+
+/// Spreads requests for a model across its backends, skipping unhealthy ones
+#[derive(Debug)]
+pub struct ModelRouter {
+    pools: RwLock<HashMap<String, BackendPool>>,
+    strategy: BalanceStrategy,
+    eject_after_failures: u32,
+    ejection_duration: Duration,
+    retries: u32,
+}
+
+impl ModelRouter {
+    pub fn new(cfg: &Config) -> Self {
+        let router = Self {
+            pools: RwLock::new(HashMap::new()),
+            strategy: cfg.balance_strategy,
+            eject_after_failures: cfg.eject_after_failures,
+            ejection_duration: Duration::from_secs(cfg.ejection_sec),
+            retries: cfg.upstream_retries,
+        };
+        router.set_backends(&cfg.model_service_map);
+        router
+    }
+
+    /// Replace the backends of every model, keeping the state of backends which remain
+    pub fn set_backends(&self, models: &HashMap<String, Vec<Url>>) {
+        let mut pools = self.pools.write().unwrap();
+        let mut existing: HashMap<Url, Arc<Backend>> = pools
+            .values()
+            .flat_map(|pool| pool.backends.iter())
+            .map(|backend| (backend.url.clone(), backend.clone()))
+            .collect();
+
+        *pools = models
+            .iter()
+            .map(|(model, urls)| {
+                let backends = urls
+                    .iter()
+                    .map(|url| {
+                        existing
+                            .entry(url.clone())
+                            .or_insert_with(|| Arc::new(Backend::new(url.clone())))
+                            .clone()
+                    })
+                    .collect();
+                (
+                    model.clone(),
+                    BackendPool {
+                        backends,
+                        next: AtomicUsize::new(0),
+                    },
+                )
+            })
+            .collect();
+    }
+
+    /// Choose a backend for the model, excluding ones which were already tried
+    pub fn pick(&self, model: &str, exclude: &[Url]) -> Option<Arc<Backend>> {
+        let pools = self.pools.read().unwrap();
+        let pool = pools.get(model)?;
+        let candidates: Vec<&Arc<Backend>> = pool
+            .backends
+            .iter()
+            .filter(|backend| !exclude.contains(&backend.url))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let available: Vec<&Arc<Backend>> = candidates
+            .iter()
+            .copied()
+            .filter(|backend| backend.is_available(now))
+            .collect();
+        // when every backend looks down, still try one rather than failing outright
+        let choices = if available.is_empty() {
+            candidates
+        } else {
+            available
+        };
+
+        let offset = pool.next.fetch_add(1, Ordering::Relaxed);
+        let backend = match self.strategy {
+            BalanceStrategy::RoundRobin => choices[offset % choices.len()],
+            BalanceStrategy::LeastOutstanding => {
+                // rotate the starting point so ties are spread evenly
+                (0..choices.len())
+                    .map(|i| choices[(offset + i) % choices.len()])
+                    .min_by_key(|backend| backend.outstanding())
+                    .unwrap()
+            }
+        };
+        Some(backend.clone())
+    }
+
+    fn unique_backends(&self) -> Vec<Arc<Backend>> {
+        let pools = self.pools.read().unwrap();
+        let mut backends: HashMap<Url, Arc<Backend>> = HashMap::new();
+        for backend in pools.values().flat_map(|pool| pool.backends.iter()) {
+            backends.insert(backend.url.clone(), backend.clone());
+        }
+        backends.into_values().collect()
+    }
+
+    pub fn stats(&self) -> Vec<BackendStats> {
+        let now = Instant::now();
+        let pools = self.pools.read().unwrap();
+        let mut stats: Vec<BackendStats> = pools
+            .iter()
+            .flat_map(|(model, pool)| {
+                pool.backends.iter().map(move |backend| {
+                    let requests = backend.requests.load(Ordering::Relaxed);
+                    let errors = backend.errors.load(Ordering::Relaxed);
+                    let latency = backend.latency_ms_total.load(Ordering::Relaxed);
+                    let successes = requests.saturating_sub(errors);
+                    BackendStats {
+                        model: model.clone(),
+                        url: backend.url.to_string(),
+                        healthy: backend.healthy.load(Ordering::Relaxed),
+                        ejected: backend.is_ejected(now),
+                        outstanding: backend.outstanding(),
+                        requests,
+                        errors,
+                        avg_latency_ms: latency.checked_div(successes).unwrap_or(0),
+                    }
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| (&a.model, &a.url).cmp(&(&b.model, &b.url)));
+        stats
+    }
+
+    /// Poll the `/health` endpoint of every backend
+    pub async fn run_health_checks(self: Arc<Self>, client: reqwest::Client, interval: Duration) {
+        loop {
+            for backend in self.unique_backends() {
+                let mut url = backend.url.clone();
+                url.set_path("/health");
+                let healthy = match client.get(url).timeout(HEALTH_CHECK_TIMEOUT).send().await {
+                    Ok(resp) => resp.status().is_success(),
+                    Err(e) => {
+                        log::debug!("Health check for {} failed: {}", backend.url, e);
+                        false
+                    }
+                };
+                let was_healthy = backend.healthy.swap(healthy, Ordering::Relaxed);
+                if was_healthy != healthy {
+                    log::warn!(
+                        "Backend {} is now {}",
+                        backend.url,
+                        if healthy { "healthy" } else { "unhealthy" }
+                    );
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Send the request to a backend of the model. Requests which never reached a model
+    /// server are retried on a different backend.
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        upstream: &UpstreamRequest<'_>,
+    ) -> Result<(reqwest::Response, InFlight), PlatformError> {
+        let model = upstream.model;
+        let mut tried: Vec<Url> = Vec::new();
+        loop {
+            let backend = self
+                .pick(model, &tried)
+                .ok_or_else(|| PlatformError::InvalidQuery(format!("model {} not found", model)))?;
+            tried.push(backend.url.clone());
+            let can_retry = tried.len() <= self.retries as usize;
+
+            let mut url = backend.url.clone();
+            url.set_path(upstream.path);
+            url.set_query(upstream.query);
+
+            let in_flight = backend.start();
+            let mut request = client.post(url).json(upstream.body);
+            for (name, value) in &upstream.headers {
+                request = request.header(name, value);
+            }
+            if let Some(timeout) = upstream.timeout {
+                request = request.timeout(timeout);
+            }
+            let result = request.send().await;
+            let backend_label = backend.url.as_str();
+            match result {
+                Ok(resp) if RETRYABLE_STATUSES.contains(&resp.status()) => {
+                    metrics::observe_upstream(model, backend_label, resp.status().as_str(), None);
+                    in_flight.failure(self);
+                    if can_retry && self.has_other_backends(model, &tried) {
+                        log::warn!(
+                            "Backend {} returned {}, retrying on another backend",
+                            backend.url,
+                            resp.status()
+                        );
+                        continue;
+                    }
+                    return Ok((resp, in_flight));
+                }
+                Ok(resp) => {
+                    metrics::observe_upstream(
+                        model,
+                        backend_label,
+                        resp.status().as_str(),
+                        Some(in_flight.started.elapsed()),
+                    );
+                    if resp.status().is_server_error() {
+                        in_flight.failure(self);
+                    } else {
+                        in_flight.success();
+                    }
+                    return Ok((resp, in_flight));
+                }
+                Err(e) => {
+                    let reason = if e.is_timeout() {
+                        "timeout"
+                    } else if e.is_connect() {
+                        "connect"
+                    } else {
+                        "error"
+                    };
+                    metrics::observe_upstream(model, backend_label, reason, None);
+                    in_flight.failure(self);
+                    if e.is_connect() && can_retry && self.has_other_backends(model, &tried) {
+                        log::warn!(
+                            "Failed to connect to backend {}, retrying on another backend: {}",
+                            backend.url,
+                            e
+                        );
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    fn has_other_backends(&self, model: &str, tried: &[Url]) -> bool {
+        self.pools
+            .read()
+            .unwrap()
+            .get(model)
+            .is_some_and(|pool| pool.backends.iter().any(|b| !tried.contains(&b.url)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(strategy: BalanceStrategy, urls: &[&str]) -> ModelRouter {
+        let router = ModelRouter {
+            pools: RwLock::new(HashMap::new()),
+            strategy,
+            eject_after_failures: 2,
+            ejection_duration: Duration::from_secs(30),
+            retries: 1,
+        };
+        let mut models = HashMap::new();
+        models.insert(
+            "model".to_string(),
+            urls.iter().map(|url| Url::parse(url).unwrap()).collect(),
+        );
+        router.set_backends(&models);
+        router
+    }
+
+    fn pick_host(router: &ModelRouter, exclude: &[Url]) -> String {
+        router
+            .pick("model", exclude)
+            .unwrap()
+            .url
+            .host_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let router = router(
+            BalanceStrategy::RoundRobin,
+            &["http://a:8000", "http://b:8000"],
+        );
+        assert_eq!(pick_host(&router, &[]), "a");
+        assert_eq!(pick_host(&router, &[]), "b");
+        assert_eq!(pick_host(&router, &[]), "a");
+        assert!(router.pick("other-model", &[]).is_none());
+    }
+
+    #[test]
+    fn test_least_outstanding() {
+        let router = router(
+            BalanceStrategy::LeastOutstanding,
+            &["http://a:8000", "http://b:8000"],
+        );
+        let a = router.pick("model", &[]).unwrap();
+        let _in_flight = a.start();
+        // b has no outstanding requests, so it's chosen until it does
+        assert_eq!(pick_host(&router, &[]), "b");
+        assert_eq!(pick_host(&router, &[]), "b");
+        drop(_in_flight);
+        assert_eq!(a.outstanding(), 0);
+    }
+
+    #[test]
+    fn test_passive_ejection() {
+        let router = router(
+            BalanceStrategy::RoundRobin,
+            &["http://a:8000", "http://b:8000"],
+        );
+        let a = router.pick("model", &[]).unwrap();
+        a.start().failure(&router);
+        assert!(a.is_available(Instant::now()));
+        a.start().failure(&router);
+        assert!(!a.is_available(Instant::now()));
+
+        for _ in 0..4 {
+            assert_eq!(pick_host(&router, &[]), "b");
+        }
+        // with b excluded, the ejected backend is still tried
+        let b = Url::parse("http://b:8000").unwrap();
+        assert_eq!(pick_host(&router, &[b]), "a");
+    }
+
+    #[test]
+    fn test_set_backends_keeps_state() {
+        let router = router(BalanceStrategy::RoundRobin, &["http://a:8000"]);
+        let a = router.pick("model", &[]).unwrap();
+        a.healthy.store(false, Ordering::Relaxed);
+
+        let mut models = HashMap::new();
+        models.insert(
+            "model".to_string(),
+            vec![
+                Url::parse("http://a:8000").unwrap(),
+                Url::parse("http://c:8000").unwrap(),
+            ],
+        );
+        router.set_backends(&models);
+        for _ in 0..3 {
+            assert_eq!(pick_host(&router, &[]), "c");
+        }
+
+        let stats = router.stats();
+        assert_eq!(stats.len(), 2);
+        assert!(!stats[0].healthy);
+        assert!(stats[1].healthy);
+    }
+
+    #[test]
+    fn test_balance_strategy_from_str() {
+        assert_eq!(
+            "least_outstanding".parse::<BalanceStrategy>().unwrap(),
+            BalanceStrategy::LeastOutstanding
+        );
+        assert!("random".parse::<BalanceStrategy>().is_err());
+    }
+}
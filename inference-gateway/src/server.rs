@@ -1,7 +1,13 @@
 use actix_web::web;
 
+use crate::api_keys::ApiKeyStore;
+use crate::audit::AuditLog;
+use crate::cache::ResponseCache;
+use crate::limits::RateLimiter;
+use crate::registry::ModelRegistry;
 use crate::routes;
-use crate::{authorization, config, db};
+use crate::routing::ModelRouter;
+use crate::{authorization, config, db, metrics};
 
 use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
@@ -13,14 +19,31 @@ pub fn webserver_routes(configuration: &mut web::ServiceConfig) {
     configuration
         .service(routes::health::ready)
         .service(routes::health::lively)
+        .service(routes::models::list_models)
+        .service(routes::audit::list_audit_logs)
         .default_service(web::to(routes::forward::forward_request));
 }
 
+/// Routes for the internal port, these expose backend URLs and per org statistics
+pub fn internal_routes(configuration: &mut web::ServiceConfig) {
+    configuration
+        .service(routes::health::ready)
+        .service(routes::health::lively)
+        .service(routes::health::backends)
+        .service(routes::health::prometheus_metrics);
+}
+
 #[derive(Debug, Clone)]
 pub struct ServerStartUpConfig {
     pub cfg: config::Config,
     pub pool: Arc<Pool<Postgres>>,
     pub auth_cache: Arc<RwLock<HashMap<String, bool>>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub api_keys: Arc<ApiKeyStore>,
+    pub response_cache: Arc<ResponseCache>,
+    pub audit_log: Arc<AuditLog>,
+    pub router: Arc<ModelRouter>,
+    pub registry: Arc<RwLock<ModelRegistry>>,
     pub http_client: reqwest::Client,
 }
 
@@ -28,38 +51,126 @@ pub async fn webserver_startup_config(cfg: config::Config) -> ServerStartUpConfi
     let dbclient: Pool<Postgres> = db::connect(&cfg.pg_conn_str, cfg.server_workers as u32)
         .await
         .expect("Failed to connect to database");
-    sqlx::migrate!("./migrations")
-        .run(&dbclient)
+    db::migrate(&dbclient)
         .await
         .expect("Failed to run migrations");
     let pool = Arc::new(dbclient);
-    let http_client: reqwest::Client = reqwest::Client::new();
+    metrics::init();
+    let http_client: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(cfg.upstream_connect_timeout_sec))
+        .build()
+        .expect("Failed to build http client");
+    let router = Arc::new(ModelRouter::new(&cfg));
+    let registry = Arc::new(RwLock::new(ModelRegistry::from_config(&cfg)));
+
+    log::info!("Starting background task to reload the model registry");
+    let registry_refresher = registry.clone();
+    let router_for_bg_task = router.clone();
+    let pool_for_bg_task = pool.clone();
+    let registry_cfg = cfg.clone();
+    actix_rt::spawn(async move {
+        loop {
+            match ModelRegistry::load(&pool_for_bg_task, &registry_cfg).await {
+                Ok(new_registry) => {
+                    router_for_bg_task.set_backends(&new_registry.backend_map());
+                    *registry_refresher.write().await = new_registry;
+                }
+                Err(e) => {
+                    log::error!("Failed to reload model registry: {:?}", e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(
+                registry_cfg.model_registry_refresh_interval_sec,
+            ))
+            .await;
+        }
+    });
+
+    log::info!("Starting background task to health check model servers");
+    actix_rt::spawn(router.clone().run_health_checks(
+        http_client.clone(),
+        Duration::from_secs(cfg.health_check_interval_sec),
+    ));
     let auth_cache = Arc::new(RwLock::new(HashMap::<String, bool>::new()));
+    let rate_limiter = Arc::new(RateLimiter::new().with_replicas(cfg.rate_limit_replicas));
+    let api_keys = Arc::new(ApiKeyStore::new());
+    let response_cache = Arc::new(ResponseCache::new(&cfg));
+    let audit_log = Arc::new(AuditLog::new(&cfg));
 
-    if cfg.org_auth_enabled {
-        log::info!("Starting background task to refresh org auth cache");
+    if !cfg.org_auth_enabled {
+        log::info!("Org auth is disabled");
+    }
+    if !cfg.rate_limits_enabled {
+        log::info!("Rate limits are disabled");
+    }
+    if !cfg.api_key_auth_enabled {
+        log::info!("API key auth is disabled, trusting X-TEMBO-ORG and X-TEMBO-INSTANCE headers");
+    }
+    if !response_cache.is_enabled() {
+        log::info!("Response cache is disabled");
+    }
+    if !audit_log.is_enabled() {
+        log::info!("Audit log is disabled");
+    }
+    if cfg.org_auth_enabled
+        || cfg.rate_limits_enabled
+        || cfg.api_key_auth_enabled
+        || response_cache.is_enabled()
+        || audit_log.is_enabled()
+    {
+        log::info!("Starting background task to refresh auth, limits, API keys and cache settings");
         let cache_refresher = auth_cache.clone();
+        let limits_refresher = rate_limiter.clone();
+        let keys_refresher = api_keys.clone();
+        let response_cache_refresher = response_cache.clone();
+        let audit_log_refresher = audit_log.clone();
         let pool_for_bg_task = pool.clone();
+        let cfg = cfg.clone();
         actix_rt::spawn(async move {
             loop {
-                match authorization::refresh_cache(&pool_for_bg_task, &cache_refresher).await {
-                    Ok(_) => {}
-                    Err(e) => {
+                if cfg.org_auth_enabled {
+                    if let Err(e) =
+                        authorization::refresh_cache(&pool_for_bg_task, &cache_refresher).await
+                    {
                         log::error!("Failed to refresh cache: {:?}", e);
                     }
                 }
+                if cfg.rate_limits_enabled {
+                    if let Err(e) = limits_refresher.refresh(&pool_for_bg_task).await {
+                        log::error!("Failed to refresh rate limits: {:?}", e);
+                    }
+                }
+                if cfg.api_key_auth_enabled {
+                    if let Err(e) = keys_refresher.refresh(&pool_for_bg_task).await {
+                        log::error!("Failed to refresh API keys: {:?}", e);
+                    }
+                }
+                if response_cache_refresher.is_enabled() {
+                    if let Err(e) = response_cache_refresher.refresh(&pool_for_bg_task).await {
+                        log::error!("Failed to refresh response cache settings: {:?}", e);
+                    }
+                }
+                if audit_log_refresher.is_enabled() {
+                    if let Err(e) = audit_log_refresher.refresh(&pool_for_bg_task).await {
+                        log::error!("Failed to refresh audit log settings: {:?}", e);
+                    }
+                }
                 tokio::time::sleep(Duration::from_secs(cfg.org_auth_cache_refresh_interval_sec))
                     .await;
             }
         });
-    } else {
-        log::info!("Org auth is disabled");
     }
 
     ServerStartUpConfig {
         cfg,
         pool,
         auth_cache,
+        rate_limiter,
+        api_keys,
+        response_cache,
+        audit_log,
+        router,
+        registry,
         http_client,
     }
 }
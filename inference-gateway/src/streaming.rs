@@ -0,0 +1,291 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::web::Bytes;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+use crate::routes::forward::Usage;
+
+/// Response body which yields chunks as they are sent on the channel
+pub struct ChannelBody {
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl ChannelBody {
+    pub fn new(rx: mpsc::Receiver<Bytes>) -> Self {
+        Self { rx }
+    }
+}
+
+impl MessageBody for ChannelBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().rx.poll_recv(cx).map(|chunk| chunk.map(Ok))
+    }
+}
+
+/// Incrementally parses a server-sent-event stream of chat completion chunks,
+/// passing events through while capturing the model and the final token usage
+#[derive(Debug, Default)]
+pub struct SseUsageParser {
+    // bytes received which don't yet form a complete line
+    buffer: Vec<u8>,
+    // lines of the event currently being read
+    event: Vec<u8>,
+    drop_event: bool,
+    // drop the usage-only chunk, for clients which did not ask for it
+    strip_usage_chunk: bool,
+    // keep the generated text, for the audit log
+    collect_content: bool,
+    pub model: Option<String>,
+    pub usage: Option<Usage>,
+    pub content: String,
+    // events carrying generated text, roughly one per token, to estimate missing usage
+    pub content_events: i32,
+}
+
+impl SseUsageParser {
+    pub fn new(strip_usage_chunk: bool) -> Self {
+        Self {
+            strip_usage_chunk,
+            ..Default::default()
+        }
+    }
+
+    /// Also collect the text of the first choice as it is generated
+    pub fn collecting_content(mut self) -> Self {
+        self.collect_content = true;
+        self
+    }
+
+    /// Feed a chunk from upstream, returning the bytes to send to the client
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.buffer.extend_from_slice(chunk);
+        let mut out = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            self.event.extend_from_slice(&line);
+
+            let line = trim_line_ending(&line);
+            if line.is_empty() {
+                // a blank line terminates the event
+                if !self.drop_event {
+                    out.append(&mut self.event);
+                }
+                self.event.clear();
+                self.drop_event = false;
+            } else if let Some(data) = line.strip_prefix(b"data:") {
+                self.parse_data(data);
+            }
+        }
+        out
+    }
+
+    /// Flush whatever is left once upstream closes the stream
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = self.feed(b"");
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            if let Some(data) = trim_line_ending(&rest).strip_prefix(b"data:") {
+                self.parse_data(data);
+            }
+            self.event.extend_from_slice(&rest);
+        }
+        if !self.drop_event {
+            out.append(&mut self.event);
+        }
+        self.event.clear();
+        out
+    }
+
+    fn parse_data(&mut self, data: &[u8]) {
+        let data = data.strip_prefix(b" ").unwrap_or(data);
+        if data.starts_with(b"[DONE]") {
+            return;
+        }
+        let Ok(value) = serde_json::from_slice::<serde_json::Value>(data) else {
+            return;
+        };
+
+        if self.model.is_none() {
+            if let Some(model) = value.get("model").and_then(|m| m.as_str()) {
+                self.model = Some(model.to_string());
+            }
+        }
+
+        let choices = value.get("choices").and_then(|c| c.as_array());
+        if choices.is_some_and(|c| {
+            c.iter()
+                .any(|choice| choice["delta"]["content"].is_string())
+        }) {
+            self.content_events += 1;
+        }
+        if self.collect_content {
+            let delta = choices
+                .and_then(|c| c.iter().find(|choice| choice["index"] == 0))
+                .and_then(|choice| choice["delta"]["content"].as_str());
+            if let Some(delta) = delta {
+                self.content.push_str(delta);
+            }
+        }
+
+        if let Some(usage) = value.get("usage").filter(|u| !u.is_null()) {
+            match serde_json::from_value::<Usage>(usage.clone()) {
+                Ok(usage) => self.usage = Some(usage),
+                Err(e) => log::warn!("invalid usage in streamed response: {}", e),
+            }
+            let usage_only = value
+                .get("choices")
+                .and_then(|c| c.as_array())
+                .is_some_and(|c| c.is_empty());
+            if self.strip_usage_chunk && usage_only {
+                self.drop_event = true;
+            }
+        }
+    }
+}
+
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Ask the model server to send token usage as the last chunk of a streamed response.
+/// Returns true when the caller did not ask for it, and the chunk should not be passed on.
+pub fn request_stream_usage(body: &mut serde_json::Value) -> bool {
+    let requested = body
+        .get("stream_options")
+        .and_then(|o| o.get("include_usage"))
+        .and_then(|u| u.as_bool())
+        .unwrap_or(false);
+    if !requested {
+        match body
+            .get_mut("stream_options")
+            .and_then(|o| o.as_object_mut())
+        {
+            Some(options) => {
+                options.insert("include_usage".to_string(), serde_json::Value::Bool(true));
+            }
+            None => {
+                body["stream_options"] = serde_json::json!({ "include_usage": true });
+            }
+        }
+    }
+    !requested
+}
+
+/// True when the request body asks for a streamed response
+pub fn is_streaming(body: &serde_json::Value) -> bool {
+    body.get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_CHUNK: &str = "data: {\"id\":\"cmpl-1\",\"model\":\"facebook/opt-125m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The\"}}],\"usage\":null}\n\n";
+    const USAGE_CHUNK: &str = "data: {\"id\":\"cmpl-1\",\"model\":\"facebook/opt-125m\",\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":12,\"total_tokens\":17}}\n\n";
+    const DONE_CHUNK: &str = "data: [DONE]\n\n";
+
+    #[test]
+    fn test_parse_usage_from_stream() {
+        let mut parser = SseUsageParser::new(false);
+        let stream = format!("{CONTENT_CHUNK}{USAGE_CHUNK}{DONE_CHUNK}");
+
+        let mut out = parser.feed(stream.as_bytes());
+        out.extend(parser.finish());
+
+        assert_eq!(out, stream.as_bytes());
+        assert_eq!(parser.model.as_deref(), Some("facebook/opt-125m"));
+        let usage = parser.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 5);
+        assert_eq!(usage.completion_tokens, 12);
+    }
+
+    #[test]
+    fn test_events_split_across_chunks() {
+        let mut parser = SseUsageParser::new(false);
+        let stream = format!("{CONTENT_CHUNK}{USAGE_CHUNK}{DONE_CHUNK}");
+
+        let mut out = Vec::new();
+        for chunk in stream.as_bytes().chunks(7) {
+            out.extend(parser.feed(chunk));
+        }
+        out.extend(parser.finish());
+
+        assert_eq!(out, stream.as_bytes());
+        assert_eq!(parser.usage.unwrap().completion_tokens, 12);
+    }
+
+    #[test]
+    fn test_strip_usage_chunk() {
+        let mut parser = SseUsageParser::new(true);
+        let stream = format!("{CONTENT_CHUNK}{USAGE_CHUNK}{DONE_CHUNK}");
+
+        let mut out = parser.feed(stream.as_bytes());
+        out.extend(parser.finish());
+
+        assert_eq!(out, format!("{CONTENT_CHUNK}{DONE_CHUNK}").as_bytes());
+        assert_eq!(parser.usage.unwrap().prompt_tokens, 5);
+    }
+
+    #[test]
+    fn test_collect_content() {
+        let mut parser = SseUsageParser::new(false).collecting_content();
+        let stream = format!("{CONTENT_CHUNK}{CONTENT_CHUNK}{USAGE_CHUNK}{DONE_CHUNK}");
+
+        parser.feed(stream.as_bytes());
+        parser.finish();
+
+        assert_eq!(parser.content, "TheThe");
+        assert_eq!(parser.content_events, 2);
+        assert!(SseUsageParser::new(false).content.is_empty());
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let mut parser = SseUsageParser::new(true);
+        let stream = USAGE_CHUNK.replace('\n', "\r\n");
+
+        let mut out = parser.feed(stream.as_bytes());
+        out.extend(parser.finish());
+
+        assert!(out.is_empty());
+        assert_eq!(parser.usage.unwrap().completion_tokens, 12);
+    }
+
+    #[test]
+    fn test_request_stream_usage() {
+        let mut body = serde_json::json!({"model": "m", "stream": true});
+        assert!(is_streaming(&body));
+        assert!(request_stream_usage(&mut body));
+        assert_eq!(body["stream_options"]["include_usage"], true);
+
+        let mut body = serde_json::json!({
+            "model": "m",
+            "stream": true,
+            "stream_options": {"include_usage": true}
+        });
+        assert!(!request_stream_usage(&mut body));
+
+        let mut body = serde_json::json!({
+            "model": "m",
+            "stream_options": {"include_usage": false}
+        });
+        assert!(!is_streaming(&body));
+        assert!(request_stream_usage(&mut body));
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+}
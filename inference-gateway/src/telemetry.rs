@@ -0,0 +1,113 @@
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpRequest;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace as sdktrace, Resource,
+};
+use std::collections::HashMap;
+
+const SERVICE_NAME: &str = "tembo.io/inference-gateway";
+
+/// Propagate W3C trace context, and export spans when an OTLP endpoint is configured
+pub fn init(otlp_endpoint_url: &Option<String>) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let Some(endpoint) = otlp_endpoint_url else {
+        log::info!("OpenTelemetry endpoint not configured, spans will not be exported");
+        return;
+    };
+    let exporter = match SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            log::error!("Failed to build OpenTelemetry span exporter: {}", e);
+            return;
+        }
+    };
+    let provider = sdktrace::TracerProvider::builder()
+        .with_resource(Resource::new(vec![KeyValue::new(
+            opentelemetry_semantic_conventions::resource::SERVICE_NAME,
+            SERVICE_NAME,
+        )]))
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .build();
+    global::set_tracer_provider(provider);
+    log::info!("Exporting OpenTelemetry spans to {}", endpoint);
+}
+
+/// Flush spans which have not been exported yet
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Start a span for an incoming request, continuing the caller's trace if it sent one
+pub fn start_request_span(req: &HttpRequest) -> Context {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let tracer = global::tracer(SERVICE_NAME);
+    let span = tracer
+        .span_builder(format!("{} {}", req.method(), req.path()))
+        .with_kind(SpanKind::Server)
+        .start_with_context(&tracer, &parent);
+    parent.with_span(span)
+}
+
+/// Headers which continue the request's trace on the model server
+pub fn trace_headers(cx: &Context) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut headers));
+    headers
+}
+
+pub fn end_request_span(cx: &Context, model: &str, org_id: &str, status: u16) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("gen_ai.request.model", model.to_string()));
+    span.set_attribute(KeyValue::new("tembo.organization_id", org_id.to_string()));
+    span.set_attribute(KeyValue::new("http.response.status_code", status as i64));
+    if status >= 500 {
+        span.set_status(Status::error(format!("status {}", status)));
+    }
+    span.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_trace_context_propagation() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let req = TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header(("traceparent", traceparent))
+            .to_http_request();
+
+        let cx = start_request_span(&req);
+        let headers = trace_headers(&cx);
+        let propagated = headers.get("traceparent").unwrap();
+        // same trace, which continues on the model server
+        assert!(propagated.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        end_request_span(&cx, "llama", "org", 200);
+    }
+}
@@ -5,7 +5,6 @@ use rand::prelude::*;
 use sqlx::Row;
 use util::common;
 
-use env_logger;
 use gateway::config::Config;
 use gateway::db::{self, connect};
 
@@ -57,7 +56,7 @@ async fn test_logging() {
 
     let choices = body.get("choices").unwrap().as_array().unwrap();
     assert_eq!(choices.len(), 1);
-    choices.get(0).unwrap();
+    choices.first().unwrap();
 
     let conn = connect(&config.pg_conn_str, 2)
         .await
@@ -71,12 +70,54 @@ async fn test_logging() {
 
     assert_eq!(rows.len(), 1);
 
-    let row = rows.get(0).unwrap();
+    let row = rows.first().unwrap();
     assert_eq!(row.get::<String, &str>("instance_id"), instance);
     assert_eq!(row.get::<String, &str>("organization_id"), "MY-TEST-ORG");
     assert_eq!(row.get::<String, &str>("model"), "facebook/opt-125m");
 }
 
+#[ignore]
+#[actix_web::test]
+async fn test_embeddings() {
+    let config = Config::new().await;
+
+    let app = common::get_test_app(false).await;
+
+    let mut rng = rand::thread_rng();
+    let rnd = rng.gen_range(0..100000);
+    let instance = format!("MY-TEST-INSTANCE-{}", rnd);
+    let payload = serde_json::json!({
+        "model": "facebook/opt-125m",
+        "input": "San Francisco is a..."
+    });
+    let req = test::TestRequest::post()
+        .uri("/v1/embeddings")
+        .insert_header(("X-TEMBO-ORG", "MY-TEST-ORG"))
+        .insert_header(("X-TEMBO-INSTANCE", instance.clone()))
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .set_payload(payload.to_string())
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body.get("data").unwrap().as_array().unwrap().len(), 1);
+
+    let conn = connect(&config.pg_conn_str, 2)
+        .await
+        .expect("Failed to connect to database");
+
+    let row = sqlx::query("SELECT * FROM inference.requests WHERE instance_id = $1")
+        .bind(&instance)
+        .fetch_one(&conn)
+        .await
+        .expect("Failed to fetch log");
+
+    assert_eq!(row.get::<String, &str>("request_type"), "embedding");
+    assert_eq!(row.get::<i32, &str>("prompt_tokens"), 8);
+    assert_eq!(row.get::<i32, &str>("completion_tokens"), 0);
+}
+
 #[ignore]
 #[actix_web::test]
 async fn test_authorization() {
@@ -106,6 +147,15 @@ async fn test_authorization() {
     // this should fail because org_id is not validated
     assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
+    // so should reading the org's audit log
+    let req = test::TestRequest::get()
+        .uri("/v1/audit/logs")
+        .insert_header(("X-TEMBO-ORG", org_id.clone()))
+        .insert_header(("X-TEMBO-INSTANCE", "test-instance"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
     // set the org_id to validated
     let cfg = Config::new().await;
     let dbclient = db::connect(&cfg.pg_conn_str, 1)
@@ -137,6 +187,25 @@ async fn test_authorization() {
     assert!(resp.status().is_success());
 }
 
+#[ignore]
+#[actix_web::test]
+async fn test_list_models() {
+    let app = common::get_test_app(false).await;
+
+    let req = test::TestRequest::get()
+        .uri("/v1/models")
+        .insert_header(("X-TEMBO-ORG", "MY-TEST-ORG"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let models = body.get("data").unwrap().as_array().unwrap();
+    assert!(models
+        .iter()
+        .any(|model| model.get("id").unwrap() == "facebook/opt-125m"));
+}
+
 #[ignore]
 #[actix_web::test]
 async fn test_unavailable_model() {
@@ -192,3 +261,53 @@ async fn test_model_rewrite() {
     let return_model = body.get("model").unwrap().as_str().unwrap();
     assert_eq!(return_model, "facebook/opt-125m");
 }
+
+// runs the migrations which add inference.requests.id on a table which already has requests,
+// in a database of its own created by sqlx::test
+#[ignore]
+#[sqlx::test(migrations = false)]
+async fn test_request_id_migrations_with_rows(pool: sqlx::PgPool) {
+    sqlx::raw_sql(
+        "CREATE SCHEMA inference;
+        CREATE TABLE inference.requests (
+            organization_id text NOT NULL,
+            completed_at timestamptz NOT NULL DEFAULT now()
+        ) PARTITION BY RANGE (completed_at);
+        CREATE TABLE inference.requests_1 PARTITION OF inference.requests
+            FOR VALUES FROM ('2024-10-01') TO ('2024-10-03');
+        CREATE TABLE inference.requests_2 PARTITION OF inference.requests
+            FOR VALUES FROM ('2024-10-03') TO ('2024-10-08');
+        INSERT INTO inference.requests (organization_id, completed_at)
+        SELECT 'MY-TEST-ORG', '2024-10-01'::timestamptz + n * interval '1 hour'
+        FROM generate_series(0, 119) AS n;",
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create requests");
+
+    db::run_migrations(&pool, db::REQUEST_ID_MIGRATION..=db::REQUEST_ID_MIGRATION)
+        .await
+        .expect("Failed to add the id column");
+    assert_eq!(db::backfill_request_ids(&pool).await.unwrap(), 120);
+    db::run_migrations(
+        &pool,
+        db::REQUEST_ID_MIGRATION..=db::REQUEST_ID_NOT_NULL_MIGRATION,
+    )
+    .await
+    .expect("Failed to make the id column not null");
+
+    let row =
+        sqlx::query("SELECT count(*) AS total, count(DISTINCT id) AS ids FROM inference.requests")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(row.get::<i64, &str>("total"), 120);
+    assert_eq!(row.get::<i64, &str>("ids"), 120);
+
+    // new requests get an id from the default, and the backfill has nothing left to do
+    sqlx::query("INSERT INTO inference.requests (organization_id, completed_at) VALUES ('MY-TEST-ORG', '2024-10-07')")
+        .execute(&pool)
+        .await
+        .expect("Failed to insert a request");
+    assert_eq!(db::backfill_request_ids(&pool).await.unwrap(), 0);
+}
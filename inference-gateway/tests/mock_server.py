@@ -29,18 +29,38 @@ fixed_response = {
   }
 }
 
+embedding_response = {
+  "object": "list",
+  "data": [
+    {
+      "object": "embedding",
+      "index": 0,
+      "embedding": [0.0023064255, -0.009327292, -0.0028842222]
+    }
+  ],
+  "model": None,
+  "usage": {
+    "prompt_tokens": 8,
+    "total_tokens": 8
+  }
+}
+
 class SimpleHandler(BaseHTTPRequestHandler):
     def do_POST(self):
         content_length = int(self.headers['Content-Length'])
         post_data = self.rfile.read(content_length)
         post_body = json.loads(post_data.decode('utf-8'))
         model_value = post_body['model']
-        fixed_response["model"] = model_value
+        if self.path.endswith('/embeddings'):
+            response = embedding_response
+        else:
+            response = fixed_response
+        response["model"] = model_value
 
         self.send_response(200)
         self.send_header('Content-type', 'application/json')
         self.end_headers()
-        self.wfile.write(json.dumps(fixed_response).encode('utf-8'))
+        self.wfile.write(json.dumps(response).encode('utf-8'))
 
 server_port = 8000
 server_address = ('', server_port)
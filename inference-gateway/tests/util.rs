@@ -17,6 +17,12 @@ pub mod common {
                 .app_data(web::Data::new(startup_config.http_client.clone()))
                 .app_data(web::Data::new(startup_config.pool.clone()))
                 .app_data(web::Data::new(startup_config.auth_cache.clone()))
+                .app_data(web::Data::new(startup_config.rate_limiter.clone()))
+                .app_data(web::Data::new(startup_config.api_keys.clone()))
+                .app_data(web::Data::new(startup_config.response_cache.clone()))
+                .app_data(web::Data::new(startup_config.audit_log.clone()))
+                .app_data(web::Data::new(startup_config.router.clone()))
+                .app_data(web::Data::new(startup_config.registry.clone()))
                 .configure(gateway::server::webserver_routes),
         )
         .await
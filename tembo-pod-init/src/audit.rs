@@ -0,0 +1,183 @@
+use crate::config::Config;
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    // Allowed without changes
+    Allowed,
+    // Allowed with a patch
+    Mutated,
+    Denied,
+}
+
+// A single admission decision. In dry-run namespaces the patch and deny
+// reason are what would have happened, only the baseline Pod mutation was
+// applied.
+#[derive(Clone, Debug, Serialize)]
+pub struct AdmissionRecord {
+    pub time: String,
+    pub namespace: String,
+    pub name: String,
+    pub resource: String,
+    pub operation: String,
+    pub decision: Decision,
+    pub dry_run: bool,
+    pub patch: Option<serde_json::Value>,
+    pub reason: Option<String>,
+}
+
+impl AdmissionRecord {
+    pub fn new(namespace: &str, name: &str, resource: &str, operation: &str) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            resource: resource.to_string(),
+            operation: operation.to_string(),
+            decision: Decision::Allowed,
+            dry_run: false,
+            patch: None,
+            reason: None,
+        }
+    }
+}
+
+// The most recent admission decisions, the oldest are dropped once the ring
+// is full
+#[derive(Debug)]
+pub struct AdmissionAudit {
+    capacity: usize,
+    records: Mutex<VecDeque<AdmissionRecord>>,
+}
+
+impl AdmissionAudit {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, record: AdmissionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    // Newest first
+    pub fn recent(&self, namespace: Option<&str>, limit: usize) -> Vec<AdmissionRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| namespace.is_none_or(|ns| record.namespace == ns))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdmissionsQuery {
+    pub namespace: Option<String>,
+    pub limit: Option<usize>,
+}
+
+// Returns true if the Authorization header is `Bearer <token>`. Compares
+// every byte so the time taken doesn't leak how much of the token matched.
+fn is_authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(bearer) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    bearer.len() == token.len()
+        && bearer
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Records hold full patches, so they're only served with the admissions
+// token, and not at all when no token is configured
+#[get("/admissions")]
+pub async fn admissions(
+    req: HttpRequest,
+    query: web::Query<AdmissionsQuery>,
+    config: web::Data<Config>,
+    audit: web::Data<AdmissionAudit>,
+) -> impl Responder {
+    let Some(token) = &config.admissions_token else {
+        return HttpResponse::NotFound().finish();
+    };
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !is_authorized(authorization, token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(audit.recent(
+        query.namespace.as_deref(),
+        query.limit.unwrap_or(DEFAULT_LIMIT),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(namespace: &str, name: &str) -> AdmissionRecord {
+        AdmissionRecord::new(namespace, name, "Pod", "Create")
+    }
+
+    #[test]
+    fn test_ring_drops_oldest() {
+        let audit = AdmissionAudit::new(2);
+        audit.record(record("org-1", "pod-1"));
+        audit.record(record("org-1", "pod-2"));
+        audit.record(record("org-2", "pod-3"));
+
+        let names: Vec<String> = audit.recent(None, 10).into_iter().map(|r| r.name).collect();
+        assert_eq!(names, vec!["pod-3", "pod-2"]);
+    }
+
+    #[test]
+    fn test_recent_filters() {
+        let audit = AdmissionAudit::new(10);
+        audit.record(record("org-1", "pod-1"));
+        audit.record(record("org-2", "pod-2"));
+        audit.record(record("org-1", "pod-3"));
+
+        let org_1 = audit.recent(Some("org-1"), 10);
+        assert_eq!(org_1.len(), 2);
+        assert_eq!(org_1[0].name, "pod-3");
+        assert_eq!(audit.recent(None, 1).len(), 1);
+
+        // a zero capacity ring records nothing
+        let disabled = AdmissionAudit::new(0);
+        disabled.record(record("org-1", "pod-1"));
+        assert!(disabled.recent(None, 10).is_empty());
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized(Some("Bearer s3cret"), "s3cret"));
+        assert!(!is_authorized(Some("Bearer s3cre"), "s3cret"));
+        assert!(!is_authorized(Some("Bearer s3creT"), "s3cret"));
+        assert!(!is_authorized(Some("s3cret"), "s3cret"));
+        assert!(!is_authorized(None, "s3cret"));
+    }
+}
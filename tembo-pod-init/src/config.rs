@@ -11,6 +11,17 @@ pub struct Config {
     pub tls_cert: String,
     pub tls_key: String,
     pub opentelemetry_endpoint_url: Option<String>,
+    pub mutation_policy_configmap: String,
+    pub mutation_policy_namespace: String,
+    pub coredb_max_replicas: i32,
+    pub image_prepull_enabled: bool,
+    pub image_prepull_namespace: String,
+    pub image_prepull_interval_sec: u64,
+    pub image_prepull_repull_sec: u64,
+    pub image_prepull_extra_images: Vec<String>,
+    pub dry_run_label: String,
+    pub admission_audit_capacity: usize,
+    pub admissions_token: Option<String>,
 }
 
 impl Config {
@@ -26,11 +37,13 @@ impl Config {
     // version and OS version to keep Pods up-to-date, but we don't want to
     // pull an image unnecessarily if it doesn't contain the Postgres and OS
     // versions. Because we don't want to change major or OS versions, configs
-    // should always use the `postgres:$pg_major-$os_version` tag.
+    // should always use the `postgres:$pg_major-$os_version` tag. When the
+    // image pre-puller is enabled it re-pulls floating tags on every node, so
+    // Pods don't have to wait for a pull on a cold node.
     pub fn image_pull_policy(&self) -> Option<String> {
         let re = regex::Regex::new(r"(?:^|/)postgres:\d+-[a-z]+$").unwrap();
         Some(
-            if re.is_match(&self.container_image) {
+            if re.is_match(&self.container_image) && !self.image_prepull_enabled {
                 "Always"
             } else {
                 "IfNotPresent"
@@ -75,6 +88,43 @@ impl Default for Config {
                     Some(url)
                 }
             },
+            mutation_policy_configmap: from_env_or_default(
+                "MUTATION_POLICY_CONFIGMAP",
+                "tembo-pod-init-policy",
+            ),
+            mutation_policy_namespace: from_env_or_default("MUTATION_POLICY_NAMESPACE", "default"),
+            coredb_max_replicas: from_env_or_default("COREDB_MAX_REPLICAS", "5")
+                .parse()
+                .unwrap(),
+            image_prepull_enabled: from_env_or_default("IMAGE_PREPULL_ENABLED", "false")
+                .parse()
+                .unwrap(),
+            image_prepull_namespace: from_env_or_default("IMAGE_PREPULL_NAMESPACE", "default"),
+            image_prepull_interval_sec: from_env_or_default("IMAGE_PREPULL_INTERVAL_SEC", "300")
+                .parse()
+                .unwrap(),
+            image_prepull_repull_sec: from_env_or_default("IMAGE_PREPULL_REPULL_SEC", "21600")
+                .parse()
+                .unwrap(),
+            image_prepull_extra_images: std::env::var("IMAGE_PREPULL_EXTRA_IMAGES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|image| image.trim().to_string())
+                .filter(|image| !image.is_empty())
+                .collect(),
+            dry_run_label: from_env_or_default("DRY_RUN_LABEL", "tembo-pod-init.tembo.io/dry-run"),
+            admission_audit_capacity: from_env_or_default("ADMISSION_AUDIT_CAPACITY", "1000")
+                .parse()
+                .unwrap(),
+            // /admissions is disabled unless a token is set
+            admissions_token: {
+                let token = std::env::var("ADMISSIONS_TOKEN").unwrap_or_default();
+                if token.is_empty() {
+                    None
+                } else {
+                    Some(token)
+                }
+            },
         }
     }
 }
@@ -174,12 +224,34 @@ mod tests {
                 tls_cert: "".to_string(),
                 tls_key: "".to_string(),
                 opentelemetry_endpoint_url: None,
+                mutation_policy_configmap: "".to_string(),
+                mutation_policy_namespace: "".to_string(),
+                coredb_max_replicas: 5,
+                image_prepull_enabled: false,
+                image_prepull_namespace: "".to_string(),
+                image_prepull_interval_sec: 300,
+                image_prepull_repull_sec: 21600,
+                image_prepull_extra_images: vec![],
+                dry_run_label: "".to_string(),
+                admission_audit_capacity: 1000,
+                admissions_token: None,
             };
             assert_eq!(uses, config.uses_postgres_image(), "{name}");
             assert_eq!(
                 Some(if always { "Always" } else { "IfNotPresent" }.to_string()),
                 config.image_pull_policy(),
             );
+
+            // nodes already have the image when it's pre-pulled
+            let prepulled = Config {
+                image_prepull_enabled: true,
+                ..config
+            };
+            assert_eq!(
+                Some("IfNotPresent".to_string()),
+                prepulled.image_pull_policy(),
+                "{name}"
+            );
         }
     }
 }
@@ -1,26 +1,40 @@
 use controller::cloudnativepg::clusters::Cluster;
 use k8s_openapi::api::core::v1::{Capabilities, Container, SecurityContext, VolumeMount};
+use kube::runtime::reflector::{ObjectRef, Store};
 use kube::{Api, Client};
+use std::sync::Arc;
 use tracing::*;
 
 use crate::config::Config;
 
 // Create a Container object that will be injected into the Pod
-#[instrument(skip(client))]
+#[instrument(skip(client, clusters))]
 pub async fn create_init_container(
     config: &Config,
     client: &Client,
+    clusters: &Store<Cluster>,
     namespace: &str,
     cluster_name: &str,
 ) -> Container {
-    // Get the correct container image to use from Cluster
-    let cluster_api: Api<Cluster> = Api::namespaced(client.clone(), namespace);
-    let cluster = cluster_api.get(cluster_name).await.unwrap();
+    // Get the correct container image to use from Cluster, the cache can miss
+    // a Cluster which was created moments before its first Pod
+    let cluster = match clusters.get(&ObjectRef::new(cluster_name).within(namespace)) {
+        Some(cluster) => cluster,
+        None => {
+            debug!(
+                "Cluster {}/{} not cached, fetching",
+                namespace, cluster_name
+            );
+            let cluster_api: Api<Cluster> = Api::namespaced(client.clone(), namespace);
+            Arc::new(cluster_api.get(cluster_name).await.unwrap())
+        }
+    };
 
     // Extract the image we need to use
     let image = cluster
         .spec
         .image_name
+        .clone()
         .unwrap_or_else(|| config.container_image.clone());
 
     // Add in mounted volumes
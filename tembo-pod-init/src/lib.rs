@@ -1,7 +1,11 @@
+pub mod audit;
 pub mod config;
 pub mod container;
 pub mod health;
 pub mod metrics;
 pub mod mutate;
+pub mod policy;
+pub mod prepull;
 pub mod telemetry;
+pub mod validate;
 pub mod watcher;
@@ -5,7 +5,16 @@ use opentelemetry::global;
 use parking_lot::Mutex;
 use std::sync::Arc;
 use tembo_pod_init::{
-    config::Config, health::*, metrics, mutate::mutate, telemetry, watcher::NamespaceWatcher,
+    audit::{admissions, AdmissionAudit},
+    config::Config,
+    health::*,
+    metrics,
+    mutate::mutate,
+    policy::PolicyWatcher,
+    prepull::ImagePrePuller,
+    telemetry,
+    validate::validate,
+    watcher::{cluster_store, NamespaceWatcher},
 };
 use tracing::*;
 use tracing_actix_web::{DefaultRootSpanBuilder, TracingLogger};
@@ -35,18 +44,33 @@ async fn main() -> std::io::Result<()> {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Kubernetes client: {}", e);
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to create Kubernetes client: {}", e),
-            ));
+            return Err(std::io::Error::other(format!(
+                "Failed to create Kubernetes client: {}",
+                e
+            )));
         }
     };
 
     // Start watching namespaces in a seperate tokio task thread
     let watcher = NamespaceWatcher::new(Arc::new(kube_client.clone()), config.clone());
     let namespaces = watcher.get_namespaces();
+    let dry_run_namespaces = watcher.get_dry_run_namespaces();
     tokio::spawn(watch_namespaces(watcher));
 
+    // Reload the mutation policy whenever its ConfigMap changes
+    let policy_watcher = PolicyWatcher::new(Arc::new(kube_client.clone()), config.clone());
+    let policy = policy_watcher.get_policy();
+    tokio::spawn(watch_policy(policy_watcher));
+
+    // Cache Clusters instead of fetching them for every Pod
+    let (clusters, cluster_watcher) = cluster_store(kube_client.clone());
+    tokio::spawn(cluster_watcher);
+
+    if config.image_prepull_enabled {
+        let pre_puller = ImagePrePuller::new(kube_client.clone(), clusters.clone(), config.clone());
+        tokio::spawn(async move { pre_puller.run().await });
+    }
+
     // Load the TLS certificate and key
     let tls_config = match setup_tls_config(&config) {
         Ok(config) => config,
@@ -61,6 +85,10 @@ async fn main() -> std::io::Result<()> {
         let config_data = web::Data::new(config.clone());
         let kube_data = web::Data::new(Arc::new(kube_client.clone()));
         let namespace_watcher_data = web::Data::new(namespaces.clone());
+        let policy_data = web::Data::new(policy.clone());
+        let cluster_data = web::Data::new(clusters.clone());
+        let dry_run_data = web::Data::new(dry_run_namespaces.clone());
+        let audit_data = web::Data::new(AdmissionAudit::new(config.admission_audit_capacity));
         let stop_handle = stop_handle.clone();
         let trace_id_data = web::Data::new(trace_id.clone());
         move || {
@@ -69,6 +97,10 @@ async fn main() -> std::io::Result<()> {
                     .app_data(config_data.clone())
                     .app_data(kube_data.clone())
                     .app_data(namespace_watcher_data.clone())
+                    .app_data(policy_data.clone())
+                    .app_data(cluster_data.clone())
+                    .app_data(dry_run_data.clone())
+                    .app_data(audit_data.clone())
                     .app_data(stop_handle.clone())
                     .app_data(trace_id_data.clone())
                     .wrap(TracingLogger::<DefaultRootSpanBuilder>::new())
@@ -81,6 +113,8 @@ async fn main() -> std::io::Result<()> {
                     .service(liveness)
                     .service(readiness)
                     .service(mutate)
+                    .service(validate)
+                    .service(admissions)
                     .service(metrics::metrics)
             }
         }
@@ -147,17 +181,48 @@ async fn watch_namespaces(watcher: NamespaceWatcher) {
     }
 }
 
+#[instrument(skip(watcher))]
+async fn watch_policy(watcher: PolicyWatcher) {
+    const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+    const MAX_CONSECUTIVE_ERRORS: usize = 10;
+
+    let mut consecutive_errors = 0;
+
+    loop {
+        match watcher.watch().await {
+            Ok(_) => {
+                info!("Mutation policy watcher finished, restarting.");
+                consecutive_errors = 0;
+            }
+            Err(e) => {
+                consecutive_errors += 1;
+                error!(
+                    "Mutation policy watcher failed (attempt {}/{}), restarting: {}",
+                    consecutive_errors, MAX_CONSECUTIVE_ERRORS, e
+                );
+
+                if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                    error!("Too many consecutive errors in mutation policy watcher, giving up");
+                    break;
+                }
+
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
 fn setup_tls_config(config: &Config) -> Result<SslAcceptorBuilder, std::io::Error> {
-    let mut tls_config = SslAcceptor::mozilla_intermediate(SslMethod::tls())
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut tls_config =
+        SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(std::io::Error::other)?;
 
     tls_config
         .set_private_key_file(&config.tls_key, SslFiletype::PEM)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
 
     tls_config
         .set_certificate_chain_file(&config.tls_cert)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
 
     Ok(tls_config)
 }
@@ -1,28 +1,41 @@
 use crate::metrics;
 use actix_web::{post, web, HttpResponse, Responder};
+use controller::cloudnativepg::clusters::Cluster;
 use json_patch::{diff, Patch};
 use k8s_openapi::api::core::v1::{Pod, VolumeMount};
 use kube::core::{
     admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
     TypeMeta,
 };
+use kube::runtime::reflector::Store;
 use kube::Client;
 use serde_json::json;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::RwLock;
 use tracing::*;
 
-use crate::{config::Config, container::*};
+use crate::{
+    audit::{AdmissionAudit, AdmissionRecord, Decision},
+    config::Config,
+    container::*,
+    policy::{namespace_labels, MutationPolicy},
+    watcher::DryRunNamespaces,
+};
 
-#[instrument(skip(client, body), fields(trace_id))]
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client, clusters, body, policy, audit), fields(trace_id))]
 #[post("/mutate")]
 async fn mutate(
     body: web::Json<AdmissionReview<Pod>>,
     config: web::Data<Config>,
     namespaces: web::Data<Arc<RwLock<HashSet<String>>>>,
+    policy: web::Data<Arc<RwLock<MutationPolicy>>>,
     client: web::Data<Arc<Client>>,
+    clusters: web::Data<Store<Cluster>>,
+    dry_run_namespaces: web::Data<DryRunNamespaces>,
+    audit: web::Data<AdmissionAudit>,
     trace_id: web::Data<String>,
 ) -> impl Responder {
     let start_time = Instant::now();
@@ -142,6 +155,18 @@ async fn mutate(
         };
     }
 
+    // In dry-run namespaces the Pod still gets the baseline mutation, only the
+    // policy rules are recorded without being applied
+    let dry_run = dry_run_namespaces.contains(&namespace).await;
+    let pod_name = pod
+        .metadata
+        .name
+        .clone()
+        .or_else(|| pod.metadata.generate_name.clone())
+        .unwrap_or_default();
+    let mut record = AdmissionRecord::new(&namespace, &pod_name, resource, &operation_str);
+    record.dry_run = dry_run;
+
     // Check if the pod has all required volumes
     let required_volumes = vec!["pgdata", "scratch-data"];
     if !has_required_volumes(pod, &required_volumes) {
@@ -152,6 +177,9 @@ async fn mutate(
         // set message to say that the pod does not have all required volumes
         let message = "Pod spec does not contain all required volumes, will not mutate";
         metrics::increment_error_counter(&namespace, "required_volumes_missing");
+        record.decision = Decision::Denied;
+        record.reason = Some(message.to_string());
+        audit.record(record);
         return HttpResponse::Ok().json(AdmissionReview {
             response: Some(mk_deny_response(&admission_request, message)),
             request: Some(admission_request),
@@ -181,8 +209,14 @@ async fn mutate(
                 config.init_container_name.to_string()
             );
         } else {
-            let init_container =
-                create_init_container(&config, &client, &namespace, &cluster_name.unwrap()).await;
+            let init_container = create_init_container(
+                &config,
+                &client,
+                &clusters,
+                &namespace,
+                &cluster_name.unwrap(),
+            )
+            .await;
             let init_containers = spec.init_containers.take().unwrap_or_default();
             let mut new_init_containers = vec![init_container];
             new_init_containers.extend(init_containers);
@@ -220,26 +254,61 @@ async fn mutate(
         }
     }
 
-    // Calculate patch and add it to the AdmissionResponse
+    // Apply the rules of the mutation policy which match the Pod, in dry-run
+    // namespaces the Pod is only patched up to the baseline mutation
+    let baseline_pod = new_pod.clone();
+    {
+        let policy = policy.read().await;
+        if !policy.rules.is_empty() {
+            let namespace_labels = if policy.needs_namespace_labels() {
+                namespace_labels(&client, &namespace).await
+            } else {
+                BTreeMap::new()
+            };
+            for rule in policy.apply(&mut new_pod, &namespace_labels) {
+                if !dry_run {
+                    metrics::increment_mutation_counter(
+                        &namespace,
+                        resource,
+                        &format!("policy_{}", rule),
+                    );
+                }
+            }
+        }
+    }
+
+    // Calculate patch and add it to the AdmissionResponse, the audit record
+    // always holds the full patch
     let patch = generate_pod_patch(pod, &new_pod);
+    if let Some(patch) = &patch {
+        record.decision = Decision::Mutated;
+        record.patch = serde_json::to_value(patch).ok();
+    }
+    audit.record(record);
+    let patch = if dry_run {
+        info!(
+            "Dry run, not applying policy rules to Pod {} in {}",
+            pod_name, namespace
+        );
+        generate_pod_patch(pod, &baseline_pod)
+    } else {
+        patch
+    };
 
     // Construct and return the AdmissionReview containing the AdmissionResponse.
-    let admission_response = match patch {
-        Some(patch) => mk_allow_response(&admission_request, Some(patch)),
-        None => mk_allow_response(&admission_request, None),
-    };
+    let admission_response = mk_allow_response(&admission_request, patch);
     debug!("AdmissionResponse: {:?}", admission_response);
 
     // Update the request duration metric
     let duration = start_time.elapsed().as_secs_f64();
     metrics::observe_request_duration(&namespace, &operation_str, resource, duration);
 
-    metrics::increment_request_counter(
-        &namespace,
-        &operation_str,
-        resource,
-        "allowed_with_mutation",
-    );
+    let result = if dry_run {
+        "dry_run"
+    } else {
+        "allowed_with_mutation"
+    };
+    metrics::increment_request_counter(&namespace, &operation_str, resource, result);
     metrics::increment_mutation_counter(&namespace, resource, "init_container_added");
     HttpResponse::Ok().json(AdmissionReview {
         response: Some(admission_response),
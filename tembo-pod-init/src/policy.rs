@@ -0,0 +1,455 @@
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, Container, EnvVar, Namespace, Pod, ResourceRequirements, Toleration, Volume,
+    VolumeMount,
+};
+use kube::api::{Api, WatchEvent, WatchParams};
+use kube::Client;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::*;
+
+use crate::{config::Config, container::add_volume_mounts, metrics};
+
+// The ConfigMap key holding the policy
+pub const POLICY_KEY: &str = "policy.yaml";
+
+// CNPG labels Pods with their role, the older label is kept for clusters
+// created before `cnpg.io/instanceRole` was introduced
+const ROLE_LABELS: &[&str] = &["cnpg.io/instanceRole", "role"];
+const CLUSTER_LABEL: &str = "cnpg.io/cluster";
+
+// Containers env vars and volume mounts are added to when a rule doesn't
+// list any
+const DEFAULT_TARGET_CONTAINER: &str = "postgres";
+
+// Mutations applied to matching Pods after the initContainer is injected
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MutationPolicy {
+    pub rules: Vec<MutationRule>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MutationRule {
+    pub name: String,
+    pub selector: PodSelector,
+    // Containers to add, skipped when the Pod has a container with the same name
+    pub sidecars: Vec<Container>,
+    // Volumes to add, skipped when the Pod has a volume with the same name
+    pub volumes: Vec<Volume>,
+    // Added to the target containers
+    pub volume_mounts: Vec<VolumeMount>,
+    // Set on the target containers, replacing env vars with the same name
+    pub env: Vec<EnvVar>,
+    pub tolerations: Vec<Toleration>,
+    pub node_selector: BTreeMap<String, String>,
+    // Resource overrides keyed by container name, limits and requests are
+    // merged into the container's existing ones
+    pub resources: BTreeMap<String, ResourceRequirements>,
+    // Names of the containers env vars and volume mounts are added to,
+    // defaults to the postgres container
+    pub containers: Option<Vec<String>>,
+}
+
+// Every field which is set has to match, an empty selector matches all Pods
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PodSelector {
+    pub namespace_labels: BTreeMap<String, String>,
+    // CNPG cluster names, from the `cnpg.io/cluster` label
+    pub clusters: Vec<String>,
+    // Instance roles, e.g. primary or replica
+    pub roles: Vec<String>,
+}
+
+impl MutationPolicy {
+    // Parse the policy from a ConfigMap, a ConfigMap without the policy key
+    // is an empty policy
+    pub fn from_config_map(config_map: &ConfigMap) -> Result<Self, serde_yaml::Error> {
+        match config_map
+            .data
+            .as_ref()
+            .and_then(|data| data.get(POLICY_KEY))
+        {
+            Some(policy) => serde_yaml::from_str(policy),
+            None => Ok(Self::default()),
+        }
+    }
+
+    // Only look up the namespace when a rule selects on its labels
+    pub fn needs_namespace_labels(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| !rule.selector.namespace_labels.is_empty())
+    }
+
+    // Apply every matching rule in order, returning the names of the rules
+    // which were applied
+    pub fn apply(&self, pod: &mut Pod, namespace_labels: &BTreeMap<String, String>) -> Vec<String> {
+        let mut applied = Vec::new();
+        for rule in &self.rules {
+            if rule.selector.matches(pod, namespace_labels) {
+                debug!("Applying mutation policy rule: {}", rule.name);
+                rule.apply(pod);
+                applied.push(rule.name.clone());
+            }
+        }
+        applied
+    }
+}
+
+impl PodSelector {
+    pub fn matches(&self, pod: &Pod, namespace_labels: &BTreeMap<String, String>) -> bool {
+        let empty = BTreeMap::new();
+        let labels = pod.metadata.labels.as_ref().unwrap_or(&empty);
+
+        let namespace_matches = self
+            .namespace_labels
+            .iter()
+            .all(|(key, value)| namespace_labels.get(key) == Some(value));
+        let cluster_matches = self.clusters.is_empty()
+            || labels
+                .get(CLUSTER_LABEL)
+                .is_some_and(|cluster| self.clusters.contains(cluster));
+        let role_matches = self.roles.is_empty()
+            || ROLE_LABELS
+                .iter()
+                .find_map(|label| labels.get(*label))
+                .is_some_and(|role| self.roles.contains(role));
+
+        namespace_matches && cluster_matches && role_matches
+    }
+}
+
+impl MutationRule {
+    pub fn apply(&self, pod: &mut Pod) {
+        let Some(spec) = pod.spec.as_mut() else {
+            warn!("Pod spec is missing, cannot apply rule: {}", self.name);
+            return;
+        };
+
+        for sidecar in &self.sidecars {
+            if spec.containers.iter().any(|c| c.name == sidecar.name) {
+                debug!("Pod already has container {}, skipping", sidecar.name);
+            } else {
+                spec.containers.push(sidecar.clone());
+            }
+        }
+
+        if !self.volumes.is_empty() {
+            let volumes = spec.volumes.get_or_insert_with(Vec::new);
+            for volume in &self.volumes {
+                if !volumes.iter().any(|v| v.name == volume.name) {
+                    volumes.push(volume.clone());
+                }
+            }
+        }
+
+        let targets = self
+            .containers
+            .clone()
+            .unwrap_or_else(|| vec![DEFAULT_TARGET_CONTAINER.to_string()]);
+        for container in spec
+            .containers
+            .iter_mut()
+            .filter(|c| targets.contains(&c.name))
+        {
+            for volume_mount in &self.volume_mounts {
+                add_volume_mounts(container, volume_mount.clone());
+            }
+            if !self.env.is_empty() {
+                let env = container.env.get_or_insert_with(Vec::new);
+                for var in &self.env {
+                    match env.iter_mut().find(|e| e.name == var.name) {
+                        Some(existing) => *existing = var.clone(),
+                        None => env.push(var.clone()),
+                    }
+                }
+            }
+        }
+
+        for (name, resources) in &self.resources {
+            match spec
+                .containers
+                .iter_mut()
+                .chain(spec.init_containers.iter_mut().flatten())
+                .find(|c| &c.name == name)
+            {
+                Some(container) => merge_resources(container, resources),
+                None => debug!("Container {} not found, skipping resources", name),
+            }
+        }
+
+        if !self.tolerations.is_empty() {
+            let tolerations = spec.tolerations.get_or_insert_with(Vec::new);
+            for toleration in &self.tolerations {
+                if !tolerations.contains(toleration) {
+                    tolerations.push(toleration.clone());
+                }
+            }
+        }
+
+        if !self.node_selector.is_empty() {
+            spec.node_selector
+                .get_or_insert_with(BTreeMap::new)
+                .extend(self.node_selector.clone());
+        }
+    }
+}
+
+fn merge_resources(container: &mut Container, overrides: &ResourceRequirements) {
+    let resources = container.resources.get_or_insert_with(Default::default);
+    if let Some(limits) = &overrides.limits {
+        resources
+            .limits
+            .get_or_insert_with(BTreeMap::new)
+            .extend(limits.clone());
+    }
+    if let Some(requests) = &overrides.requests {
+        resources
+            .requests
+            .get_or_insert_with(BTreeMap::new)
+            .extend(requests.clone());
+    }
+    if overrides.claims.is_some() {
+        resources.claims.clone_from(&overrides.claims);
+    }
+}
+
+// Labels of the Pod's namespace, empty when the namespace can't be read so
+// rules selecting on namespace labels don't match
+pub async fn namespace_labels(client: &Client, namespace: &str) -> BTreeMap<String, String> {
+    let api: Api<Namespace> = Api::all(client.clone());
+    match api.get(namespace).await {
+        Ok(ns) => ns.metadata.labels.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to get namespace {}: {}", namespace, e);
+            BTreeMap::new()
+        }
+    }
+}
+
+pub struct PolicyWatcher {
+    policy: Arc<RwLock<MutationPolicy>>,
+    client: Arc<Client>,
+    config: Config,
+}
+
+impl PolicyWatcher {
+    pub fn new(client: Arc<Client>, config: Config) -> Self {
+        Self {
+            policy: Arc::new(RwLock::new(MutationPolicy::default())),
+            client,
+            config,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn watch(&self) -> Result<(), kube::Error> {
+        let name = &self.config.mutation_policy_configmap;
+        let api: Api<ConfigMap> = Api::namespaced(
+            (*self.client).clone(),
+            &self.config.mutation_policy_namespace,
+        );
+
+        // Load the current policy, no ConfigMap means no policy
+        match api.get_opt(name).await? {
+            Some(config_map) => self.load(&config_map).await,
+            None => {
+                info!("Mutation policy ConfigMap {} not found", name);
+                *self.policy.write().await = MutationPolicy::default();
+            }
+        }
+
+        let wp = WatchParams::default().fields(&format!("metadata.name={}", name));
+        let mut stream = api.watch(&wp, "0").await?.boxed();
+
+        while let Some(status) = stream.try_next().await? {
+            debug!("Got event: {:?}", status);
+            match status {
+                WatchEvent::Added(config_map) | WatchEvent::Modified(config_map) => {
+                    self.load(&config_map).await;
+                }
+                WatchEvent::Deleted(_) => {
+                    info!("Mutation policy ConfigMap {} deleted", name);
+                    *self.policy.write().await = MutationPolicy::default();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // An invalid policy is logged and the previous policy is kept
+    async fn load(&self, config_map: &ConfigMap) {
+        match MutationPolicy::from_config_map(config_map) {
+            Ok(policy) => {
+                info!("Loaded mutation policy with {} rules", policy.rules.len());
+                *self.policy.write().await = policy;
+            }
+            Err(e) => {
+                error!("Invalid mutation policy, keeping the previous one: {}", e);
+                metrics::increment_error_counter(
+                    &self.config.mutation_policy_namespace,
+                    "invalid_mutation_policy",
+                );
+            }
+        }
+    }
+
+    pub fn get_policy(&self) -> Arc<RwLock<MutationPolicy>> {
+        self.policy.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::PodSpec;
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+    const POLICY: &str = r#"
+rules:
+  - name: replicas-on-spot
+    selector:
+      namespaceLabels:
+        tembo.io/tier: free
+      roles: ["replica"]
+    tolerations:
+      - key: spot
+        operator: Exists
+        effect: NoSchedule
+    nodeSelector:
+      tembo.io/pool: spot
+  - name: log-shipper
+    selector:
+      clusters: ["org-one"]
+    sidecars:
+      - name: vector
+        image: timberio/vector:0.38.0
+        volumeMounts:
+          - name: vector-config
+            mountPath: /etc/vector
+    volumes:
+      - name: vector-config
+        configMap:
+          name: vector
+    env:
+      - name: LOG_FORMAT
+        value: json
+    resources:
+      postgres:
+        limits:
+          memory: 2Gi
+"#;
+
+    fn policy() -> MutationPolicy {
+        let config_map = ConfigMap {
+            data: Some(BTreeMap::from([(
+                POLICY_KEY.to_string(),
+                POLICY.to_string(),
+            )])),
+            ..Default::default()
+        };
+        MutationPolicy::from_config_map(&config_map).unwrap()
+    }
+
+    fn pod(cluster: &str, role: &str) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                labels: Some(BTreeMap::from([
+                    (CLUSTER_LABEL.to_string(), cluster.to_string()),
+                    ("cnpg.io/instanceRole".to_string(), role.to_string()),
+                ])),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                containers: vec![Container {
+                    name: "postgres".to_string(),
+                    env: Some(vec![EnvVar {
+                        name: "LOG_FORMAT".to_string(),
+                        value: Some("text".to_string()),
+                        ..Default::default()
+                    }]),
+                    resources: Some(ResourceRequirements {
+                        limits: Some(BTreeMap::from([
+                            ("cpu".to_string(), Quantity("1".to_string())),
+                            ("memory".to_string(), Quantity("1Gi".to_string())),
+                        ])),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_config_map() {
+        let policy = policy();
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(policy.rules[1].sidecars[0].name, "vector");
+        assert!(policy.needs_namespace_labels());
+
+        let empty = MutationPolicy::from_config_map(&ConfigMap::default()).unwrap();
+        assert!(empty.rules.is_empty());
+    }
+
+    #[test]
+    fn test_selector_matches() {
+        let policy = policy();
+        let free = BTreeMap::from([("tembo.io/tier".to_string(), "free".to_string())]);
+        let selector = &policy.rules[0].selector;
+
+        assert!(selector.matches(&pod("org-one", "replica"), &free));
+        assert!(!selector.matches(&pod("org-one", "primary"), &free));
+        assert!(!selector.matches(&pod("org-one", "replica"), &BTreeMap::new()));
+
+        // pods of older clusters only have the role label
+        let mut old = pod("org-two", "replica");
+        let labels = old.metadata.labels.as_mut().unwrap();
+        labels.remove("cnpg.io/instanceRole");
+        labels.insert("role".to_string(), "replica".to_string());
+        assert!(selector.matches(&old, &free));
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = policy();
+        let mut pod = pod("org-one", "primary");
+
+        let applied = policy.apply(&mut pod, &BTreeMap::new());
+        assert_eq!(applied, vec!["log-shipper".to_string()]);
+
+        let spec = pod.spec.as_ref().unwrap();
+        assert_eq!(spec.containers.len(), 2);
+        assert_eq!(spec.volumes.as_ref().unwrap()[0].name, "vector-config");
+        assert!(spec.tolerations.is_none());
+
+        let postgres = &spec.containers[0];
+        let env = postgres.env.as_ref().unwrap();
+        assert_eq!(env.len(), 1);
+        assert_eq!(env[0].value.as_deref(), Some("json"));
+        let limits = postgres
+            .resources
+            .as_ref()
+            .unwrap()
+            .limits
+            .as_ref()
+            .unwrap();
+        assert_eq!(limits["memory"], Quantity("2Gi".to_string()));
+        assert_eq!(limits["cpu"], Quantity("1".to_string()));
+
+        // applying the policy again doesn't change the pod
+        let before = pod.clone();
+        policy.apply(&mut pod, &BTreeMap::new());
+        assert_eq!(pod, before);
+    }
+}
@@ -0,0 +1,275 @@
+use controller::cloudnativepg::clusters::Cluster;
+use controller::defaults::{default_images, default_repository};
+use k8s_openapi::api::apps::v1::{DaemonSet, DaemonSetSpec};
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, PodSpec, PodTemplateSpec, ResourceRequirements, SecurityContext,
+    Toleration,
+};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use kube::api::{Api, Patch, PatchParams};
+use kube::runtime::reflector::Store;
+use kube::Client;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::*;
+
+use crate::config::Config;
+
+pub const PREPULL_NAME: &str = "tembo-image-prepull";
+const PAUSE_IMAGE: &str = "registry.k8s.io/pause:3.10";
+// Changing these annotations rolls the DaemonSet, which pulls the images again
+const IMAGES_ANNOTATION: &str = "tembo.io/prepull-images";
+const PULL_WINDOW_ANNOTATION: &str = "tembo.io/prepull-window";
+
+// Keeps the Postgres images used by Clusters, and the stack defaults, pulled
+// on every node with a DaemonSet. Each image is an initContainer which exits
+// straight away, so pulling it is all the DaemonSet does.
+pub struct ImagePrePuller {
+    client: Client,
+    clusters: Store<Cluster>,
+    config: Config,
+}
+
+impl ImagePrePuller {
+    pub fn new(client: Client, clusters: Store<Cluster>, config: Config) -> Self {
+        Self {
+            client,
+            clusters,
+            config,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn run(&self) {
+        if let Err(e) = self.clusters.wait_until_ready().await {
+            error!(
+                "Cluster cache is unavailable, not pre-pulling images: {}",
+                e
+            );
+            return;
+        }
+
+        let interval = Duration::from_secs(self.config.image_prepull_interval_sec);
+        loop {
+            if let Err(e) = self.reconcile().await {
+                error!("Failed to reconcile image pre-pull DaemonSet: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    pub async fn reconcile(&self) -> Result<(), kube::Error> {
+        let images = referenced_images(&self.clusters.state(), &self.config);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let pull_window = now / self.config.image_prepull_repull_sec.max(1);
+        let daemonset = prepull_daemonset(&images, &self.config, pull_window);
+
+        let api: Api<DaemonSet> =
+            Api::namespaced(self.client.clone(), &self.config.image_prepull_namespace);
+        api.patch(
+            PREPULL_NAME,
+            &PatchParams::apply("tembo-pod-init").force(),
+            &Patch::Apply(&daemonset),
+        )
+        .await?;
+        debug!("Pre-pulling {} images: {:?}", images.len(), images);
+        Ok(())
+    }
+}
+
+// Images of every Cluster, the default stack images and any extra images
+pub fn referenced_images(clusters: &[Arc<Cluster>], config: &Config) -> BTreeSet<String> {
+    let defaults = default_images();
+    let repository = default_repository();
+    let stack_images = [defaults.pg14, defaults.pg15, defaults.pg16, defaults.pg17]
+        .into_iter()
+        .flatten()
+        .map(|image| format!("{}/{}", repository, image));
+
+    clusters
+        .iter()
+        .filter_map(|cluster| cluster.spec.image_name.clone())
+        .chain(stack_images)
+        .chain(std::iter::once(config.container_image.clone()))
+        .chain(config.image_prepull_extra_images.iter().cloned())
+        .collect()
+}
+
+pub fn prepull_daemonset(
+    images: &BTreeSet<String>,
+    config: &Config,
+    pull_window: u64,
+) -> DaemonSet {
+    let labels = BTreeMap::from([("app".to_string(), PREPULL_NAME.to_string())]);
+    let annotations = BTreeMap::from([
+        (
+            IMAGES_ANNOTATION.to_string(),
+            images.iter().cloned().collect::<Vec<_>>().join(","),
+        ),
+        (PULL_WINDOW_ANNOTATION.to_string(), pull_window.to_string()),
+    ]);
+
+    let resources = ResourceRequirements {
+        requests: Some(BTreeMap::from([
+            ("cpu".to_string(), Quantity("1m".to_string())),
+            ("memory".to_string(), Quantity("8Mi".to_string())),
+        ])),
+        limits: Some(BTreeMap::from([
+            ("cpu".to_string(), Quantity("50m".to_string())),
+            ("memory".to_string(), Quantity("32Mi".to_string())),
+        ])),
+        ..Default::default()
+    };
+    let security_context = SecurityContext {
+        allow_privilege_escalation: Some(false),
+        capabilities: Some(Capabilities {
+            drop: Some(vec!["ALL".to_string()]),
+            ..Default::default()
+        }),
+        run_as_non_root: Some(true),
+        run_as_user: Some(65534),
+        ..Default::default()
+    };
+
+    let init_containers = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| Container {
+            name: format!("prepull-{}", i),
+            image: Some(image.clone()),
+            // Floating tags are pulled again whenever the DaemonSet rolls
+            image_pull_policy: Some("Always".to_string()),
+            command: Some(vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                "true".to_string(),
+            ]),
+            resources: Some(resources.clone()),
+            security_context: Some(security_context.clone()),
+            ..Default::default()
+        })
+        .collect();
+
+    DaemonSet {
+        metadata: ObjectMeta {
+            name: Some(PREPULL_NAME.to_string()),
+            namespace: Some(config.image_prepull_namespace.clone()),
+            labels: Some(labels.clone()),
+            ..Default::default()
+        },
+        spec: Some(DaemonSetSpec {
+            selector: LabelSelector {
+                match_labels: Some(labels.clone()),
+                ..Default::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels),
+                    annotations: Some(annotations),
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    init_containers: Some(init_containers),
+                    containers: vec![Container {
+                        name: "pause".to_string(),
+                        image: Some(PAUSE_IMAGE.to_string()),
+                        resources: Some(resources),
+                        security_context: Some(security_context),
+                        ..Default::default()
+                    }],
+                    // Postgres nodes are usually tainted, the images are
+                    // needed on all of them
+                    tolerations: Some(vec![Toleration {
+                        operator: Some("Exists".to_string()),
+                        ..Default::default()
+                    }]),
+                    automount_service_account_token: Some(false),
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controller::cloudnativepg::clusters::ClusterSpec;
+
+    fn cluster(image: Option<&str>) -> Arc<Cluster> {
+        Arc::new(Cluster::new(
+            "cluster",
+            ClusterSpec {
+                image_name: image.map(str::to_string),
+                ..Default::default()
+            },
+        ))
+    }
+
+    fn config() -> Config {
+        Config {
+            container_image: "quay.io/tembo/postgres:17-noble".to_string(),
+            image_prepull_extra_images: vec!["quay.io/tembo/postgres:16-noble".to_string()],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_referenced_images() {
+        let clusters = vec![
+            cluster(Some("quay.io/tembo/postgres:15-noble")),
+            cluster(Some("quay.io/tembo/postgres:15-noble")),
+            cluster(None),
+        ];
+        let images = referenced_images(&clusters, &config());
+
+        assert!(images.contains("quay.io/tembo/postgres:15-noble"));
+        assert!(images.contains("quay.io/tembo/postgres:16-noble"));
+        assert!(images.contains("quay.io/tembo/postgres:17-noble"));
+        assert!(images.contains("quay.io/tembo/standard-cnpg:15-bffd097"));
+        // 3 distinct images plus the 4 stack defaults
+        assert_eq!(images.len(), 7);
+    }
+
+    #[test]
+    fn test_prepull_daemonset() {
+        let images = BTreeSet::from([
+            "quay.io/tembo/postgres:15-noble".to_string(),
+            "quay.io/tembo/postgres:17-noble".to_string(),
+        ]);
+        let daemonset = prepull_daemonset(&images, &config(), 7);
+        let template = daemonset.spec.unwrap().template;
+        let spec = template.spec.unwrap();
+
+        let init_containers = spec.init_containers.unwrap();
+        assert_eq!(init_containers.len(), 2);
+        assert_eq!(
+            init_containers[1].image.as_deref(),
+            Some("quay.io/tembo/postgres:17-noble")
+        );
+        assert_eq!(spec.containers[0].image.as_deref(), Some(PAUSE_IMAGE));
+
+        // a new pull window rolls the DaemonSet
+        let annotations = template.metadata.unwrap().annotations.unwrap();
+        assert_eq!(annotations[PULL_WINDOW_ANNOTATION], "7");
+        let rolled = prepull_daemonset(&images, &config(), 8);
+        assert_ne!(
+            rolled
+                .spec
+                .unwrap()
+                .template
+                .metadata
+                .unwrap()
+                .annotations
+                .unwrap()[PULL_WINDOW_ANNOTATION],
+            "7"
+        );
+    }
+}
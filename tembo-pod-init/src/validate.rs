@@ -0,0 +1,851 @@
+use crate::metrics;
+use actix_web::{post, web, HttpResponse, Responder};
+use controller::apis::coredb_types::CoreDBSpec;
+use controller::apis::postgres_parameters::{
+    ConfigValue, PgConfig, DISALLOWED_CONFIGS, MULTI_VAL_CONFIGS,
+};
+use controller::ingress::valid_cidrs;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use kube::core::{
+    admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+    DynamicObject, TypeMeta,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashSet;
+use std::time::Instant;
+use tracing::*;
+
+use crate::{
+    audit::{AdmissionAudit, AdmissionRecord, Decision},
+    config::Config,
+    watcher::DryRunNamespaces,
+};
+
+static EXTENSION_NAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_\-]*$").unwrap());
+static EXTENSION_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9.\-+_]*$").unwrap());
+// Postgres parameter names are case-insensitive, e.g. TimeZone and DateStyle
+static PARAMETER_NAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^[a-z_][a-z0-9_]*(\.[a-z_][a-z0-9_]*)?$").unwrap());
+static MEMORY_VALUE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^-?\d+(\.\d+)?\s*(B|kB|MB|GB|TB)?$").unwrap());
+static DURATION_VALUE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^-?\d+(\.\d+)?\s*(us|ms|s|min|h|d)?$").unwrap());
+
+const BOOL_VALUES: &[&str] = &["on", "off", "true", "false", "yes", "no", "1", "0"];
+
+#[derive(Clone, Copy, Debug)]
+enum ParameterType {
+    Bool,
+    Integer,
+    Real,
+    // An integer with an optional B, kB, MB, GB or TB unit
+    Memory,
+    // An integer with an optional us, ms, s, min, h or d unit
+    Duration,
+    Enum(&'static [&'static str]),
+}
+
+// The types of commonly tuned Postgres parameters. Parameters which aren't
+// listed are only checked for typos of the listed ones, extension parameters
+// such as `pg_stat_statements.track` aren't checked.
+const PARAMETERS: &[(&str, ParameterType)] = &[
+    ("autovacuum", ParameterType::Bool),
+    ("autovacuum_analyze_scale_factor", ParameterType::Real),
+    ("autovacuum_analyze_threshold", ParameterType::Integer),
+    ("autovacuum_freeze_max_age", ParameterType::Integer),
+    ("autovacuum_max_workers", ParameterType::Integer),
+    ("autovacuum_naptime", ParameterType::Duration),
+    ("autovacuum_vacuum_cost_delay", ParameterType::Duration),
+    ("autovacuum_vacuum_cost_limit", ParameterType::Integer),
+    ("autovacuum_vacuum_insert_scale_factor", ParameterType::Real),
+    ("autovacuum_vacuum_insert_threshold", ParameterType::Integer),
+    ("autovacuum_vacuum_scale_factor", ParameterType::Real),
+    ("autovacuum_vacuum_threshold", ParameterType::Integer),
+    ("autovacuum_work_mem", ParameterType::Memory),
+    ("bgwriter_delay", ParameterType::Duration),
+    ("bgwriter_lru_maxpages", ParameterType::Integer),
+    ("bgwriter_lru_multiplier", ParameterType::Real),
+    ("checkpoint_completion_target", ParameterType::Real),
+    ("checkpoint_timeout", ParameterType::Duration),
+    ("checkpoint_warning", ParameterType::Duration),
+    ("cpu_index_tuple_cost", ParameterType::Real),
+    ("cpu_operator_cost", ParameterType::Real),
+    ("cpu_tuple_cost", ParameterType::Real),
+    ("deadlock_timeout", ParameterType::Duration),
+    ("default_statistics_target", ParameterType::Integer),
+    (
+        "default_transaction_isolation",
+        ParameterType::Enum(&[
+            "serializable",
+            "repeatable read",
+            "read committed",
+            "read uncommitted",
+        ]),
+    ),
+    ("effective_cache_size", ParameterType::Memory),
+    ("effective_io_concurrency", ParameterType::Integer),
+    ("enable_bitmapscan", ParameterType::Bool),
+    ("enable_hashjoin", ParameterType::Bool),
+    ("enable_indexscan", ParameterType::Bool),
+    ("enable_mergejoin", ParameterType::Bool),
+    ("enable_nestloop", ParameterType::Bool),
+    ("enable_partitionwise_aggregate", ParameterType::Bool),
+    ("enable_partitionwise_join", ParameterType::Bool),
+    ("enable_seqscan", ParameterType::Bool),
+    ("from_collapse_limit", ParameterType::Integer),
+    ("hash_mem_multiplier", ParameterType::Real),
+    ("huge_pages", ParameterType::Enum(&["on", "off", "try"])),
+    (
+        "idle_in_transaction_session_timeout",
+        ParameterType::Duration,
+    ),
+    ("idle_session_timeout", ParameterType::Duration),
+    ("jit", ParameterType::Bool),
+    ("jit_above_cost", ParameterType::Real),
+    ("join_collapse_limit", ParameterType::Integer),
+    ("lock_timeout", ParameterType::Duration),
+    ("log_autovacuum_min_duration", ParameterType::Duration),
+    ("log_checkpoints", ParameterType::Bool),
+    ("log_connections", ParameterType::Bool),
+    ("log_disconnections", ParameterType::Bool),
+    ("log_lock_waits", ParameterType::Bool),
+    ("log_min_duration_statement", ParameterType::Duration),
+    (
+        "log_min_messages",
+        ParameterType::Enum(&[
+            "debug5", "debug4", "debug3", "debug2", "debug1", "info", "notice", "warning", "error",
+            "log", "fatal", "panic",
+        ]),
+    ),
+    (
+        "log_statement",
+        ParameterType::Enum(&["none", "ddl", "mod", "all"]),
+    ),
+    ("log_temp_files", ParameterType::Memory),
+    ("logical_decoding_work_mem", ParameterType::Memory),
+    ("maintenance_io_concurrency", ParameterType::Integer),
+    ("maintenance_work_mem", ParameterType::Memory),
+    ("max_connections", ParameterType::Integer),
+    ("max_locks_per_transaction", ParameterType::Integer),
+    ("max_parallel_maintenance_workers", ParameterType::Integer),
+    ("max_parallel_workers", ParameterType::Integer),
+    ("max_parallel_workers_per_gather", ParameterType::Integer),
+    ("max_prepared_transactions", ParameterType::Integer),
+    ("max_replication_slots", ParameterType::Integer),
+    ("max_slot_wal_keep_size", ParameterType::Memory),
+    ("max_wal_senders", ParameterType::Integer),
+    ("max_wal_size", ParameterType::Memory),
+    ("max_worker_processes", ParameterType::Integer),
+    ("min_wal_size", ParameterType::Memory),
+    ("parallel_setup_cost", ParameterType::Real),
+    ("parallel_tuple_cost", ParameterType::Real),
+    (
+        "password_encryption",
+        ParameterType::Enum(&["md5", "scram-sha-256"]),
+    ),
+    (
+        "plan_cache_mode",
+        ParameterType::Enum(&["auto", "force_generic_plan", "force_custom_plan"]),
+    ),
+    ("random_page_cost", ParameterType::Real),
+    ("seq_page_cost", ParameterType::Real),
+    ("shared_buffers", ParameterType::Memory),
+    (
+        "ssl_min_protocol_version",
+        ParameterType::Enum(&["tlsv1", "tlsv1.1", "tlsv1.2", "tlsv1.3"]),
+    ),
+    ("statement_timeout", ParameterType::Duration),
+    ("superuser_reserved_connections", ParameterType::Integer),
+    (
+        "synchronous_commit",
+        ParameterType::Enum(&["on", "off", "local", "remote_write", "remote_apply"]),
+    ),
+    ("temp_buffers", ParameterType::Memory),
+    ("temp_file_limit", ParameterType::Memory),
+    ("track_activities", ParameterType::Bool),
+    ("track_activity_query_size", ParameterType::Memory),
+    ("track_counts", ParameterType::Bool),
+    (
+        "track_functions",
+        ParameterType::Enum(&["none", "pl", "all"]),
+    ),
+    ("track_io_timing", ParameterType::Bool),
+    ("vacuum_cost_delay", ParameterType::Duration),
+    ("vacuum_cost_limit", ParameterType::Integer),
+    ("wal_buffers", ParameterType::Memory),
+    (
+        "wal_compression",
+        ParameterType::Enum(&[
+            "on", "off", "true", "false", "yes", "no", "1", "0", "pglz", "lz4", "zstd",
+        ]),
+    ),
+    ("wal_keep_size", ParameterType::Memory),
+    ("wal_writer_delay", ParameterType::Duration),
+    ("work_mem", ParameterType::Memory),
+];
+
+#[instrument(skip(body, config, audit), fields(trace_id))]
+#[post("/validate")]
+async fn validate(
+    body: web::Json<AdmissionReview<DynamicObject>>,
+    config: web::Data<Config>,
+    dry_run_namespaces: web::Data<DryRunNamespaces>,
+    audit: web::Data<AdmissionAudit>,
+    trace_id: web::Data<String>,
+) -> impl Responder {
+    let start_time = Instant::now();
+
+    // Set trace_id for logging
+    Span::current().record("trace_id", field::display(&trace_id.as_ref()));
+
+    let admission_request: AdmissionRequest<DynamicObject> = match body.into_inner().request {
+        Some(request) => request,
+        None => {
+            metrics::increment_error_counter("unknown", "missing_request");
+            return HttpResponse::BadRequest().body("expected AdmissionRequest");
+        }
+    };
+    let namespace = admission_request
+        .namespace
+        .clone()
+        .unwrap_or_else(|| "unknown".to_string());
+    let resource = "CoreDB";
+    let operation_str = format!("{:?}", admission_request.operation);
+
+    // We only validate CoreDB resources, anything else is allowed
+    if admission_request.kind.group != "coredb.io" || admission_request.kind.kind != "CoreDB" {
+        debug!(
+            "Skipping resource with group: {}, version: {}, kind: {}",
+            admission_request.kind.group,
+            admission_request.kind.version,
+            admission_request.kind.kind
+        );
+        return mk_review(
+            AdmissionResponse::from(&admission_request),
+            admission_request,
+        );
+    }
+
+    // Deletes don't have an object to validate
+    let Some(object) = admission_request.object.as_ref() else {
+        return mk_review(
+            AdmissionResponse::from(&admission_request),
+            admission_request,
+        );
+    };
+    let errors = match coredb_spec(object) {
+        Ok(spec) => {
+            // An existing CoreDB which doesn't parse shouldn't block fixing it
+            let old_spec = admission_request
+                .old_object
+                .as_ref()
+                .and_then(|old| coredb_spec(old).ok());
+            validate_coredb(&spec, old_spec.as_ref(), &config)
+        }
+        Err(e) => vec![format!("invalid CoreDB spec: {}", e)],
+    };
+
+    // Denials in dry-run namespaces are recorded, but the CoreDB is allowed
+    let dry_run = dry_run_namespaces.contains(&namespace).await;
+    let mut record = AdmissionRecord::new(
+        &namespace,
+        &admission_request.name,
+        resource,
+        &operation_str,
+    );
+    record.dry_run = dry_run;
+
+    let admission_response = if errors.is_empty() {
+        metrics::increment_request_counter(&namespace, &operation_str, resource, "allowed");
+        AdmissionResponse::from(&admission_request)
+    } else {
+        let message = errors.join("; ");
+        record.decision = Decision::Denied;
+        record.reason = Some(message.clone());
+        if dry_run {
+            info!(
+                "Dry run, not denying CoreDB {} in {}: {}",
+                admission_request.name, namespace, message
+            );
+            metrics::increment_request_counter(&namespace, &operation_str, resource, "dry_run");
+            AdmissionResponse::from(&admission_request)
+        } else {
+            info!(
+                "Denying CoreDB {} in {}: {}",
+                admission_request.name, namespace, message
+            );
+            metrics::increment_request_counter(&namespace, &operation_str, resource, "denied");
+            AdmissionResponse::from(&admission_request).deny(message)
+        }
+    };
+    audit.record(record);
+    debug!("AdmissionResponse: {:?}", admission_response);
+
+    let duration = start_time.elapsed().as_secs_f64();
+    metrics::observe_request_duration(&namespace, &operation_str, resource, duration);
+
+    mk_review(admission_response, admission_request)
+}
+
+fn mk_review(
+    response: AdmissionResponse,
+    request: AdmissionRequest<DynamicObject>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(AdmissionReview {
+        response: Some(response),
+        request: Some(request),
+        types: TypeMeta {
+            api_version: "admission.k8s.io/v1".to_string(),
+            kind: "AdmissionReview".to_string(),
+        },
+    })
+}
+
+fn coredb_spec(object: &DynamicObject) -> Result<CoreDBSpec, serde_json::Error> {
+    serde_json::from_value(
+        object
+            .data
+            .get("spec")
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({})),
+    )
+}
+
+// Returns a message for every problem found with the spec, `old` is the
+// current spec on updates. Updates are only denied for problems the current
+// spec doesn't already have, so CoreDBs which predate a rule can still change.
+pub fn validate_coredb(
+    spec: &CoreDBSpec,
+    old: Option<&CoreDBSpec>,
+    config: &Config,
+) -> Vec<String> {
+    let errors = spec_errors(spec, old, config);
+    match old {
+        Some(old) => {
+            let existing: HashSet<String> = spec_errors(old, None, config).into_iter().collect();
+            errors
+                .into_iter()
+                .filter(|error| !existing.contains(error))
+                .collect()
+        }
+        None => errors,
+    }
+}
+
+fn spec_errors(spec: &CoreDBSpec, old: Option<&CoreDBSpec>, config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if spec.replicas < 1 || spec.replicas > config.coredb_max_replicas {
+        errors.push(format!(
+            "replicas must be between 1 and {}, got {}",
+            config.coredb_max_replicas, spec.replicas
+        ));
+    }
+
+    validate_storage(spec, old, &mut errors);
+    validate_resources(spec, &mut errors);
+
+    if let Some(ip_allow_list) = &spec.ip_allow_list {
+        let valid = valid_cidrs(ip_allow_list);
+        for ip in ip_allow_list.iter().filter(|ip| !valid.contains(ip)) {
+            errors.push(format!(
+                "ipAllowList: {} is not a valid IPv4 address or CIDR",
+                ip
+            ));
+        }
+    }
+
+    validate_extensions(spec, &mut errors);
+
+    // Stacks set some parameters users can't, such as wal_level
+    for (field, configs, user_set) in [
+        ("runtime_config", spec.runtime_config.as_ref(), true),
+        ("override_configs", spec.override_configs.as_ref(), true),
+        (
+            "stack.postgres_config",
+            spec.stack
+                .as_ref()
+                .and_then(|stack| stack.postgres_config.as_ref()),
+            false,
+        ),
+    ] {
+        for parameter in configs.into_iter().flatten() {
+            if let Err(e) = validate_pg_config(parameter, user_set) {
+                errors.push(format!("{}: {}", field, e));
+            }
+        }
+    }
+
+    errors
+}
+
+fn validate_storage(spec: &CoreDBSpec, old: Option<&CoreDBSpec>, errors: &mut Vec<String>) {
+    let Some(storage) = parse_quantity(&spec.storage) else {
+        errors.push(format!("storage: invalid quantity {}", spec.storage.0));
+        return;
+    };
+    if storage <= 0.0 {
+        errors.push("storage must be greater than zero".to_string());
+    }
+    // Persistent volumes can only be expanded
+    if let Some(old) = old {
+        if parse_quantity(&old.storage).is_some_and(|old_storage| storage < old_storage) {
+            errors.push(format!(
+                "storage cannot be decreased from {} to {}",
+                old.storage.0, spec.storage.0
+            ));
+        }
+    }
+}
+
+fn validate_resources(spec: &CoreDBSpec, errors: &mut Vec<String>) {
+    let empty = Default::default();
+    let limits = spec.resources.limits.as_ref().unwrap_or(&empty);
+    let requests = spec.resources.requests.as_ref().unwrap_or(&empty);
+
+    for (kind, values) in [("limits", limits), ("requests", requests)] {
+        for (name, quantity) in values {
+            match parse_quantity(quantity) {
+                Some(value) if value <= 0.0 => errors.push(format!(
+                    "resources.{}.{} must be greater than zero",
+                    kind, name
+                )),
+                Some(_) => {}
+                None => errors.push(format!(
+                    "resources.{}.{}: invalid quantity {}",
+                    kind, name, quantity.0
+                )),
+            }
+        }
+    }
+
+    for (name, request) in requests {
+        let Some(limit) = limits.get(name) else {
+            continue;
+        };
+        if let (Some(request_value), Some(limit_value)) =
+            (parse_quantity(request), parse_quantity(limit))
+        {
+            if request_value > limit_value {
+                errors.push(format!(
+                    "resources.requests.{} ({}) exceeds resources.limits.{} ({})",
+                    name, request.0, name, limit.0
+                ));
+            }
+        }
+    }
+}
+
+fn validate_extensions(spec: &CoreDBSpec, errors: &mut Vec<String>) {
+    let mut names = HashSet::new();
+    for extension in &spec.extensions {
+        if !EXTENSION_NAME.is_match(&extension.name) {
+            errors.push(format!(
+                "extensions: invalid extension name {:?}",
+                extension.name
+            ));
+        }
+        if !names.insert(extension.name.as_str()) {
+            errors.push(format!(
+                "extensions: {} is listed more than once",
+                extension.name
+            ));
+        }
+        for location in &extension.locations {
+            if location.database.is_empty() {
+                errors.push(format!(
+                    "extensions: {} has a location without a database",
+                    extension.name
+                ));
+            }
+            if let Some(version) = &location.version {
+                if !EXTENSION_VERSION.is_match(version) {
+                    errors.push(format!(
+                        "extensions: invalid version {:?} for {}",
+                        version, extension.name
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    for install in &spec.trunk_installs {
+        if !EXTENSION_NAME.is_match(&install.name) {
+            errors.push(format!(
+                "trunk_installs: invalid extension name {:?}",
+                install.name
+            ));
+        }
+        if !names.insert(install.name.as_str()) {
+            errors.push(format!(
+                "trunk_installs: {} is listed more than once",
+                install.name
+            ));
+        }
+        if let Some(version) = &install.version {
+            if !EXTENSION_VERSION.is_match(version) {
+                errors.push(format!(
+                    "trunk_installs: invalid version {:?} for {}",
+                    version, install.name
+                ));
+            }
+        }
+    }
+}
+
+// `user_set` is false for the stack's parameters, which may include DISALLOWED_CONFIGS
+fn validate_pg_config(config: &PgConfig, user_set: bool) -> Result<(), String> {
+    let name = config.name.as_str();
+    if !PARAMETER_NAME.is_match(name) {
+        return Err(format!("invalid parameter name {:?}", name));
+    }
+    let lowercase_name = name.to_ascii_lowercase();
+    if DISALLOWED_CONFIGS.contains(&lowercase_name.as_str()) {
+        if user_set {
+            return Err(format!("{} cannot be set", name));
+        }
+        return Ok(());
+    }
+
+    let Some((_, parameter_type)) = PARAMETERS
+        .iter()
+        .find(|(known, _)| *known == lowercase_name)
+    else {
+        // Extension parameters are namespaced and can't be checked
+        if !name.contains('.') {
+            if let Some(suggestion) = suggest_parameter(&lowercase_name) {
+                return Err(format!(
+                    "unknown parameter {}, did you mean {}?",
+                    name, suggestion
+                ));
+            }
+        }
+        return Ok(());
+    };
+
+    let value = match &config.value {
+        ConfigValue::Single(value) => value.trim(),
+        ConfigValue::Multiple(_) if MULTI_VAL_CONFIGS.contains(&lowercase_name.as_str()) => {
+            return Ok(())
+        }
+        ConfigValue::Multiple(_) => {
+            return Err(format!("{} does not accept multiple values", name));
+        }
+    };
+    let valid = match parameter_type {
+        ParameterType::Bool => BOOL_VALUES.contains(&value.to_lowercase().as_str()),
+        ParameterType::Integer => value.parse::<i64>().is_ok(),
+        ParameterType::Real => value.parse::<f64>().is_ok_and(f64::is_finite),
+        ParameterType::Memory => MEMORY_VALUE.is_match(value),
+        ParameterType::Duration => DURATION_VALUE.is_match(value),
+        ParameterType::Enum(values) => values.contains(&value.to_lowercase().as_str()),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid value {:?} for {}, expected {}",
+            value,
+            name,
+            describe(parameter_type)
+        ))
+    }
+}
+
+fn describe(parameter_type: &ParameterType) -> String {
+    match parameter_type {
+        ParameterType::Bool => "on or off".to_string(),
+        ParameterType::Integer => "an integer".to_string(),
+        ParameterType::Real => "a number".to_string(),
+        ParameterType::Memory => "a size such as 128MB".to_string(),
+        ParameterType::Duration => "a duration such as 30s".to_string(),
+        ParameterType::Enum(values) => format!("one of {}", values.join(", ")),
+    }
+}
+
+// The closest known parameter when the name looks like a typo of it
+fn suggest_parameter(name: &str) -> Option<&'static str> {
+    PARAMETERS
+        .iter()
+        .map(|(known, _)| *known)
+        .chain(DISALLOWED_CONFIGS)
+        .map(|known| (known, edit_distance(name, known)))
+        .filter(|(_, distance)| *distance <= 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| known)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Parse a Kubernetes quantity such as 500m, 2Gi or 1.5 into its value
+fn parse_quantity(quantity: &Quantity) -> Option<f64> {
+    const SUFFIXES: &[(&str, f64)] = &[
+        ("Ki", 1024.0),
+        ("Mi", 1024.0 * 1024.0),
+        ("Gi", 1024.0 * 1024.0 * 1024.0),
+        ("Ti", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("Pi", 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("Ei", 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    let value = quantity.0.trim();
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            value
+                .strip_suffix(suffix)
+                .map(|number| (number, *multiplier))
+        })
+        .unwrap_or((value, 1.0));
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .map(|n| n * multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controller::apis::coredb_types::Stack;
+    use controller::extensions::types::{Extension, TrunkInstall};
+    use k8s_openapi::api::core::v1::ResourceRequirements;
+    use std::collections::BTreeMap;
+
+    fn config() -> Config {
+        Config {
+            coredb_max_replicas: 3,
+            ..Config::default()
+        }
+    }
+
+    // A spec with the CRD's defaults, as the API server would store it
+    fn spec() -> CoreDBSpec {
+        serde_json::from_value(serde_json::json!({})).unwrap()
+    }
+
+    fn pg_config(name: &str, value: &str) -> PgConfig {
+        PgConfig {
+            name: name.to_string(),
+            value: value.into(),
+        }
+    }
+
+    fn resources(limits: &[(&str, &str)], requests: &[(&str, &str)]) -> ResourceRequirements {
+        let quantities = |values: &[(&str, &str)]| {
+            Some(
+                values
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), Quantity(v.to_string())))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+        };
+        ResourceRequirements {
+            limits: quantities(limits),
+            requests: quantities(requests),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_spec() {
+        let spec = CoreDBSpec {
+            replicas: 2,
+            storage: Quantity("10Gi".to_string()),
+            resources: resources(&[("cpu", "2"), ("memory", "2Gi")], &[("cpu", "500m")]),
+            ip_allow_list: Some(vec!["10.0.0.0/8".to_string(), "1.2.3.4".to_string()]),
+            trunk_installs: vec![TrunkInstall {
+                name: "pgmq".to_string(),
+                version: Some("1.1.1".to_string()),
+            }],
+            runtime_config: Some(vec![
+                pg_config("shared_buffers", "512MB"),
+                pg_config("random_page_cost", "1.1"),
+                pg_config("statement_timeout", "30s"),
+                pg_config("shared_preload_libraries", "pg_cron,pg_stat_statements"),
+                pg_config("pg_stat_statements.track", "all"),
+                pg_config("TimeZone", "UTC"),
+                pg_config("DateStyle", "ISO, MDY"),
+                pg_config("Work_Mem", "64MB"),
+            ]),
+            stack: Some(Stack {
+                name: "Standard".to_string(),
+                postgres_config: Some(vec![pg_config("track_io_timing", "on")]),
+            }),
+            ..spec()
+        };
+        assert!(validate_coredb(&spec, None, &config()).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        let spec = CoreDBSpec {
+            replicas: 5,
+            resources: resources(&[("cpu", "1")], &[("cpu", "2"), ("memory", "lots")]),
+            ip_allow_list: Some(vec!["10.0.0.0/33".to_string()]),
+            extensions: vec![
+                Extension {
+                    name: "pg_partman".to_string(),
+                    ..Extension::default()
+                },
+                Extension {
+                    name: "pg_partman".to_string(),
+                    ..Extension::default()
+                },
+            ],
+            runtime_config: Some(vec![
+                pg_config("max_conections", "100"),
+                pg_config("max_connections", "lots"),
+                pg_config("wal_level", "logical"),
+            ]),
+            ..spec()
+        };
+        let errors = validate_coredb(&spec, None, &config());
+        assert_eq!(
+            errors,
+            vec![
+                "replicas must be between 1 and 3, got 5",
+                "resources.requests.memory: invalid quantity lots",
+                "resources.requests.cpu (2) exceeds resources.limits.cpu (1)",
+                "ipAllowList: 10.0.0.0/33 is not a valid IPv4 address or CIDR",
+                "extensions: pg_partman is listed more than once",
+                "runtime_config: unknown parameter max_conections, did you mean max_connections?",
+                "runtime_config: invalid value \"lots\" for max_connections, expected an integer",
+                "runtime_config: wal_level cannot be set",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_storage_shrink() {
+        let old = CoreDBSpec {
+            storage: Quantity("20Gi".to_string()),
+            ..spec()
+        };
+        let new = CoreDBSpec {
+            storage: Quantity("10Gi".to_string()),
+            ..spec()
+        };
+        assert_eq!(
+            validate_coredb(&new, Some(&old), &config()),
+            vec!["storage cannot be decreased from 20Gi to 10Gi"]
+        );
+        assert!(validate_coredb(&old, Some(&new), &config()).is_empty());
+    }
+
+    #[test]
+    fn test_update_only_denies_new_errors() {
+        // e.g. a CoreDB created before the replica limit was lowered
+        let old = CoreDBSpec {
+            replicas: 5,
+            runtime_config: Some(vec![pg_config("max_connections", "lots")]),
+            ..spec()
+        };
+        let new = CoreDBSpec {
+            storage: Quantity("20Gi".to_string()),
+            ..old.clone()
+        };
+        assert!(validate_coredb(&new, Some(&old), &config()).is_empty());
+
+        let new = CoreDBSpec {
+            runtime_config: Some(vec![
+                pg_config("max_connections", "lots"),
+                pg_config("work_mem", "lots"),
+            ]),
+            ..old.clone()
+        };
+        assert_eq!(
+            validate_coredb(&new, Some(&old), &config()),
+            vec!["runtime_config: invalid value \"lots\" for work_mem, expected a size such as 128MB"]
+        );
+    }
+
+    #[test]
+    fn test_parameter_names_are_case_insensitive() {
+        assert!(validate_pg_config(&pg_config("TimeZone", "UTC"), true).is_ok());
+        assert_eq!(
+            validate_pg_config(&pg_config("WAL_LEVEL", "logical"), true),
+            Err("WAL_LEVEL cannot be set".to_string())
+        );
+        assert!(validate_pg_config(&pg_config("wal_level", "logical"), false).is_ok());
+        assert_eq!(
+            validate_pg_config(&pg_config("Max_Connections", "lots"), true),
+            Err("invalid value \"lots\" for Max_Connections, expected an integer".to_string())
+        );
+    }
+
+    // e.g. the timeseries stack sets wal_level, which users can't
+    #[test]
+    fn test_stack_specs() {
+        #[derive(serde::Deserialize)]
+        struct StackSpec {
+            name: String,
+            trunk_installs: Option<Vec<TrunkInstall>>,
+            extensions: Option<Vec<Extension>>,
+            postgres_config: Option<Vec<PgConfig>>,
+        }
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../tembo-stacks/src/stacks/specs");
+        let mut stacks = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "yaml") {
+                continue;
+            }
+            let stack: StackSpec =
+                serde_yaml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let spec = CoreDBSpec {
+                trunk_installs: stack.trunk_installs.unwrap_or_default(),
+                extensions: stack.extensions.unwrap_or_default(),
+                stack: Some(Stack {
+                    name: stack.name,
+                    postgres_config: stack.postgres_config,
+                }),
+                ..spec()
+            };
+            assert_eq!(
+                validate_coredb(&spec, None, &config()),
+                Vec::<String>::new(),
+                "{}",
+                path.display()
+            );
+            stacks += 1;
+        }
+        assert!(stacks > 0, "no stacks in {}", dir.display());
+    }
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity(&Quantity("500m".to_string())), Some(0.5));
+        assert_eq!(parse_quantity(&Quantity("2".to_string())), Some(2.0));
+        assert_eq!(
+            parse_quantity(&Quantity("1Gi".to_string())),
+            Some(1024.0 * 1024.0 * 1024.0)
+        );
+        assert_eq!(parse_quantity(&Quantity("1G".to_string())), Some(1e9));
+        assert_eq!(parse_quantity(&Quantity("Gi".to_string())), None);
+    }
+}
@@ -1,6 +1,8 @@
-use futures::{StreamExt, TryStreamExt};
+use controller::cloudnativepg::clusters::Cluster;
+use futures::{future, Future, StreamExt, TryStreamExt};
 use k8s_openapi::api::core::v1::Namespace;
 use kube::api::{Api, ListParams, WatchEvent, WatchParams};
+use kube::runtime::{reflector, reflector::Store, watcher, WatchStreamExt};
 use kube::Client;
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -9,8 +11,19 @@ use tracing::*;
 
 use crate::config::Config;
 
+// The set of watched namespaces which are in dry-run mode
+#[derive(Clone, Debug, Default)]
+pub struct DryRunNamespaces(pub Arc<RwLock<HashSet<String>>>);
+
+impl DryRunNamespaces {
+    pub async fn contains(&self, namespace: &str) -> bool {
+        self.0.read().await.contains(namespace)
+    }
+}
+
 pub struct NamespaceWatcher {
     namespaces: Arc<RwLock<HashSet<String>>>,
+    dry_run_namespaces: DryRunNamespaces,
     client: Arc<Client>,
     config: Config,
 }
@@ -19,11 +32,32 @@ impl NamespaceWatcher {
     pub fn new(client: Arc<Client>, config: Config) -> Self {
         Self {
             namespaces: Arc::new(RwLock::new(HashSet::new())),
+            dry_run_namespaces: DryRunNamespaces::default(),
             client,
             config,
         }
     }
 
+    // Track whether a watched namespace has the dry-run label
+    async fn update_dry_run(&self, ns: &Namespace) {
+        let Some(name) = ns.metadata.name.clone() else {
+            return;
+        };
+        let dry_run = ns
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(&self.config.dry_run_label))
+            .is_some_and(|value| value == "true");
+        let mut dry_run_namespaces = self.dry_run_namespaces.0.write().await;
+        if dry_run {
+            debug!("Namespace {} is in dry-run mode", name);
+            dry_run_namespaces.insert(name);
+        } else {
+            dry_run_namespaces.remove(&name);
+        }
+    }
+
     #[instrument(skip(self))]
     pub async fn watch(&self) -> Result<(), kube::Error> {
         let namespaces = self.namespaces.clone();
@@ -36,6 +70,7 @@ impl NamespaceWatcher {
         // Get all the namespaces and add the ones with the correct label
         let ns_list = api.list(&lp).await?;
         for ns in ns_list {
+            self.update_dry_run(&ns).await;
             if let Some(name) = ns.metadata.name {
                 namespaces.write().await.insert(name.clone());
                 debug!("Added namespaces: {}", name);
@@ -49,6 +84,7 @@ impl NamespaceWatcher {
             debug!("Got event: {:?}", status);
             match status {
                 WatchEvent::Added(ns) | WatchEvent::Modified(ns) => {
+                    self.update_dry_run(&ns).await;
                     let name = ns.metadata.name.clone().unwrap();
                     if ns.metadata.labels.is_some()
                         && ns
@@ -71,6 +107,7 @@ impl NamespaceWatcher {
                 WatchEvent::Deleted(ns) => {
                     let name = ns.metadata.name.clone().unwrap();
                     namespaces.write().await.remove(&name.clone());
+                    self.dry_run_namespaces.0.write().await.remove(&name);
                     debug!("Deleted namespace: {}", name);
                 }
                 _ => {}
@@ -82,4 +119,26 @@ impl NamespaceWatcher {
     pub fn get_namespaces(&self) -> Arc<RwLock<HashSet<String>>> {
         self.namespaces.clone()
     }
+
+    pub fn get_dry_run_namespaces(&self) -> DryRunNamespaces {
+        self.dry_run_namespaces.clone()
+    }
+}
+
+// A cache of every CNPG Cluster, kept up to date by the returned future which
+// has to be spawned
+pub fn cluster_store(client: Client) -> (Store<Cluster>, impl Future<Output = ()>) {
+    let api: Api<Cluster> = Api::all(client);
+    let (reader, writer) = reflector::store();
+    let stream = watcher(api, watcher::Config::default())
+        .default_backoff()
+        .reflect(writer)
+        .applied_objects()
+        .for_each(|event| {
+            if let Err(e) = event {
+                warn!("Cluster watcher error: {}", e);
+            }
+            future::ready(())
+        });
+    (reader, stream)
 }